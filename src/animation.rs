@@ -0,0 +1,615 @@
+// Keyframe animation of the camera, the light, shape transforms and
+// materials, and rendering of numbered frame sequences for videos. Times are
+// in seconds; a property without keys is left as it is in the scene.
+use crate::camera::{view_transform, Camera};
+use crate::color::Color;
+use crate::error::Error;
+use crate::integrators::Integrator;
+use crate::matrix::Mat4x4;
+use crate::progress::CancelToken;
+use crate::quaternion::{self, Quaternion};
+use crate::shape::{Shape, ShapeId};
+use crate::transform::rotate;
+use crate::tuple::{Point3, Vector3};
+use crate::world::World;
+use std::f64::consts::PI;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    // Starts slowly and speeds up
+    EaseIn,
+    // Slows down towards the next key
+    EaseOut,
+    EaseInOut,
+    // Holds the value until the next key
+    Step,
+}
+
+impl Easing {
+    // Maps the fraction of a segment that has elapsed to the fraction of the
+    // change between its keys
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0., 1.);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2. - t),
+            Easing::EaseInOut => t * t * (3. - 2. * t),
+            Easing::Step => 0.,
+        }
+    }
+}
+
+// Values that can be blended between two keys, t in [0, 1]
+pub trait Interpolate: Copy {
+    fn interpolate(&self, other: &Self, t: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &f64, t: f64) -> f64 {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Point3 {
+    fn interpolate(&self, other: &Point3, t: f64) -> Point3 {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Interpolate for Vector3 {
+    fn interpolate(&self, other: &Vector3, t: f64) -> Vector3 {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Interpolate for Color {
+    fn interpolate(&self, other: &Color, t: f64) -> Color {
+        *self + (*other - *self) * t
+    }
+}
+
+// Slerps the rotation so that rotating shapes keep their size
+impl Interpolate for Mat4x4 {
+    fn interpolate(&self, other: &Mat4x4, t: f64) -> Mat4x4 {
+        quaternion::interpolate(self, other, t)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe<T> {
+    pub time: f64,
+    pub value: T,
+    // How the value moves from this key to the next one
+    pub easing: Easing,
+}
+
+// Keyframes of one property, sorted by time. Before the first key and after
+// the last the value is held.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T> Default for Track<T> {
+    fn default() -> Self {
+        Track { keys: Vec::new() }
+    }
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a key that moves linearly to the next one
+    pub fn key(self, time: f64, value: T) -> Self {
+        self.eased_key(time, value, Easing::Linear)
+    }
+
+    // Adds a key, replacing any key at the same time
+    pub fn eased_key(mut self, time: f64, value: T, easing: Easing) -> Self {
+        self.keys.retain(|k| k.time != time);
+        let i = self.keys.partition_point(|k| k.time < time);
+        self.keys.insert(
+            i,
+            Keyframe {
+                time,
+                value,
+                easing,
+            },
+        );
+        self
+    }
+
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    // None when the track has no keys
+    pub fn value_at(&self, time: f64) -> Option<T> {
+        let next = self.keys.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keys.first().map(|k| k.value);
+        }
+        let key = &self.keys[next - 1];
+        match self.keys.get(next) {
+            Some(to) => {
+                let t = (time - key.time) / (to.time - key.time);
+                Some(key.value.interpolate(&to.value, key.easing.apply(t)))
+            }
+            None => Some(key.value),
+        }
+    }
+}
+
+// Where the camera is and what it looks at, see `view_transform`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct View {
+    pub from: Point3,
+    pub to: Point3,
+    pub up: Vector3,
+}
+
+impl View {
+    pub fn new(from: Point3, to: Point3, up: Vector3) -> Self {
+        View { from, to, up }
+    }
+
+    pub fn transform(&self) -> Mat4x4 {
+        view_transform(self.from, self.to, self.up)
+    }
+}
+
+// The target moves in a straight line while the eye swings around it about
+// the up direction, by the smaller angle between the two keys, with its
+// height above the target and its horizontal distance changing linearly.
+// Keys in the same direction dolly straight in; keys a quarter turn apart
+// around the target orbit it on a circle.
+impl Interpolate for View {
+    fn interpolate(&self, other: &View, t: f64) -> View {
+        let to = self.to.interpolate(&other.to, t);
+        let up = self.up.normalize();
+        let split = |offset: Vector3| {
+            let height = offset.dot(up);
+            (height, offset - up * height)
+        };
+        let (ha, a) = split(self.from - self.to);
+        let (hb, b) = split(other.from - other.to);
+        let (ra, rb) = (a.magnitude(), b.magnitude());
+        let axis = a.cross(b);
+        let around = if axis.magnitude() < 1e-9 * ra * rb {
+            a.interpolate(&b, t)
+        } else {
+            let angle = (a.dot(b) / (ra * rb)).clamp(-1., 1.).acos();
+            let turned = Quaternion::from_axis_angle(axis, angle * t).rotate(a);
+            turned.normalize() * ra.interpolate(&rb, t)
+        };
+        View {
+            from: to + around + up * ha.interpolate(&hb, t),
+            to,
+            up: self.up.interpolate(&other.up, t),
+        }
+    }
+}
+
+// Material parameters that can be keyframed besides the color
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaterialParameter {
+    Ambient,
+    Diffuse,
+    Specular,
+    Shininess,
+    Reflective,
+    Transparency,
+    RefractiveIndex,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShapeAnimation {
+    pub transform: Track<Mat4x4>,
+    pub color: Track<Color>,
+    pub material: Vec<(MaterialParameter, Track<f64>)>,
+}
+
+impl ShapeAnimation {
+    fn apply(&self, shape: &mut Shape, time: f64) {
+        if let Some(transform) = self.transform.value_at(time) {
+            shape.transform = transform;
+        }
+        let m = &mut shape.material;
+        if let Some(color) = self.color.value_at(time) {
+            m.color = color;
+        }
+        for (parameter, track) in &self.material {
+            let value = match track.value_at(time) {
+                Some(v) => v,
+                None => continue,
+            };
+            let field = match parameter {
+                MaterialParameter::Ambient => &mut m.ambient,
+                MaterialParameter::Diffuse => &mut m.diffuse,
+                MaterialParameter::Specular => &mut m.specular,
+                MaterialParameter::Shininess => &mut m.shininess,
+                MaterialParameter::Reflective => &mut m.reflective,
+                MaterialParameter::Transparency => &mut m.transparency,
+                MaterialParameter::RefractiveIndex => &mut m.refractive_index,
+            };
+            *field = value;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Animation {
+    pub camera: Track<View>,
+    pub light_position: Track<Point3>,
+    pub light_intensity: Track<Color>,
+    // Keyed by id, so that keys stay with their shape when shapes are added
+    // to or removed from the world
+    pub shapes: Vec<(ShapeId, ShapeAnimation)>,
+}
+
+impl Animation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // One full turn of the camera around the target about the up direction,
+    // the same way as positive rotations about `up`, in `seconds`
+    pub fn turntable(view: View, seconds: f64) -> Self {
+        let mut camera = Track::new();
+        for quarter in 0..=4 {
+            let turn = rotate(view.up, quarter as f64 * PI / 2.);
+            let from = view.to + turn * (view.from - view.to);
+            let key = View::new(from, view.to, view.up);
+            camera = camera.key(seconds * quarter as f64 / 4., key);
+        }
+        Animation {
+            camera,
+            ..Self::default()
+        }
+    }
+
+    // Keyframes for the shape with this id, added on first use
+    pub fn shape(&mut self, id: ShapeId) -> &mut ShapeAnimation {
+        let i = match self.shapes.iter().position(|(s, _)| *s == id) {
+            Some(i) => i,
+            None => {
+                self.shapes.push((id, ShapeAnimation::default()));
+                self.shapes.len() - 1
+            }
+        };
+        &mut self.shapes[i].1
+    }
+
+    // Sets every keyed property to its value at `time`. Keys for shapes that
+    // are not in the world are ignored.
+    pub fn apply(&self, time: f64, camera: &mut Camera, world: &mut World) {
+        if let Some(view) = self.camera.value_at(time) {
+            camera.transform = view.transform();
+        }
+        if let Some(position) = self.light_position.value_at(time) {
+            world.light.position = position;
+        }
+        if let Some(intensity) = self.light_intensity.value_at(time) {
+            world.light.intensity = intensity;
+        }
+        for (id, animation) in &self.shapes {
+            if let Some(shape) = world.shape_mut(*id) {
+                animation.apply(shape, time);
+            }
+        }
+    }
+
+    // Renders every frame of `frames` into `directory` as frame_0001.<format>
+    // and so on, creating the directory if needed. `on_frame` is called with
+    // the 0-based frame number and file after each frame is written. Fails
+    // before writing a frame whose camera or shapes cannot be rendered.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_frames(
+        &self,
+        camera: &Camera,
+        world: &World,
+        integrator: &dyn Integrator,
+        frames: &FrameSequence,
+        directory: &Path,
+        format: &str,
+        threads: usize,
+        on_frame: &mut dyn FnMut(usize, &Path),
+    ) -> Result<Vec<PathBuf>, Error> {
+        std::fs::create_dir_all(directory)?;
+        let mut files = Vec::new();
+        for (i, time) in frames.times().enumerate() {
+            let (mut camera, mut world) = (*camera, world.clone());
+            self.apply(time, &mut camera, &mut world);
+            let outcome = camera.render_observed(
+                &world,
+                integrator,
+                camera.full_region(),
+                threads,
+                &|_| {},
+                &CancelToken::new(),
+            )?;
+            let file = directory.join(FrameSequence::file_name(i, format));
+            outcome.canvas.write(file.to_string_lossy().into_owned())?;
+            on_frame(i, &file);
+            files.push(file);
+        }
+        Ok(files)
+    }
+}
+
+// Frames at `frame_rate` per second from `start` up to but not including
+// `end`, so that consecutive sequences join without a repeated frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameSequence {
+    start: f64,
+    end: f64,
+    frame_rate: f64,
+}
+
+impl FrameSequence {
+    // Fails unless the times are finite with `start <= end` and the frame
+    // rate is finite and positive
+    pub fn new(start: f64, end: f64, frame_rate: f64) -> Result<Self, Error> {
+        let times = start.is_finite() && end.is_finite() && start <= end;
+        if !times || !frame_rate.is_finite() || frame_rate <= 0. {
+            return Err(Error::InvalidFrameSequence {
+                start,
+                end,
+                frame_rate,
+            });
+        }
+        Ok(FrameSequence {
+            start,
+            end,
+            frame_rate,
+        })
+    }
+
+    pub fn start(&self) -> f64 {
+        self.start
+    }
+
+    pub fn end(&self) -> f64 {
+        self.end
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        self.frame_rate
+    }
+
+    pub fn len(&self) -> usize {
+        // A range that holds a whole number of frames up to rounding error,
+        // such as 2.2s at 25fps, must not gain a frame at the end
+        let frames = (self.end - self.start) * self.frame_rate;
+        let nearest = frames.round();
+        let frames = if (frames - nearest).abs() <= 1e-9 * nearest.abs().max(1.) {
+            nearest
+        } else {
+            frames.ceil()
+        };
+        frames as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn times(&self) -> impl Iterator<Item = f64> {
+        let (start, frame_rate) = (self.start, self.frame_rate);
+        (0..self.len()).map(move |i| start + i as f64 / frame_rate)
+    }
+
+    // Frames are numbered from 1, as video tools expect
+    pub fn file_name(frame: usize, format: &str) -> String {
+        format!("frame_{:04}.{}", frame + 1, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::animation::{
+        Animation, Easing, FrameSequence, Interpolate, MaterialParameter, Track, View,
+    };
+    use crate::camera::{view_transform, Camera};
+    use crate::canvas::Canvas;
+    use crate::color::Color;
+    use crate::error::Error;
+    use crate::integrators::WhittedIntegrator;
+    use crate::shape::{Shape, ShapeType};
+    use crate::test_utils::{assert_f64_near, assert_mat4x4_near, assert_tuple_near};
+    use crate::transform::{rotate_y, scale, translate};
+    use crate::tuple::{point, vector};
+    use crate::world::World;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn tracks_interpolate_between_keys_and_hold_outside() {
+        let track = Track::new().key(1., 10.).key(3., 20.).key(2., 30.);
+        assert_eq!(None, Track::<f64>::new().value_at(0.));
+        assert_eq!(Some(10.), track.value_at(0.));
+        assert_eq!(Some(20.), track.value_at(1.5));
+        assert_eq!(Some(30.), track.value_at(2.));
+        assert_eq!(Some(25.), track.value_at(2.5));
+        assert_eq!(Some(20.), track.value_at(5.));
+        assert_eq!(Some(60.), track.key(2., 60.).value_at(2.));
+    }
+
+    #[test]
+    fn easing_shapes_the_segment() {
+        let eased = |easing| Track::new().eased_key(0., 0., easing).key(1., 1.);
+        assert_eq!(Some(0.25), eased(Easing::Linear).value_at(0.25));
+        assert_eq!(Some(0.0625), eased(Easing::EaseIn).value_at(0.25));
+        assert_eq!(Some(0.4375), eased(Easing::EaseOut).value_at(0.25));
+        assert_eq!(Some(0.5), eased(Easing::EaseInOut).value_at(0.5));
+        assert_eq!(Some(0.), eased(Easing::Step).value_at(0.99));
+        assert_eq!(Some(1.), eased(Easing::Step).value_at(1.));
+    }
+
+    #[test]
+    fn views_orbit_their_target() {
+        let up = vector(0., 1., 0.);
+        let a = View::new(point(0., 1., -5.), point(0., 1., 0.), up);
+        let b = View::new(point(5., 1., 0.), point(0., 1., 0.), up);
+        let half = 0.5_f64.sqrt() * 5.;
+        assert_tuple_near(point(half, 1., -half), a.interpolate(&b, 0.5).from, 1e-9);
+
+        let closer = View::new(point(0., 1., -1.), point(0., 1., 0.), up);
+        assert_tuple_near(point(0., 1., -3.), a.interpolate(&closer, 0.5).from, 1e-9);
+
+        let above = View::new(point(0., 5., -1.), point(0., 1., 0.), up);
+        assert_tuple_near(point(0., 3., -3.), a.interpolate(&above, 0.5).from, 1e-9);
+    }
+
+    #[test]
+    fn turntable_keeps_its_distance_to_the_target() {
+        let view = View::new(point(0., 2., -4.), point(0., 1., 0.), vector(0., 1., 0.));
+        let animation = Animation::turntable(view, 8.);
+        for i in 0..16 {
+            let v = animation.camera.value_at(i as f64 / 2.).unwrap();
+            assert_f64_near(2., v.from.y, 1e-9);
+            assert_f64_near(4., (v.from - point(0., 2., 0.)).magnitude(), 1e-9);
+        }
+        let quarter = animation.camera.value_at(2.).unwrap();
+        assert_tuple_near(point(-4., 2., 0.), quarter.from, 1e-9);
+        assert_tuple_near(view.from, animation.camera.value_at(8.).unwrap().from, 1e-9);
+    }
+
+    #[test]
+    fn applying_an_animation_sets_keyed_properties() {
+        let mut w = World::default();
+        let mut c = Camera::new(10, 10, PI / 2.);
+        let mut animation = Animation::new();
+        animation.light_position = Track::new()
+            .key(0., point(0., 0., 0.))
+            .key(2., point(2., 4., 6.));
+        let inner = w.shapes[1].id();
+        let shape = animation.shape(inner);
+        shape.transform = Track::new()
+            .key(0., translate(0., 0., 0.))
+            .key(2., translate(4., 0., 0.));
+        shape.material.push((
+            MaterialParameter::Reflective,
+            Track::new().key(0., 0.).key(2., 1.),
+        ));
+        animation.shape(inner).color = Track::new().key(0., Color::white());
+        let missing = Shape::new(ShapeType::Sphere).id();
+        animation.shape(missing).color = Track::new().key(0., Color::black());
+
+        let original = w.shapes[0];
+        let camera_transform = c.transform;
+        animation.apply(1., &mut c, &mut w);
+        assert_eq!(point(1., 2., 3.), w.light.position);
+        assert_eq!(2, animation.shapes.len());
+        assert_mat4x4_near(translate(2., 0., 0.), w.shapes[1].transform);
+        assert_eq!(0.5, w.shapes[1].material.reflective);
+        assert_eq!(Color::white(), w.shapes[1].material.color);
+        assert_eq!(original, w.shapes[0]);
+        assert_eq!(camera_transform, c.transform);
+        assert_eq!(w.light.intensity, World::default().light.intensity);
+    }
+
+    #[test]
+    fn shape_keys_follow_the_shape_when_others_are_removed() {
+        let mut w = World::default();
+        let outer = w.shapes[0].id();
+        let inner = w.shapes[1].id();
+        let mut animation = Animation::new();
+        animation.shape(inner).color = Track::new().key(0., Color::black());
+
+        w.remove(outer);
+        animation.apply(0., &mut Camera::new(10, 10, PI / 2.), &mut w);
+        assert_eq!(Color::black(), w.shape(inner).unwrap().material.color);
+    }
+
+    #[test]
+    fn rotating_shape_keys_keep_the_shape_size() {
+        let track = Track::new()
+            .key(0., scale(2., 2., 2.))
+            .key(1., rotate_y(PI / 2.) * scale(2., 2., 2.));
+        assert_mat4x4_near(
+            rotate_y(PI / 4.) * scale(2., 2., 2.),
+            track.value_at(0.5).unwrap(),
+        );
+    }
+
+    #[test]
+    fn frame_sequences_cover_the_range_without_the_end() {
+        let frames = FrameSequence::new(1., 2., 4.).unwrap();
+        assert_eq!(4, frames.len());
+        assert_eq!(
+            vec![1., 1.25, 1.5, 1.75],
+            frames.times().collect::<Vec<_>>()
+        );
+        assert!(FrameSequence::new(1., 1., 24.).unwrap().is_empty());
+        assert_eq!("frame_0001.ppm", FrameSequence::file_name(0, "ppm"));
+        assert_eq!("frame_0123.png", FrameSequence::file_name(122, "png"));
+    }
+
+    #[test]
+    fn frame_sequences_ignore_rounding_error_in_the_length() {
+        let frames = FrameSequence::new(0., 2.2, 25.).unwrap();
+        assert_eq!(55, frames.len());
+        assert!(frames.times().last().unwrap() < 2.2);
+        assert_eq!(3, FrameSequence::new(0., 0.1001, 25.).unwrap().len());
+    }
+
+    #[test]
+    fn frame_sequences_must_be_finite_and_forwards() {
+        for (start, end, frame_rate) in [
+            (0., 1., f64::INFINITY),
+            (0., 1., f64::NAN),
+            (0., 1., 0.),
+            (0., 1., -24.),
+            (2., 1., 24.),
+            (0., f64::INFINITY, 24.),
+            (f64::NAN, 1., 24.),
+        ] {
+            assert!(matches!(
+                FrameSequence::new(start, end, frame_rate),
+                Err(Error::InvalidFrameSequence { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn rendering_a_frame_sequence() {
+        let directory = std::env::temp_dir().join("rust_raytracer_animation_frames");
+        let _ = std::fs::remove_dir_all(&directory);
+        let w = World::default();
+        let mut c = Camera::new(5, 5, PI / 2.);
+        let from = point(0., 0., -5.);
+        c.transform = view_transform(from, point(0., 0., 0.), vector(0., 1., 0.));
+        let mut animation = Animation::new();
+        animation.light_intensity = Track::new().key(0., Color::white()).key(1., Color::black());
+
+        let mut written = Vec::new();
+        let files = animation
+            .render_frames(
+                &c,
+                &w,
+                &WhittedIntegrator::default(),
+                &FrameSequence::new(0., 1., 2.).unwrap(),
+                &directory,
+                "ppm",
+                2,
+                &mut |i, _| written.push(i),
+            )
+            .unwrap();
+        assert_eq!(vec![0, 1], written);
+        assert_eq!(directory.join("frame_0002.ppm"), files[1]);
+
+        let first = Canvas::read(files[0].to_string_lossy().into_owned()).unwrap();
+        let second = Canvas::read(files[1].to_string_lossy().into_owned()).unwrap();
+        assert!(first.get_pixel(2, 2).r > second.get_pixel(2, 2).r);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
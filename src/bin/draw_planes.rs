@@ -32,10 +32,9 @@ fn main() {
     let light_color = color::Color::new(1., 1., 1.);
     let light = lights::PointLight::new(light_color, light_position);
 
-    let world = world::World {
-        light,
-        shapes: vec![floor, middle, right, left],
-    };
+    let mut world = world::World::new();
+    world.light = light;
+    world.shapes = vec![floor, middle, right, left];
 
     let mut camera = camera::Camera::new(2000, 1000, std::f64::consts::PI / 3.);
     camera.transform = camera::view_transform(
@@ -45,5 +44,8 @@ fn main() {
     );
 
     let canvas = camera.render(world);
-    canvas.write_ppm("draw_planes.ppm".to_string());
+    if let Err(e) = canvas.write_ppm("draw_planes.ppm".to_string()) {
+        eprintln!("Unable to write draw_planes.ppm: {}", e);
+        std::process::exit(1);
+    }
 }
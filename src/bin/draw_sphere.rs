@@ -43,5 +43,8 @@ fn main() {
         }
     }
 
-    canvas.write_ppm("draw_sphere.ppm".to_string());
+    if let Err(e) = canvas.write_ppm("draw_sphere.ppm".to_string()) {
+        eprintln!("Unable to write draw_sphere.ppm: {}", e);
+        std::process::exit(1);
+    }
 }
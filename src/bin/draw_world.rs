@@ -62,10 +62,9 @@ fn main() {
     let light_color = color::Color::new(1., 1., 1.);
     let light = lights::PointLight::new(light_color, light_position);
 
-    let world = world::World {
-        light,
-        shapes: vec![floor, left_wall, right_wall, middle, right, left],
-    };
+    let mut world = world::World::new();
+    world.light = light;
+    world.shapes = vec![floor, left_wall, right_wall, middle, right, left];
 
     let mut camera = camera::Camera::new(1000, 500, std::f64::consts::PI / 3.);
     camera.transform = camera::view_transform(
@@ -75,5 +74,8 @@ fn main() {
     );
 
     let canvas = camera.render(world);
-    canvas.write_ppm("draw_world.ppm".to_string());
+    if let Err(e) = canvas.write_ppm("draw_world.ppm".to_string()) {
+        eprintln!("Unable to write draw_world.ppm: {}", e);
+        std::process::exit(1);
+    }
 }
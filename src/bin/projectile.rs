@@ -2,13 +2,13 @@ extern crate rust_raytracer;
 use rust_raytracer::*;
 
 struct Projectile {
-    pub pos: tuple::Tuple,
-    pub vel: tuple::Tuple,
+    pub pos: tuple::Point3,
+    pub vel: tuple::Vector3,
 }
 
 struct Environment {
-    pub gravity: tuple::Tuple,
-    pub wind: tuple::Tuple,
+    pub gravity: tuple::Vector3,
+    pub wind: tuple::Vector3,
 }
 
 fn tick(env: &Environment, proj: Projectile) -> Projectile {
@@ -43,5 +43,8 @@ fn main() {
         ticks += 1;
     }
     println!("Ticks: {}", ticks);
-    canvas.write_ppm("projectile.ppm".to_string());
+    if let Err(e) = canvas.write_ppm("projectile.ppm".to_string()) {
+        eprintln!("Unable to write projectile.ppm: {}", e);
+        std::process::exit(1);
+    }
 }
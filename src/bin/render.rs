@@ -0,0 +1,415 @@
+extern crate rust_raytracer;
+use rust_raytracer::animation::{Animation, FrameSequence, View};
+use rust_raytracer::camera::{Camera, Region};
+use rust_raytracer::integrators::{Integrator, WhittedIntegrator};
+use rust_raytracer::pathtracer::PathTracer;
+use rust_raytracer::progress::{CancelToken, Progress};
+use rust_raytracer::scene::Scene;
+use rust_raytracer::tuple::{point, vector};
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "Usage: render <scene.yml|json> [output] [options]
+
+The output defaults to the scene file name with the format's extension. With
+--turntable it is a directory of frames, by default the scene file name
+followed by _frames.
+
+Options:
+  --preset <name>      draft, preview, medium or high (default medium)
+  --width <pixels>     Image width; with only one of width and height the
+  --height <pixels>    scene's aspect ratio is kept
+  --fov <degrees>      Horizontal or vertical field of view, whichever is longer
+  --depth <n>          Reflection and refraction depth, or the maximum path
+                       length with --path-tracer
+  --samples <n>        Samples per pixel
+  --threads <n>        Render threads (default: one per core)
+  --format <format>    png, ppm, hdr or pfm (default: from the output name, or png)
+  --region <x,y,w,h>   Render only this rectangle of the image
+  --path-tracer        Use the path tracer instead of the Whitted integrator
+  --time-limit <s>     Stop after this many seconds and write the finished rows
+  --turntable <s>      Render frame_0001 and on of the camera circling the
+                       scene once about the vertical axis in this many seconds
+  --fps <n>            Frames per second for --turntable (default 24)
+  --stats              Count rays, intersection tests and recursion depth,
+                       and report them with the time taken per row
+  --quiet              Do not report progress";
+
+const FORMATS: [&str; 4] = ["png", "ppm", "hdr", "pfm"];
+
+// Resolution scale, depth and samples per pixel
+struct Preset {
+    scale: f64,
+    depth: u32,
+    samples: u32,
+}
+
+fn preset(name: &str) -> Option<Preset> {
+    let (scale, depth, samples) = match name {
+        "draft" => (0.25, 1, 1),
+        "preview" => (0.5, 3, 1),
+        "medium" => (1., 5, 1),
+        "high" => (1., 8, 16),
+        _ => return None,
+    };
+    Some(Preset {
+        scale,
+        depth,
+        samples,
+    })
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn value<'a>(args: &mut impl Iterator<Item = &'a String>, option: &str) -> &'a str {
+    args.next()
+        .unwrap_or_else(|| fail(&format!("{} expects a value\n\n{}", option, USAGE)))
+}
+
+fn positive<T: std::str::FromStr + PartialOrd + Default>(text: &str, option: &str) -> T {
+    match text.parse::<T>() {
+        Ok(n) if n > T::default() => n,
+        _ => fail(&format!(
+            "{} expects a positive number, found '{}'",
+            option, text
+        )),
+    }
+}
+
+// Like `positive`, also rejecting infinity, which parses as a valid f64
+fn positive_finite(text: &str, option: &str) -> f64 {
+    let n = positive::<f64>(text, option);
+    if !n.is_finite() {
+        fail(&format!(
+            "{} expects a finite number, found '{}'",
+            option, text
+        ));
+    }
+    n
+}
+
+fn parse_region(text: &str) -> Region {
+    let values: Vec<u32> = text
+        .split(',')
+        .filter_map(|v| v.trim().parse().ok())
+        .collect();
+    match values.as_slice() {
+        [x, y, width, height] if *width > 0 && *height > 0 => Region {
+            x: *x,
+            y: *y,
+            width: *width,
+            height: *height,
+        },
+        _ => fail(&format!(
+            "--region expects x,y,width,height, found '{}'",
+            text
+        )),
+    }
+}
+
+// The camera's view with the target on the vertical axis through the origin,
+// or in front of the eye when it looks straight up or down
+fn turntable_view(camera: &Camera) -> View {
+    let inv = camera
+        .transform
+        .inverse()
+        .unwrap_or_else(|e| fail(&format!("Camera transform: {}", e)));
+    let eye = inv * point(0., 0., 0.);
+    let forward = (inv * vector(0., 0., -1.)).normalize();
+    let horizontal = forward.x * forward.x + forward.z * forward.z;
+    let s = -(eye.x * forward.x + eye.z * forward.z) / horizontal;
+    let distance = if horizontal > 1e-9 && s > 0. {
+        s
+    } else {
+        (eye - point(0., 0., 0.)).magnitude().max(1.)
+    };
+    View::new(eye, eye + forward * distance, vector(0., 1., 0.))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|a| a == "-h" || a == "--help") {
+        fail(USAGE);
+    }
+
+    let scene_file = &args[0];
+    let mut output = None;
+    let mut settings = preset("medium").unwrap();
+    let (mut width, mut height, mut fov, mut depth, mut samples) = (None, None, None, None, None);
+    let mut threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut format = None;
+    let mut region = None;
+    let mut path_tracer = false;
+    let mut time_limit = None;
+    let mut turntable = None;
+    let mut fps = 24.;
+    let mut collect_stats = false;
+    let mut quiet = false;
+
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--preset" => {
+                let name = value(&mut options, option);
+                settings = preset(name)
+                    .unwrap_or_else(|| fail(&format!("Unknown preset '{}'\n\n{}", name, USAGE)))
+            }
+            "--width" => width = Some(positive::<u32>(value(&mut options, option), option)),
+            "--height" => height = Some(positive::<u32>(value(&mut options, option), option)),
+            "--fov" => fov = Some(positive_finite(value(&mut options, option), option)),
+            "--depth" => depth = Some(positive::<u32>(value(&mut options, option), option)),
+            "--samples" => samples = Some(positive::<u32>(value(&mut options, option), option)),
+            "--threads" => threads = positive::<usize>(value(&mut options, option), option),
+            "--format" => {
+                let name = value(&mut options, option).to_ascii_lowercase();
+                if !FORMATS.contains(&name.as_str()) {
+                    fail(&format!("Unsupported format '{}'\n\n{}", name, USAGE));
+                }
+                format = Some(name)
+            }
+            "--region" => region = Some(parse_region(value(&mut options, option))),
+            "--path-tracer" => path_tracer = true,
+            "--time-limit" => {
+                time_limit = Some(positive_finite(value(&mut options, option), option))
+            }
+            "--turntable" => turntable = Some(positive_finite(value(&mut options, option), option)),
+            "--fps" => fps = positive_finite(value(&mut options, option), option),
+            "--stats" => collect_stats = true,
+            "--quiet" => quiet = true,
+            _ if option.starts_with("--") || output.is_some() => {
+                fail(&format!("Unknown option '{}'\n\n{}", option, USAGE))
+            }
+            _ => output = Some(option.clone()),
+        }
+    }
+
+    if turntable.is_some() && (region.is_some() || time_limit.is_some()) {
+        fail("--turntable renders whole frames, without --region or --time-limit");
+    }
+
+    // The format comes from --format, else the output name, else png
+    let output_format = format
+        .clone()
+        .or_else(|| {
+            output.as_ref().and_then(|o| {
+                Path::new(o)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .map(|e| e.to_ascii_lowercase())
+            })
+        })
+        .unwrap_or_else(|| "png".to_string());
+    if !FORMATS.contains(&output_format.as_str()) {
+        fail(&format!(
+            "Unsupported format '{}', use --format\n\n{}",
+            output_format, USAGE
+        ));
+    }
+    let output = match (output, &format) {
+        (Some(o), _) if turntable.is_some() => Path::new(&o).to_path_buf(),
+        (None, _) if turntable.is_some() => {
+            let stem = Path::new(scene_file)
+                .file_stem()
+                .unwrap_or_else(|| fail(&format!("{} is not a scene file name", scene_file)))
+                .to_string_lossy();
+            Path::new(&format!("{}_frames", stem)).to_path_buf()
+        }
+        (Some(o), Some(f)) => Path::new(&o).with_extension(f),
+        (Some(o), None) => Path::new(&o).to_path_buf(),
+        (None, _) => Path::new(scene_file)
+            .with_extension(&output_format)
+            .file_name()
+            .map(Path::new)
+            .unwrap_or_else(|| fail(&format!("{} is not a scene file name", scene_file)))
+            .to_path_buf(),
+    };
+
+    let start = Instant::now();
+    let mut scene =
+        Scene::load(scene_file).unwrap_or_else(|e| fail(&format!("{}: {}", scene_file, e)));
+    let load_time = start.elapsed();
+    let diagnostics = scene.diagnostics();
+    for diagnostic in &diagnostics {
+        eprintln!("{}: {}", scene_file, diagnostic);
+    }
+    if diagnostics.iter().any(|d| d.is_error()) {
+        fail(&format!(
+            "{}: the scene has errors, not rendering",
+            scene_file
+        ));
+    }
+
+    let camera = &mut scene.camera;
+    let aspect = camera.hsize as f64 / camera.vsize as f64;
+    let (w, h) = match (width, height) {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, ((w as f64 / aspect).round() as u32).max(1)),
+        (None, Some(h)) => (((h as f64 * aspect).round() as u32).max(1), h),
+        (None, None) => (
+            ((camera.hsize as f64 * settings.scale).round() as u32).max(1),
+            ((camera.vsize as f64 * settings.scale).round() as u32).max(1),
+        ),
+    };
+    camera.set_size(w, h);
+    if let Some(degrees) = fov {
+        camera.set_field_of_view(degrees.to_radians());
+    }
+    let depth = depth.unwrap_or(settings.depth);
+    let samples = samples.unwrap_or(settings.samples);
+    camera.time_samples = samples;
+
+    let region = region.unwrap_or_else(|| camera.full_region());
+    if !region.fits_in(w, h) {
+        fail(&format!(
+            "Region {},{},{},{} is outside the {}x{} image",
+            region.x, region.y, region.width, region.height, w, h
+        ));
+    }
+
+    let integrator: Box<dyn Integrator> = if path_tracer {
+        let mut tracer = PathTracer::new(samples);
+        tracer.max_depth = depth;
+        Box::new(tracer)
+    } else {
+        Box::new(WhittedIntegrator::new(depth.min(i8::MAX as u32) as i8))
+    };
+
+    let render_start = Instant::now();
+    if let Some(seconds) = turntable {
+        let frames = FrameSequence::new(0., seconds, fps).unwrap_or_else(|e| fail(&e.to_string()));
+        let animation = Animation::turntable(turntable_view(camera), seconds);
+        let files = animation
+            .render_frames(
+                camera,
+                &scene.world,
+                integrator.as_ref(),
+                &frames,
+                &output,
+                &output_format,
+                threads,
+                &mut |i, _| {
+                    if !quiet {
+                        eprint!("\rRendering frame {}/{}    ", i + 1, frames.len());
+                        let _ = std::io::stderr().flush();
+                    }
+                },
+            )
+            .unwrap_or_else(|e| fail(&format!("{}: {}", scene_file, e)));
+        if !quiet {
+            eprintln!();
+        }
+        println!("Wrote {} frames to {}", files.len(), output.display());
+        println!(
+            "Time: {:.3}s loading, {:.3}s rendering, {:.3}s per frame",
+            load_time.as_secs_f64(),
+            render_start.elapsed().as_secs_f64(),
+            render_start.elapsed().as_secs_f64() / files.len().max(1) as f64
+        );
+        return;
+    }
+    let cancel = CancelToken::new();
+    if let Some(seconds) = time_limit {
+        let cancel = cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_secs_f64(seconds));
+            cancel.cancel();
+        });
+    }
+    let rows_done = AtomicU32::new(0);
+    let last_report = Mutex::new(Instant::now());
+    let on_progress = |p: &Progress| {
+        rows_done.fetch_max(p.rows_done, Ordering::Relaxed);
+        let mut last = last_report.lock().unwrap();
+        // Reports at most ten times a second, and always for the last row
+        if quiet || (last.elapsed().as_millis() < 100 && p.rows_done < p.total_rows) {
+            return;
+        }
+        *last = Instant::now();
+        let eta = p
+            .eta()
+            .map_or("-".to_string(), |d| format!("{:.1}s", d.as_secs_f64()));
+        eprint!(
+            "\rRendering {:5.1}%  row {}/{}  elapsed {:.1}s  remaining {}    ",
+            p.fraction() * 100.,
+            p.rows_done,
+            p.total_rows,
+            p.elapsed.as_secs_f64(),
+            eta
+        );
+        let _ = std::io::stderr().flush();
+    };
+    let render = if collect_stats {
+        Camera::render_instrumented
+    } else {
+        Camera::render_observed
+    };
+    let outcome = render(
+        camera,
+        &scene.world,
+        integrator.as_ref(),
+        region,
+        threads,
+        &on_progress,
+        &cancel,
+    )
+    .unwrap_or_else(|e| fail(&format!("{}: {}", scene_file, e)));
+    if !quiet {
+        eprintln!();
+    }
+    let canvas = &outcome.canvas;
+    let render_time = render_start.elapsed();
+    let output_name = output
+        .to_str()
+        .unwrap_or_else(|| fail("The output path is not valid UTF-8"));
+    if let Err(e) = canvas.write(output_name.to_string()) {
+        fail(&format!("Unable to write {}: {}", output_name, e));
+    }
+
+    let rows = rows_done.load(Ordering::Relaxed);
+    let pixels = region.width as u64 * rows as u64;
+    let camera_rays = pixels * samples.max(integrator.samples_per_pixel()) as u64;
+    if outcome.cancelled {
+        println!(
+            "Stopped after {} of {} rows, wrote the partial image to {}",
+            rows,
+            region.height,
+            output.display()
+        );
+    } else {
+        println!("Wrote {}", output.display());
+    }
+    println!(
+        "Image: {}x{} pixels of {}x{}, {} thread{}",
+        region.width,
+        region.height,
+        w,
+        h,
+        threads,
+        if threads == 1 { "" } else { "s" }
+    );
+    println!(
+        "Time: {:.3}s loading, {:.3}s rendering, {:.3}s total",
+        load_time.as_secs_f64(),
+        render_time.as_secs_f64(),
+        start.elapsed().as_secs_f64()
+    );
+    match &outcome.stats {
+        Some(stats) => println!(
+            "{}\nRays per second: {:.0}",
+            stats,
+            stats.rays() as f64 / render_time.as_secs_f64().max(1e-9)
+        ),
+        None => println!(
+            "Camera rays: {} ({:.0} per second)",
+            camera_rays,
+            camera_rays as f64 / render_time.as_secs_f64().max(1e-9)
+        ),
+    }
+}
@@ -1,9 +1,19 @@
 use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::error::Error;
+use crate::integrators::{Integrator, WhittedIntegrator};
 use crate::matrix::Mat4x4;
+use crate::passes::RenderPasses;
+use crate::progress::{CancelToken, Progress, RenderOutcome};
 use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::stats::{self, RayKind, RenderStats, TileStats};
 use crate::transform::translate;
-use crate::tuple::{point, Tuple};
-use crate::world::World;
+use crate::tuple::{point, Point3, Vector3};
+use crate::validation::{self, Diagnostic};
+use crate::world::{Pick, World};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Camera {
@@ -14,63 +24,444 @@ pub struct Camera {
     pub pixel_size: f64,
     pub half_width: f64,
     pub half_height: f64,
+    // Shutter interval in shape motion time, 0 is the start transform and 1 the end transform
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    // Number of ray times sampled across the shutter interval per pixel
+    pub time_samples: u32,
+    // Stores the fraction of samples that hit a shape as the canvas alpha,
+    // leaving the background transparent
+    pub transparent_background: bool,
+}
+
+// Rectangle of pixels, x: column, y: row of the top left corner
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    // Whether the region lies inside an image of the given size
+    pub fn fits_in(&self, width: u32, height: u32) -> bool {
+        self.x <= width
+            && self.width <= width - self.x
+            && self.y <= height
+            && self.height <= height - self.y
+    }
 }
 
 impl Camera {
     pub fn new(hsize: u32, vsize: u32, field_of_view: f64) -> Self {
-        let half_view = (field_of_view / 2.).tan();
-        let aspect = (hsize as f64) / (vsize as f64);
+        let mut camera = Camera {
+            hsize,
+            vsize,
+            field_of_view,
+            transform: Mat4x4::identity(),
+            pixel_size: 0.,
+            half_width: 0.,
+            half_height: 0.,
+            shutter_open: 0.,
+            shutter_close: 0.,
+            time_samples: 1,
+            transparent_background: false,
+        };
+        camera.update_view();
+        camera
+    }
+
+    fn update_view(&mut self) {
+        let half_view = (self.field_of_view / 2.).tan();
+        let aspect = (self.hsize as f64) / (self.vsize as f64);
         let (half_width, half_height) = if aspect >= 1. {
             (half_view, half_view / aspect)
         } else {
             (half_view * aspect, half_view)
         };
+        self.half_width = half_width;
+        self.half_height = half_height;
+        self.pixel_size = (half_width * 2.) / (self.hsize as f64);
+    }
 
-        let pixel_size = (half_width * 2.) / (hsize as f64);
+    // Changes the image size, keeping the field of view across the longer side
+    pub fn set_size(&mut self, hsize: u32, vsize: u32) {
+        self.hsize = hsize;
+        self.vsize = vsize;
+        self.update_view();
+    }
 
-        Camera {
-            hsize,
-            vsize,
-            field_of_view,
-            transform: Mat4x4::identity(),
-            pixel_size,
-            half_width,
-            half_height,
+    pub fn set_field_of_view(&mut self, field_of_view: f64) {
+        self.field_of_view = field_of_view;
+        self.update_view();
+    }
+
+    pub fn full_region(&self) -> Region {
+        Region {
+            x: 0,
+            y: 0,
+            width: self.hsize,
+            height: self.vsize,
+        }
+    }
+
+    // Checks that the view transform can be inverted
+    pub fn validate(&self) -> Result<(), Error> {
+        self.transform.inverse().map(|_| ())
+    }
+
+    fn check_region(&self, region: Region) -> Result<(), Error> {
+        if region.fits_in(self.hsize, self.vsize) {
+            Ok(())
+        } else {
+            Err(Error::RegionOutsideImage {
+                region,
+                width: self.hsize,
+                height: self.vsize,
+            })
         }
     }
 
+    // Problems with the image size, field of view, transform and shutter
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        validation::check_camera(self)
+    }
+
+    // The ray functions panic if the camera transform cannot be inverted,
+    // see `validate`
     pub fn ray_for_pixel(&self, px: u32, py: u32) -> Ray {
-        let xoffset = (px as f64 + 0.5) * self.pixel_size;
-        let yoffset = (py as f64 + 0.5) * self.pixel_size;
+        self.ray_for_pixel_at_time(px, py, self.shutter_open)
+    }
 
-        let world_x = self.half_width - xoffset;
-        let world_y = self.half_height - yoffset;
+    pub fn ray_for_pixel_at_time(&self, px: u32, py: u32, time: f64) -> Ray {
+        self.ray_for_subpixel(px, py, 0.5, 0.5, time)
+    }
 
-        let inv = self
+    // dx, dy: position inside the pixel, in [0, 1)
+    pub fn ray_for_subpixel(&self, px: u32, py: u32, dx: f64, dy: f64, time: f64) -> Ray {
+        let inverse = self
             .transform
             .inverse()
-            .expect("Could not get the inverse camera transform");
-        let pixel = inv * point(world_x, world_y, -1.);
-        let origin = inv * point(0., 0., 0.);
+            .expect("Camera transform is not invertible, see Camera::validate");
+        self.ray_through(&inverse, px, py, dx, dy, time)
+    }
+
+    // `inverse` is the inverse of the camera transform, computed once per render
+    fn ray_through(&self, inverse: &Mat4x4, px: u32, py: u32, dx: f64, dy: f64, time: f64) -> Ray {
+        let xoffset = (px as f64 + dx) * self.pixel_size;
+        let yoffset = (py as f64 + dy) * self.pixel_size;
+
+        let world_x = self.half_width - xoffset;
+        let world_y = self.half_height - yoffset;
+
+        let pixel = *inverse * point(world_x, world_y, -1.);
+        let origin = *inverse * point(0., 0., 0.);
         let direction = (pixel - origin).normalize();
 
-        Ray::new(origin, direction)
+        Ray::with_time(origin, direction, time)
     }
 
+    // Averages stratified ray times across the shutter interval. With more
+    // than one sample the rays are also jittered inside the pixel. Returns the
+    // color and the coverage, which is only computed for transparent
+    // backgrounds and is 1 otherwise.
+    fn color_for_pixel(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        inverse: &Mat4x4,
+        px: u32,
+        py: u32,
+    ) -> (Color, f64) {
+        let samples = self.time_samples.max(integrator.samples_per_pixel()).max(1);
+        let shutter = self.shutter_close - self.shutter_open;
+        let seed = integrator.seed().wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let mut rng = Rng::new(seed ^ ((py as u64) << 32) ^ px as u64);
+        let mut color = Color::black();
+        let mut hits = 0;
+        for i in 0..samples {
+            let ray = if samples > 1 {
+                let time =
+                    self.shutter_open + shutter * (i as f64 + rng.next_f64()) / samples as f64;
+                self.ray_through(inverse, px, py, rng.next_f64(), rng.next_f64(), time)
+            } else {
+                self.ray_through(inverse, px, py, 0.5, 0.5, self.shutter_open)
+            };
+            stats::count_ray(RayKind::Primary, 0);
+            let (sample, hit) = integrator.sample(world, ray, &mut rng);
+            if hit {
+                hits += 1;
+            }
+            color = color + sample;
+        }
+        let coverage = if self.transparent_background {
+            hits as f64 / samples as f64
+        } else {
+            1.
+        };
+        (color * (1. / samples as f64), coverage)
+    }
+
+    // Returns only the image. Statistics come from `render_with_stats` or
+    // `render_instrumented` instead, so that renders that do not ask for
+    // them are not slowed down by counting. Panics like `render_region`.
     pub fn render(&self, world: World) -> Canvas {
-        let mut image = Canvas::new(self.hsize as usize, self.vsize as usize);
-        for y in 0..image.height() - 1 {
-            for x in 0..image.width() - 1 {
-                let ray = self.ray_for_pixel(x as u32, y as u32);
-                let color = world.color_at(ray, 5);
-                image.set_pixel(x as usize, y as usize, color);
+        self.render_with(&world, &WhittedIntegrator::default())
+    }
+
+    pub fn render_with(&self, world: &World, integrator: &dyn Integrator) -> Canvas {
+        self.render_region(world, integrator, self.full_region(), 1)
+    }
+
+    // Like `render_with`, also returning how many rays and intersection tests
+    // the image took. Panics like `render_region`.
+    pub fn render_with_stats(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+    ) -> (Canvas, RenderStats) {
+        let outcome = self
+            .render_rows(
+                world,
+                integrator,
+                self.full_region(),
+                1,
+                &|_| {},
+                &CancelToken::new(),
+                true,
+            )
+            .unwrap_or_else(|e| panic!("{}", e));
+        (outcome.canvas, outcome.stats.unwrap_or_default())
+    }
+
+    // Renders the pixels of `region` into a canvas of the region's size,
+    // spreading rows over `threads` threads. Pixels are seeded by their
+    // position and the integrator's seed, so the result does not depend on
+    // the thread count. Panics if the region is not inside the image or a
+    // transform cannot be inverted; `render_observed` returns those as errors.
+    pub fn render_region(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        region: Region,
+        threads: usize,
+    ) -> Canvas {
+        self.render_rows(
+            world,
+            integrator,
+            region,
+            threads,
+            &|_| {},
+            &CancelToken::new(),
+            false,
+        )
+        .unwrap_or_else(|e| panic!("{}", e))
+        .canvas
+    }
+
+    // Like `render_region`, calling `on_progress` after every completed row
+    // and checking `cancel` before starting each row. The callback is called
+    // from the render threads, possibly concurrently. Fails before rendering
+    // if the region is not inside the image, or if the camera or any shape
+    // has a transform that cannot be inverted.
+    pub fn render_observed(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        region: Region,
+        threads: usize,
+        on_progress: &(dyn Fn(&Progress) + Sync),
+        cancel: &CancelToken,
+    ) -> Result<RenderOutcome, Error> {
+        self.render_rows(
+            world,
+            integrator,
+            region,
+            threads,
+            on_progress,
+            cancel,
+            false,
+        )
+    }
+
+    // Like `render_observed`, also counting rays, intersection tests,
+    // recursion depth and the time taken by every row into
+    // `RenderOutcome::stats`. Counting makes the render a little slower.
+    pub fn render_instrumented(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        region: Region,
+        threads: usize,
+        on_progress: &(dyn Fn(&Progress) + Sync),
+        cancel: &CancelToken,
+    ) -> Result<RenderOutcome, Error> {
+        self.render_rows(
+            world,
+            integrator,
+            region,
+            threads,
+            on_progress,
+            cancel,
+            true,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_rows(
+        &self,
+        world: &World,
+        integrator: &dyn Integrator,
+        region: Region,
+        threads: usize,
+        on_progress: &(dyn Fn(&Progress) + Sync),
+        cancel: &CancelToken,
+        collect_stats: bool,
+    ) -> Result<RenderOutcome, Error> {
+        self.check_region(region)?;
+        let inverse = self.prepare(world)?;
+        let mut image = Canvas::new(region.width as usize, region.height as usize);
+        let threads = threads.clamp(1, region.height.max(1) as usize);
+        let start = Instant::now();
+        let rows_done = AtomicU32::new(0);
+        type Rows = Vec<(u32, Vec<(Color, f64)>)>;
+        let render_rows = |first: u32| -> (Rows, Option<RenderStats>) {
+            if collect_stats {
+                stats::start();
+            }
+            let mut rows = Vec::new();
+            // Rows are interleaved between threads to balance the load
+            for y in (first..region.height).step_by(threads) {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                let row_start = Instant::now();
+                let row = (0..region.width)
+                    .map(|x| {
+                        self.color_for_pixel(
+                            world,
+                            integrator,
+                            &inverse,
+                            region.x + x,
+                            region.y + y,
+                        )
+                    })
+                    .collect();
+                stats::add_tile(TileStats {
+                    region: Region {
+                        x: region.x,
+                        y: region.y + y,
+                        width: region.width,
+                        height: 1,
+                    },
+                    time: row_start.elapsed(),
+                });
+                rows.push((y, row));
+                on_progress(&Progress {
+                    rows_done: rows_done.fetch_add(1, Ordering::Relaxed) + 1,
+                    total_rows: region.height,
+                    elapsed: start.elapsed(),
+                });
+            }
+            (rows, collect_stats.then(stats::finish))
+        };
+
+        let results: Vec<(Rows, Option<RenderStats>)> = if threads == 1 {
+            vec![render_rows(0)]
+        } else {
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = (0..threads)
+                    .map(|t| {
+                        let render_rows = &render_rows;
+                        scope.spawn(move || render_rows(t as u32))
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().expect("Render thread panicked"))
+                    .collect()
+            })
+        };
+        let mut stats = collect_stats.then(RenderStats::default);
+        let mut rows = Vec::new();
+        for (thread_rows, thread_stats) in results {
+            rows.extend(thread_rows);
+            if let (Some(stats), Some(thread_stats)) = (&mut stats, thread_stats) {
+                stats.merge(&thread_stats);
             }
         }
-        image
+
+        let cancelled = rows.len() < region.height as usize;
+        if cancelled {
+            for y in 0..image.height() {
+                image.alpha_row_mut(y).iter_mut().for_each(|a| *a = 0.);
+            }
+        }
+        for (y, row) in rows {
+            for (x, (color, coverage)) in row.into_iter().enumerate() {
+                image.set_pixel(x, y as usize, color);
+                image.set_alpha(x, y as usize, coverage);
+            }
+        }
+        Ok(RenderOutcome {
+            canvas: image,
+            cancelled,
+            stats,
+        })
+    }
+
+    // Checks the camera and the world, returning the inverse camera transform
+    fn prepare(&self, world: &World) -> Result<Mat4x4, Error> {
+        let inverse = self.transform.inverse()?;
+        world.validate()?;
+        Ok(inverse)
+    }
+
+    // The shape seen through the center of pixel (px, py) at shutter open,
+    // None for the background and for pixels outside the image. Panics if a
+    // transform cannot be inverted.
+    pub fn pick(&self, world: &World, px: u32, py: u32) -> Option<Pick> {
+        if px >= self.hsize || py >= self.vsize {
+            return None;
+        }
+        world.pick(self.ray_for_pixel(px, py))
+    }
+
+    // Renders the beauty image with `integrator` and fills the other passes
+    // from the primary ray through the center of each pixel. Panics like
+    // `render_region`.
+    pub fn render_passes(&self, world: &World, integrator: &dyn Integrator) -> RenderPasses {
+        let inverse = self.prepare(world).unwrap_or_else(|e| panic!("{}", e));
+        let mut passes = RenderPasses::new(self.hsize as usize, self.vsize as usize);
+        for y in 0..self.vsize {
+            for x in 0..self.hsize {
+                let (px, py) = (x as usize, y as usize);
+                let (color, coverage) = self.color_for_pixel(world, integrator, &inverse, x, y);
+                passes.color.set_pixel(px, py, color);
+                passes.color.set_alpha(px, py, coverage);
+
+                let ray = self.ray_through(&inverse, x, y, 0.5, 0.5, self.shutter_open);
+                if let Some(comps) = world.first_hit(ray) {
+                    let shape = comps.shape.at_time(comps.time);
+                    passes
+                        .depth
+                        .set(px, py, comps.t * ray.direction.magnitude());
+                    passes.normal.set(px, py, comps.normalv.into());
+                    passes
+                        .albedo
+                        .set_pixel(px, py, shape.material.color_at(&shape, comps.point));
+                    passes
+                        .shape_index
+                        .set(px, py, world.shape_index(comps.shape));
+                }
+            }
+        }
+        passes
     }
 }
 
-pub fn view_transform(from: Tuple, to: Tuple, up: Tuple) -> Mat4x4 {
+pub fn view_transform(from: Point3, to: Point3, up: Vector3) -> Mat4x4 {
     let forw = (to - from).normalize();
     let left = forw.cross(up.normalize());
     let true_up = left.cross(forw);
@@ -84,15 +475,20 @@ pub fn view_transform(from: Tuple, to: Tuple, up: Tuple) -> Mat4x4 {
 
 #[cfg(test)]
 mod tests {
-    use crate::camera::{view_transform, Camera};
+    use crate::camera::{view_transform, Camera, Region};
     use crate::color::Color;
+    use crate::error::Error;
+    use crate::integrators::{AlbedoIntegrator, WhittedIntegrator};
     use crate::matrix::Mat4x4;
+    use crate::pathtracer::PathTracer;
+    use crate::progress::CancelToken;
     use crate::test_utils::{
         assert_color_near, assert_mat4x4_near, assert_near, assert_tuple_near,
     };
     use crate::transform::{rotate_y, scale, translate};
     use crate::tuple::{point, vector};
     use crate::world::World;
+    use std::sync::Mutex;
 
     #[test]
     fn view_transformation_matrix_for_default_orientation() {
@@ -147,6 +543,9 @@ mod tests {
         assert_eq!(vsize, c.vsize);
         assert_eq!(field_of_view, c.field_of_view);
         assert_eq!(Mat4x4::identity(), c.transform);
+        assert_eq!(0., c.shutter_open);
+        assert_eq!(0., c.shutter_close);
+        assert_eq!(1, c.time_samples);
     }
 
     #[test]
@@ -190,6 +589,391 @@ mod tests {
         );
     }
 
+    #[test]
+    fn constructing_ray_at_a_time() {
+        let mut c = Camera::new(201, 101, std::f64::consts::PI / 2.);
+        c.shutter_open = 0.25;
+        c.shutter_close = 0.75;
+        assert_eq!(0.25, c.ray_for_pixel(100, 50).time);
+        assert_eq!(0.5, c.ray_for_pixel_at_time(100, 50, 0.5).time);
+    }
+
+    #[test]
+    fn rendering_moving_sphere_blurs_its_color() {
+        let mut w = World::default();
+        w.shapes[0].end_transform = Some(translate(0., 20., 0.));
+        w.shapes[1].end_transform = Some(translate(0., 20., 0.));
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.0), point(0., 0., 0.), vector(0., 1., 0.));
+        c.shutter_close = 1.;
+        c.time_samples = 64;
+        let image = c.render(w);
+        let blurred = image.get_pixel(5, 5);
+        assert!(blurred.g > 0.);
+        assert!(blurred.g < 0.47583);
+    }
+
+    #[test]
+    fn rendering_world_with_path_tracer() {
+        let w = World::default();
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.0), point(0., 0., 0.), vector(0., 1., 0.));
+        let image = c.render_with(&w, &PathTracer::new(4));
+        assert!(image.get_pixel(5, 5).g > 0.);
+        assert_eq!(Color::black(), image.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn rendering_with_a_debug_integrator() {
+        let w = World::default();
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.0), point(0., 0., 0.), vector(0., 1., 0.));
+        let image = c.render_with(&w, &AlbedoIntegrator);
+        assert_eq!(Color::new(0.8, 1.0, 0.6), image.get_pixel(5, 5));
+        assert_eq!(Color::black(), image.get_pixel(10, 10));
+    }
+
+    #[test]
+    fn rendering_covers_the_last_row_and_column() {
+        let w = World::default();
+        let mut c = Camera::new(3, 3, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -1.2), point(0., 0., 0.), vector(0., 1., 0.));
+        let image = c.render_with(&w, &AlbedoIntegrator);
+        assert_eq!(Color::new(0.8, 1.0, 0.6), image.get_pixel(2, 2));
+    }
+
+    #[test]
+    fn rendering_passes_alongside_the_color() {
+        let w = World::default();
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.0), point(0., 0., 0.), vector(0., 1., 0.));
+        let passes = c.render_passes(&w, &WhittedIntegrator::default());
+        assert_color_near(
+            Color::new(0.38066, 0.47583, 0.2855),
+            passes.color.get_pixel(5, 5),
+            0.00001,
+        );
+        assert_near(4., passes.depth.get(5, 5));
+        assert_tuple_near(vector(0., 0., -1.), passes.normal.get(5, 5), 0.00001);
+        assert_eq!(Color::new(0.8, 1.0, 0.6), passes.albedo.get_pixel(5, 5));
+        assert_eq!(Some(0), passes.shape_index.get(5, 5));
+
+        assert_eq!(f64::INFINITY, passes.depth.get(0, 0));
+        assert_eq!(None, passes.shape_index.get(0, 0));
+    }
+
+    #[test]
+    fn rendering_with_stats_counts_rays_and_tests() {
+        let w = World::default();
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.0), point(0., 0., 0.), vector(0., 1., 0.));
+        let (image, stats) = c.render_with_stats(&w, &WhittedIntegrator::default());
+        let plain = c.render_with(&w, &WhittedIntegrator::default());
+        assert_eq!(plain.get_pixel(5, 5), image.get_pixel(5, 5));
+
+        let passes = c.render_passes(&w, &WhittedIntegrator::default());
+        let hits = (0..11)
+            .flat_map(|y| (0..11).map(move |x| (x, y)))
+            .filter(|&(x, y)| passes.shape_index.get(x, y).is_some())
+            .count() as u64;
+        assert_eq!(121, stats.primary_rays);
+        assert_eq!(hits, stats.shadow_rays);
+        assert_eq!(0, stats.reflection_rays + stats.refraction_rays);
+        assert_eq!(2 * (121 + hits), stats.sphere_tests);
+        assert_eq!(0, stats.plane_tests);
+        assert_eq!((0., 0), (stats.average_depth(), stats.max_depth));
+        assert_eq!(11, stats.tiles.len());
+        assert_eq!(
+            Region {
+                x: 0,
+                y: 10,
+                width: 11,
+                height: 1
+            },
+            stats.tiles[10].region
+        );
+    }
+
+    #[test]
+    fn instrumented_renders_count_the_same_on_any_number_of_threads() {
+        let mut w = World::default();
+        w.shapes[0].material.reflective = 0.5;
+        w.shapes[0].material.transparency = 0.5;
+        w.shapes[0].material.refractive_index = 1.5;
+        let mut c = Camera::new(16, 12, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.0), point(0., 0., 0.), vector(0., 1., 0.));
+        let render = |threads| {
+            let mut stats = c
+                .render_instrumented(
+                    &w,
+                    &WhittedIntegrator::new(3),
+                    c.full_region(),
+                    threads,
+                    &|_| {},
+                    &CancelToken::new(),
+                )
+                .unwrap()
+                .stats
+                .unwrap();
+            stats.tiles.clear();
+            stats
+        };
+        let stats = render(1);
+        assert_eq!(stats, render(3));
+        assert!(stats.reflection_rays > 0 && stats.refraction_rays > 0);
+        assert_eq!(3, stats.max_depth);
+        assert!(stats.average_depth() > 0. && stats.average_depth() < 3.);
+
+        let outcome = c
+            .render_observed(
+                &w,
+                &WhittedIntegrator::new(3),
+                c.full_region(),
+                1,
+                &|_| {},
+                &CancelToken::new(),
+            )
+            .unwrap();
+        assert_eq!(None, outcome.stats);
+    }
+
+    #[test]
+    fn picking_matches_the_shape_index_pass() {
+        let mut w = World::default();
+        w.shapes[1].transform = translate(1.5, 0., -1.);
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.0), point(0., 0., 0.), vector(0., 1., 0.));
+        let passes = c.render_passes(&w, &WhittedIntegrator::default());
+        for y in 0..11 {
+            for x in 0..11 {
+                let pick = c.pick(&w, x, y);
+                let (px, py) = (x as usize, y as usize);
+                assert_eq!(passes.shape_index.get(px, py), pick.map(|p| p.shape_index));
+                if let Some(pick) = pick {
+                    assert_near(passes.depth.get(px, py), pick.distance);
+                }
+            }
+        }
+        let pick = c.pick(&w, 5, 5).unwrap();
+        assert_eq!(0, pick.shape_index);
+        assert_tuple_near(point(0., 0., -1.), pick.point, 0.00001);
+        assert_eq!(None, c.pick(&w, 11, 5));
+    }
+
+    #[test]
+    fn rendering_with_transparent_background_stores_coverage() {
+        let w = World::default();
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.0), point(0., 0., 0.), vector(0., 1., 0.));
+        assert_eq!(1., c.render_with(&w, &AlbedoIntegrator).get_alpha(0, 0));
+
+        c.transparent_background = true;
+        let image = c.render_with(&w, &AlbedoIntegrator);
+        assert_eq!(1., image.get_alpha(5, 5));
+        assert_eq!(0., image.get_alpha(0, 0));
+        assert_eq!(Color::black(), image.get_pixel(0, 0));
+
+        c.time_samples = 64;
+        let alpha = c.render_with(&w, &AlbedoIntegrator).get_alpha(6, 6);
+        assert!(alpha > 0. && alpha < 1.);
+    }
+
+    #[test]
+    fn coverage_comes_from_the_integrators_own_rays() {
+        let w = World::default();
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.0), point(0., 0., 0.), vector(0., 1., 0.));
+        let (_, opaque) = c.render_with_stats(&w, &WhittedIntegrator::default());
+        c.transparent_background = true;
+        let (_, transparent) = c.render_with_stats(&w, &WhittedIntegrator::default());
+        assert_eq!(opaque.sphere_tests, transparent.sphere_tests);
+    }
+
+    #[test]
+    fn resizing_keeps_the_view() {
+        let mut c = Camera::new(200, 125, std::f64::consts::PI / 2.);
+        c.set_size(125, 200);
+        assert_near(0.01, c.pixel_size);
+        c.set_field_of_view(std::f64::consts::PI / 3.);
+        assert_eq!(
+            Camera::new(125, 200, std::f64::consts::PI / 3.).pixel_size,
+            c.pixel_size
+        );
+    }
+
+    #[test]
+    fn rendering_a_region_with_threads() {
+        let w = World::default();
+        let mut c = Camera::new(11, 11, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.0), point(0., 0., 0.), vector(0., 1., 0.));
+        c.time_samples = 2;
+        let full = c.render_with(&w, &WhittedIntegrator::default());
+        let region = Region {
+            x: 3,
+            y: 4,
+            width: 5,
+            height: 6,
+        };
+        for threads in &[1, 4, 20] {
+            let part = c.render_region(&w, &WhittedIntegrator::default(), region, *threads);
+            assert_eq!(5, part.width());
+            assert_eq!(6, part.height());
+            for (x, y, color) in part.pixels() {
+                assert_eq!(full.get_pixel(x + 3, y + 4), color);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "outside the 11x11 image")]
+    fn regions_must_be_inside_the_image() {
+        let region = Region {
+            x: 8,
+            y: 0,
+            width: 5,
+            height: 1,
+        };
+        Camera::new(11, 11, 1.).render_region(&World::default(), &AlbedoIntegrator, region, 1);
+    }
+
+    #[test]
+    fn observed_renders_of_regions_outside_the_image_fail() {
+        let c = Camera::new(4, 4, 1.);
+        let region = Region {
+            x: 2,
+            y: 0,
+            width: 4,
+            height: 4,
+        };
+        let e = c
+            .render_observed(
+                &World::default(),
+                &AlbedoIntegrator,
+                region,
+                1,
+                &|_| {},
+                &CancelToken::new(),
+            )
+            .err()
+            .unwrap();
+        assert!(matches!(e, Error::RegionOutsideImage { width: 4, .. }));
+        assert!(c
+            .render_instrumented(
+                &World::default(),
+                &AlbedoIntegrator,
+                region,
+                1,
+                &|_| {},
+                &CancelToken::new(),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn regions_near_the_integer_limit_do_not_fit() {
+        let region = Region {
+            x: u32::MAX,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        assert!(!region.fits_in(11, 11));
+        let region = Region {
+            x: 0,
+            y: 1,
+            width: 11,
+            height: u32::MAX,
+        };
+        assert!(!region.fits_in(11, 11));
+        assert!(Camera::new(11, 11, 1.).full_region().fits_in(11, 11));
+    }
+
+    #[test]
+    fn rendering_reports_progress_for_every_row() {
+        let w = World::default();
+        let c = Camera::new(4, 6, std::f64::consts::PI / 2.);
+        let reports = Mutex::new(Vec::new());
+        let outcome = c
+            .render_observed(
+                &w,
+                &AlbedoIntegrator,
+                c.full_region(),
+                3,
+                &|p| reports.lock().unwrap().push(*p),
+                &CancelToken::new(),
+            )
+            .unwrap();
+        assert!(!outcome.cancelled);
+        assert!(!outcome.canvas.has_transparency());
+        let mut done: Vec<u32> = reports
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|p| p.rows_done)
+            .collect();
+        done.sort_unstable();
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], done);
+        assert!(reports.lock().unwrap().iter().all(|p| p.total_rows == 6));
+    }
+
+    #[test]
+    fn cancelled_render_returns_the_finished_rows() {
+        let w = World::default();
+        let mut c = Camera::new(5, 5, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -1.2), point(0., 0., 0.), vector(0., 1., 0.));
+        let cancel = CancelToken::new();
+        let outcome = c
+            .render_observed(
+                &w,
+                &AlbedoIntegrator,
+                c.full_region(),
+                1,
+                &|p| {
+                    if p.rows_done == 2 {
+                        cancel.cancel()
+                    }
+                },
+                &cancel,
+            )
+            .unwrap();
+        assert!(outcome.cancelled);
+        let image = outcome.canvas;
+        assert_eq!(Color::new(0.8, 1.0, 0.6), image.get_pixel(2, 1));
+        assert_eq!(1., image.get_alpha(2, 1));
+        assert_eq!(Color::black(), image.get_pixel(2, 2));
+        assert_eq!(0., image.get_alpha(2, 2));
+    }
+
+    #[test]
+    fn non_invertible_transforms_are_reported_before_rendering() {
+        let mut w = World::default();
+        let mut c = Camera::new(5, 5, std::f64::consts::PI / 2.);
+        let render = |c: &Camera, w: &World| {
+            c.render_observed(
+                w,
+                &AlbedoIntegrator,
+                c.full_region(),
+                1,
+                &|_| {},
+                &CancelToken::new(),
+            )
+        };
+        w.shapes[0].transform = scale(1., 0., 1.);
+        assert!(matches!(render(&c, &w), Err(Error::NonInvertibleMatrix)));
+
+        w = World::default();
+        c.transform = Mat4x4::zero();
+        assert!(matches!(render(&c, &w), Err(Error::NonInvertibleMatrix)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Matrix is not invertible")]
+    fn unchecked_renders_panic_on_non_invertible_transforms() {
+        let mut w = World::default();
+        w.shapes[0].transform = scale(1., 0., 1.);
+        Camera::new(5, 5, 1.).render_with(&w, &AlbedoIntegrator);
+    }
+
     #[test]
     fn rendering_world_with_camera() {
         let w = World::default();
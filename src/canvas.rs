@@ -1,81 +1,204 @@
 use crate::color::Color;
+use crate::error::Error;
+use crate::hdr;
+use crate::png::{self, BitDepth};
+use crate::ppm;
+use crate::tonemap::OutputTransform;
+use std::fs::File;
+use std::path::Path;
 use std::vec::Vec;
 
+// Pixels are stored row-major in a single buffer. Each pixel has a coverage
+// (alpha) value in [0, 1]; colors are premultiplied by it, so rays that miss
+// the scene leave black, transparent pixels.
 pub struct Canvas {
-    pixels: Vec<Vec<Color>>,
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    alpha: Vec<f64>,
+    output: OutputTransform,
 }
 
 impl Canvas {
     pub fn new(width: usize, height: usize) -> Self {
         Canvas {
-            pixels: vec![
-                vec![
-                    Color {
-                        r: 0.0,
-                        g: 0.0,
-                        b: 0.0
-                    };
-                    height
-                ];
-                width
-            ],
+            width,
+            height,
+            pixels: vec![Color::black(); width * height],
+            alpha: vec![1.; width * height],
+            output: OutputTransform::default(),
         }
     }
 
     pub fn width(&self) -> usize {
-        self.pixels.len()
+        self.width
     }
 
     pub fn height(&self) -> usize {
-        if self.pixels.is_empty() {
-            0
-        } else {
-            self.pixels[0].len()
-        }
+        self.height
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        assert!(
+            x < self.width && y < self.height,
+            "Pixel ({}, {}) is outside the {}x{} canvas",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        y * self.width + x
     }
 
     // x: column, y: row
     pub fn get_pixel(&self, x: usize, y: usize) -> Color {
-        self.pixels[x][y]
+        self.pixels[self.index(x, y)]
     }
 
     // x: column, y: row
     pub fn set_pixel(&mut self, x: usize, y: usize, c: Color) {
-        self.pixels[x][y] = c
+        let i = self.index(x, y);
+        self.pixels[i] = c
     }
 
-    fn to_ppm(&self) -> String {
-        let mut result = format!(
-            "P3\n{width} {height}\n255\n",
-            width = self.width(),
-            height = self.height(),
-        );
-        for y in 0..self.height() {
-            let mut row_str = Vec::new();
-            for x in 0..self.width() {
-                let p = self.get_pixel(x, y);
-                let r = self.convert(p.r);
-                let g = self.convert(p.g);
-                let b = self.convert(p.b);
-                row_str.push(format!("{} {} {}", r, g, b));
-            }
-            result.push_str(&row_str.join(" "));
-            result.push_str("\n");
-        }
+    pub fn get_alpha(&self, x: usize, y: usize) -> f64 {
+        self.alpha[self.index(x, y)]
+    }
+
+    pub fn set_alpha(&mut self, x: usize, y: usize, alpha: f64) {
+        let i = self.index(x, y);
+        self.alpha[i] = alpha
+    }
+
+    // True when any pixel is not fully opaque
+    pub fn has_transparency(&self) -> bool {
+        self.alpha.iter().any(|&a| a < 1.)
+    }
+
+    pub fn row(&self, y: usize) -> &[Color] {
+        &self.pixels[y * self.width..(y + 1) * self.width]
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [Color] {
+        &mut self.pixels[y * self.width..(y + 1) * self.width]
+    }
+
+    pub fn alpha_row(&self, y: usize) -> &[f64] {
+        &self.alpha[y * self.width..(y + 1) * self.width]
+    }
+
+    pub fn alpha_row_mut(&mut self, y: usize) -> &mut [f64] {
+        &mut self.alpha[y * self.width..(y + 1) * self.width]
+    }
+
+    // All pixels row by row as (x, y, color)
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, Color)> + '_ {
+        let width = self.width;
+        self.pixels
+            .iter()
+            .enumerate()
+            .map(move |(i, c)| (i % width, i / width, *c))
+    }
+
+    pub fn pixels_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut Color)> {
+        let width = self.width;
+        self.pixels
+            .iter_mut()
+            .enumerate()
+            .map(move |(i, c)| (i % width, i / width, c))
+    }
+
+    // Transform applied when writing PPM and PNG files. HDR and PFM files
+    // always store the linear pixels. The default encodes to sRGB; use
+    // `OutputTransform::linear` to write the pixels unchanged.
+    pub fn output_transform(&self) -> OutputTransform {
+        self.output
+    }
+
+    pub fn set_output_transform(&mut self, output: OutputTransform) {
+        self.output = output
+    }
+
+    // Pixel as it is written to low dynamic range formats
+    pub fn output_pixel(&self, x: usize, y: usize) -> Color {
+        self.output.apply(self.get_pixel(x, y))
+    }
+
+    pub fn to_ppm(&self) -> String {
+        let mut result = Vec::new();
+        ppm::write_p3(self, &mut result).expect("Unable to write to memory");
+        String::from_utf8(result).expect("PPM data is ASCII")
+    }
+
+    pub fn write_ppm(&self, file: String) -> Result<(), Error> {
+        Ok(ppm::write_p3(self, File::create(file)?)?)
+    }
+
+    pub fn write_ppm_binary(&self, file: String) -> Result<(), Error> {
+        Ok(ppm::write_p6(self, File::create(file)?)?)
+    }
+
+    // Reads PPM and PGM images, plain or binary
+    pub fn read_ppm(file: String) -> Result<Canvas, Error> {
+        ppm::read(File::open(file)?)
+    }
+
+    // Radiance RGBE, keeps values above 1
+    pub fn write_hdr(&self, file: String) -> Result<(), Error> {
+        Ok(hdr::write_radiance(self, File::create(file)?)?)
+    }
+
+    pub fn read_hdr(file: String) -> Result<Canvas, Error> {
+        hdr::read_radiance(File::open(file)?)
+    }
 
-        result
+    // Portable Float Map, 32-bit float per channel
+    pub fn write_pfm(&self, file: String) -> Result<(), Error> {
+        Ok(hdr::write_pfm(self, File::create(file)?)?)
     }
 
-    pub fn write_ppm(&self, file: String) {
-        use std::fs;
-        let data = self.to_ppm();
-        fs::write(file, data).expect("Unable to write file");
+    pub fn read_pfm(file: String) -> Result<Canvas, Error> {
+        hdr::read_pfm(File::open(file)?)
     }
 
-    fn convert(&self, val: f64) -> u8 {
+    pub fn to_png(&self, depth: BitDepth) -> Vec<u8> {
+        png::encode(self, depth)
+    }
+
+    pub fn write_png(&self, file: String, depth: BitDepth) -> Result<(), Error> {
+        Ok(std::fs::write(file, self.to_png(depth))?)
+    }
+
+    // Picks the image format from the file extension. PNG and PPM files get
+    // the output transform, sRGB encoded unless it is set otherwise.
+    pub fn write(&self, file: String) -> Result<(), Error> {
+        match extension(&file).as_deref() {
+            Some("png") => self.write_png(file, BitDepth::Eight),
+            Some("ppm") => self.write_ppm_binary(file),
+            Some("hdr") => self.write_hdr(file),
+            Some("pfm") => self.write_pfm(file),
+            _ => Err(Error::UnsupportedFormat(file)),
+        }
+    }
+
+    // Picks the image format from the file extension
+    pub fn read(file: String) -> Result<Canvas, Error> {
+        match extension(&file).as_deref() {
+            Some("ppm") | Some("pgm") => Canvas::read_ppm(file),
+            Some("hdr") => Canvas::read_hdr(file),
+            Some("pfm") => Canvas::read_pfm(file),
+            _ => Err(Error::UnsupportedFormat(file)),
+        }
+    }
+
+    pub(crate) fn convert(&self, val: f64) -> u8 {
         self.clamp(val * 255., 0., 255.).round() as u8
     }
 
+    pub(crate) fn convert16(&self, val: f64) -> u16 {
+        self.clamp(val * 65535., 0., 65535.).round() as u16
+    }
+
     fn clamp(&self, val: f64, min: f64, max: f64) -> f64 {
         if val < min {
             min
@@ -87,11 +210,21 @@ impl Canvas {
     }
 }
 
+fn extension(file: &str) -> Option<String> {
+    Path::new(file)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::canvas::Canvas;
     use crate::color::Color;
-    use crate::test_utils::assert_color_eq;
+    use crate::error::Error;
+    use crate::png::BitDepth;
+    use crate::test_utils::{assert_color_eq, assert_color_near};
+    use crate::tonemap::{OutputTransform, ToneMap};
 
     #[test]
     fn create_canvas() {
@@ -126,6 +259,43 @@ mod tests {
         assert_color_eq(color, canvas.get_pixel(2, 3));
     }
 
+    #[test]
+    fn canvas_is_stored_row_major() {
+        let mut canvas = Canvas::new(3, 2);
+        canvas.set_pixel(2, 0, Color::new(1., 0., 0.));
+        canvas.row_mut(1)[0] = Color::new(0., 1., 0.);
+        assert_eq!(
+            &[Color::black(), Color::black(), Color::new(1., 0., 0.)],
+            canvas.row(0)
+        );
+        assert_eq!(Color::new(0., 1., 0.), canvas.get_pixel(0, 1));
+
+        let order: Vec<(usize, usize)> = canvas.pixels().map(|(x, y, _)| (x, y)).collect();
+        assert_eq!(vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)], order);
+        for (x, _, c) in canvas.pixels_mut() {
+            *c = Color::white() * x as f64;
+        }
+        assert_eq!(Color::new(2., 2., 2.), canvas.get_pixel(2, 1));
+    }
+
+    #[test]
+    fn canvas_is_opaque_until_alpha_is_set() {
+        let mut canvas = Canvas::new(2, 2);
+        assert!(!canvas.has_transparency());
+        assert_eq!(1., canvas.get_alpha(1, 1));
+        canvas.set_alpha(1, 1, 0.25);
+        assert!(canvas.has_transparency());
+        assert_eq!(&[1., 0.25], canvas.alpha_row(1));
+        canvas.alpha_row_mut(1)[1] = 1.;
+        assert!(!canvas.has_transparency());
+    }
+
+    #[test]
+    #[should_panic(expected = "outside the 2x2 canvas")]
+    fn pixels_outside_the_canvas_are_rejected() {
+        Canvas::new(2, 2).get_pixel(2, 0);
+    }
+
     #[test]
     fn to_ppm_constructs_ppm_header() {
         let canvas = Canvas::new(5, 3);
@@ -145,6 +315,7 @@ mod tests {
         canvas.set_pixel(0, 0, c1);
         canvas.set_pixel(2, 1, c2);
         canvas.set_pixel(4, 2, c3);
+        canvas.set_output_transform(OutputTransform::linear());
 
         let ppm_str = canvas.to_ppm();
         let pixel_data: Vec<&str> = ppm_str.lines().skip(3).take(3).collect();
@@ -153,6 +324,93 @@ mod tests {
         assert_eq!("0 0 0 0 0 0 0 0 0 0 0 0 0 0 255", pixel_data[2]);
     }
 
+    #[test]
+    fn write_selects_format_by_extension() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.set_pixel(0, 0, Color::new(1., 0.5, 0.));
+        let dir = std::env::temp_dir();
+        let png_path = dir.join("rust_raytracer_canvas_test.PNG");
+        let ppm_path = dir.join("rust_raytracer_canvas_test.ppm");
+        canvas
+            .write(png_path.to_str().unwrap().to_string())
+            .unwrap();
+        canvas
+            .write(ppm_path.to_str().unwrap().to_string())
+            .unwrap();
+        let png_data = std::fs::read(&png_path).unwrap();
+        let ppm_data = std::fs::read(&ppm_path).unwrap();
+        std::fs::remove_file(png_path).unwrap();
+        std::fs::remove_file(ppm_path).unwrap();
+        assert_eq!(canvas.to_png(BitDepth::Eight), png_data);
+        // sRGB encoded by default
+        assert_eq!(b"P6\n2 1\n255\n\xff\xbc\x00\x00\x00\x00", &ppm_data[..]);
+    }
+
+    #[test]
+    fn written_ppm_can_be_read_back() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.set_pixel(1, 0, Color::new(0., 1., 0.2));
+        canvas.set_output_transform(OutputTransform::linear());
+        let path = std::env::temp_dir().join("rust_raytracer_read_back_test.ppm");
+        let path = path.to_str().unwrap().to_string();
+        canvas.write_ppm(path.clone()).unwrap();
+        let read = Canvas::read_ppm(path.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(Color::new(0., 1., 0.2), read.get_pixel(1, 0));
+        assert!(Canvas::read_ppm(path).is_err());
+    }
+
+    #[test]
+    fn hdr_formats_keep_values_above_one() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.set_pixel(0, 0, Color::new(4., 1.5, 0.25));
+        let dir = std::env::temp_dir();
+        for name in &["rust_raytracer_hdr_test.hdr", "rust_raytracer_hdr_test.pfm"] {
+            let path = dir.join(name).to_str().unwrap().to_string();
+            canvas.write(path.clone()).unwrap();
+            let read = Canvas::read(path.clone()).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_color_near(Color::new(4., 1.5, 0.25), read.get_pixel(0, 0), 0.05);
+            assert_eq!(Color::black(), read.get_pixel(1, 0));
+        }
+        assert!(Canvas::read("image.bmp".to_string()).is_err());
+    }
+
+    #[test]
+    fn write_rejects_unknown_extensions() {
+        let e = Canvas::new(1, 1)
+            .write("image.bmp".to_string())
+            .unwrap_err();
+        assert_eq!("Unsupported image format: image.bmp", e.to_string());
+    }
+
+    #[test]
+    fn io_errors_are_returned() {
+        let missing = std::env::temp_dir().join("rust_raytracer_missing_dir/image.ppm");
+        let missing = missing.to_str().unwrap().to_string();
+        assert!(matches!(
+            Canvas::new(1, 1).write(missing.clone()),
+            Err(Error::Io(_))
+        ));
+        assert!(matches!(Canvas::read(missing), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn output_transform_applies_to_ldr_output_only() {
+        let mut canvas = Canvas::new(1, 1);
+        canvas.set_pixel(0, 0, Color::new(0.5, 1., 3.));
+        canvas.set_output_transform(OutputTransform {
+            exposure: 0.,
+            tone_map: ToneMap::Reinhard,
+            srgb: true,
+        });
+        assert_eq!("P3\n1 1\n255\n156 188 225\n", canvas.to_ppm());
+
+        let mut pfm = Vec::new();
+        crate::hdr::write_pfm(&canvas, &mut pfm).unwrap();
+        assert_eq!(3f32.to_le_bytes(), pfm[pfm.len() - 4..]);
+    }
+
     #[test]
     fn to_ppm_adds_terminating_newline() {
         let canvas = Canvas::new(5, 3);
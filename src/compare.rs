@@ -0,0 +1,265 @@
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::error::Error;
+use crate::tonemap::srgb_encode;
+
+// Statistics of the difference between two images of the same size. Channel
+// values are compared linear and unclamped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Comparison {
+    // Largest absolute difference of any channel
+    pub max_difference: f64,
+    pub mean_difference: f64,
+    pub rmse: f64,
+    // Peak signal to noise ratio in dB for a peak value of 1, infinite for
+    // identical images
+    pub psnr: f64,
+    // Pixels whose perceptual difference exceeds the tolerance threshold
+    pub differing_pixels: usize,
+}
+
+// Perceptual matching: pixels count as different when their perceptual
+// difference is above `threshold`, and images match when at most
+// `max_differing_pixels` pixels differ.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    pub threshold: f64,
+    pub max_differing_pixels: usize,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            threshold: 0.02,
+            max_differing_pixels: 0,
+        }
+    }
+}
+
+fn check_size(a: &Canvas, b: &Canvas) -> Result<(), Error> {
+    if a.width() != b.width() || a.height() != b.height() {
+        return Err(Error::ImageMismatch(format!(
+            "Image sizes differ: {}x{} and {}x{}",
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height()
+        )));
+    }
+    Ok(())
+}
+
+pub fn compare(a: &Canvas, b: &Canvas, tolerance: &Tolerance) -> Result<Comparison, Error> {
+    check_size(a, b)?;
+    let mut max_difference: f64 = 0.;
+    let mut sum = 0.;
+    let mut sum_squares = 0.;
+    let mut differing_pixels = 0;
+    for (x, y, ca) in a.pixels() {
+        let cb = b.get_pixel(x, y);
+        for d in &[ca.r - cb.r, ca.g - cb.g, ca.b - cb.b] {
+            max_difference = max_difference.max(d.abs());
+            sum += d.abs();
+            sum_squares += d * d;
+        }
+        if perceptual_difference(ca, cb) > tolerance.threshold {
+            differing_pixels += 1;
+        }
+    }
+    let samples = (a.width() * a.height() * 3).max(1) as f64;
+    let mse = sum_squares / samples;
+    Ok(Comparison {
+        max_difference,
+        mean_difference: sum / samples,
+        rmse: mse.sqrt(),
+        psnr: if mse == 0. {
+            f64::INFINITY
+        } else {
+            -10. * mse.log10()
+        },
+        differing_pixels,
+    })
+}
+
+// Per-pixel absolute difference of every channel
+pub fn difference(a: &Canvas, b: &Canvas) -> Result<Canvas, Error> {
+    check_size(a, b)?;
+    let mut out = Canvas::new(a.width(), a.height());
+    for (x, y, c) in out.pixels_mut() {
+        let (ca, cb) = (a.get_pixel(x, y), b.get_pixel(x, y));
+        *c = Color::new(
+            (ca.r - cb.r).abs(),
+            (ca.g - cb.g).abs(),
+            (ca.b - cb.b).abs(),
+        );
+    }
+    Ok(out)
+}
+
+// Perceptual difference in [0, 1] of two linear colors, the weighted YIQ
+// distance of their sRGB encodings used by pixelmatch. Values above 1 are
+// clamped as they would be on a display.
+pub fn perceptual_difference(a: Color, b: Color) -> f64 {
+    let yiq = |c: Color| {
+        let (r, g, b) = (srgb_encode(c.r), srgb_encode(c.g), srgb_encode(c.b));
+        (
+            0.298_895_31 * r + 0.586_622_47 * g + 0.114_482_23 * b,
+            0.595_977_99 * r - 0.274_176_10 * g - 0.321_801_89 * b,
+            0.211_470_17 * r - 0.522_617_20 * g + 0.311_147_03 * b,
+        )
+    };
+    let (ya, ia, qa) = yiq(a);
+    let (yb, ib, qb) = yiq(b);
+    let (y, i, q) = (ya - yb, ia - ib, qa - qb);
+    // Normalized so that black and white are 1 apart
+    let delta = 0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q;
+    (delta / 0.5053).sqrt().min(1.)
+}
+
+// Perceptual difference of every pixel as black for none, through red, to
+// yellow for the largest difference in the image.
+pub fn heat_map(a: &Canvas, b: &Canvas) -> Result<Canvas, Error> {
+    check_size(a, b)?;
+    let mut out = Canvas::new(a.width(), a.height());
+    let differences: Vec<f64> = a
+        .pixels()
+        .map(|(x, y, c)| perceptual_difference(c, b.get_pixel(x, y)))
+        .collect();
+    let max = differences.iter().cloned().fold(0., f64::max);
+    if max == 0. {
+        return Ok(out);
+    }
+    for ((_, _, c), d) in out.pixels_mut().zip(differences) {
+        let t = d / max;
+        *c = if t < 0.5 {
+            Color::new(t * 2., 0., 0.)
+        } else {
+            Color::new(1., (t - 0.5) * 2., 0.)
+        };
+    }
+    Ok(out)
+}
+
+// Compares a render to a reference image on disk, in any format
+// `Canvas::read` supports. The error describes the mismatch.
+pub fn matches_reference(
+    canvas: &Canvas,
+    reference_file: &str,
+    tolerance: &Tolerance,
+) -> Result<Comparison, Error> {
+    let reference = Canvas::read(reference_file.to_string())?;
+    let comparison = compare(canvas, &reference, tolerance)?;
+    if comparison.differing_pixels > tolerance.max_differing_pixels {
+        return Err(Error::ImageMismatch(format!(
+            "{} pixels differ from {} (at most {} allowed), RMSE {:.5}, PSNR {:.2} dB",
+            comparison.differing_pixels,
+            reference_file,
+            tolerance.max_differing_pixels,
+            comparison.rmse,
+            comparison.psnr
+        )));
+    }
+    Ok(comparison)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::{view_transform, Camera};
+    use crate::canvas::Canvas;
+    use crate::color::Color;
+    use crate::compare::{
+        compare, difference, heat_map, matches_reference, perceptual_difference, Tolerance,
+    };
+    use crate::test_utils::assert_f64_near;
+    use crate::tuple::{point, vector};
+    use crate::world::World;
+
+    fn gradient() -> Canvas {
+        let mut canvas = Canvas::new(4, 2);
+        for (x, y, c) in canvas.pixels_mut() {
+            *c = Color::new(x as f64 / 4., y as f64 / 2., 0.5);
+        }
+        canvas
+    }
+
+    #[test]
+    fn identical_images() {
+        let c = compare(&gradient(), &gradient(), &Tolerance::default()).unwrap();
+        assert_eq!(0., c.max_difference);
+        assert_eq!(0., c.rmse);
+        assert_eq!(f64::INFINITY, c.psnr);
+        assert_eq!(0, c.differing_pixels);
+        assert_eq!(
+            Color::black(),
+            heat_map(&gradient(), &gradient()).unwrap().get_pixel(1, 1)
+        );
+    }
+
+    #[test]
+    fn statistics_of_a_single_changed_pixel() {
+        let mut other = gradient();
+        other.set_pixel(1, 1, Color::new(0.25, 0.5, 0.));
+        let c = compare(&gradient(), &other, &Tolerance::default()).unwrap();
+        assert_eq!(0.5, c.max_difference);
+        assert_f64_near(0.5 / 24., c.mean_difference, 1e-12);
+        assert_f64_near((0.25f64 / 24.).sqrt(), c.rmse, 1e-12);
+        assert_f64_near(-10. * (0.25f64 / 24.).log10(), c.psnr, 1e-9);
+        assert_eq!(1, c.differing_pixels);
+
+        let d = difference(&gradient(), &other).unwrap();
+        assert_eq!(Color::new(0., 0., 0.5), d.get_pixel(1, 1));
+        assert_eq!(Color::black(), d.get_pixel(0, 0));
+
+        let heat = heat_map(&gradient(), &other).unwrap();
+        assert_eq!(Color::new(1., 1., 0.), heat.get_pixel(1, 1));
+        assert_eq!(Color::black(), heat.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn perceptual_difference_ignores_invisible_changes() {
+        assert_f64_near(
+            1.,
+            perceptual_difference(Color::black(), Color::white()),
+            1e-3,
+        );
+        assert_eq!(
+            0.,
+            perceptual_difference(Color::white(), Color::new(3., 3., 3.))
+        );
+        let small = perceptual_difference(Color::new(0.5, 0.5, 0.5), Color::new(0.501, 0.5, 0.5));
+        assert!(small < Tolerance::default().threshold);
+    }
+
+    #[test]
+    fn mismatched_sizes_are_errors() {
+        assert!(compare(
+            &Canvas::new(2, 2),
+            &Canvas::new(2, 3),
+            &Tolerance::default()
+        )
+        .is_err());
+        assert!(difference(&Canvas::new(2, 2), &Canvas::new(3, 2)).is_err());
+        assert!(heat_map(&Canvas::new(2, 2), &Canvas::new(3, 2)).is_err());
+    }
+
+    #[test]
+    fn render_matches_stored_reference() {
+        let mut camera = Camera::new(11, 11, std::f64::consts::PI / 2.);
+        camera.transform =
+            view_transform(point(0., 0., -5.), point(0., 0., 0.), vector(0., 1., 0.));
+        let image = camera.render(World::default());
+        let path = std::env::temp_dir().join("rust_raytracer_reference_test.pfm");
+        let path = path.to_str().unwrap().to_string();
+        image.write(path.clone()).unwrap();
+
+        assert!(matches_reference(&image, &path, &Tolerance::default()).is_ok());
+        let mut changed = camera.render(World::default());
+        changed.set_pixel(5, 5, Color::new(1., 0., 0.));
+        let result = matches_reference(&changed, &path, &Tolerance::default());
+        std::fs::remove_file(&path).unwrap();
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("1 pixels differ"));
+    }
+}
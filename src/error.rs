@@ -0,0 +1,105 @@
+use crate::camera::Region;
+use crate::scene::SceneError;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    // A transform that flattens space, e.g. a zero scale
+    NonInvertibleMatrix,
+    Io(io::Error),
+    // Malformed image data
+    Parse(String),
+    Scene(SceneError),
+    UnsupportedFormat(String),
+    // Images of different sizes, or a render that differs from its reference
+    ImageMismatch(String),
+    // A frame range that runs backwards or is not finite, or a frame rate
+    // that is not positive and finite
+    InvalidFrameSequence {
+        start: f64,
+        end: f64,
+        frame_rate: f64,
+    },
+    // A render region that is not inside the camera's image
+    RegionOutsideImage {
+        region: Region,
+        width: u32,
+        height: u32,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NonInvertibleMatrix => write!(f, "Matrix is not invertible"),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Parse(message) => write!(f, "{}", message),
+            Error::Scene(e) => write!(f, "{}", e),
+            Error::UnsupportedFormat(file) => write!(f, "Unsupported image format: {}", file),
+            Error::ImageMismatch(message) => write!(f, "{}", message),
+            Error::InvalidFrameSequence {
+                start,
+                end,
+                frame_rate,
+            } => write!(
+                f,
+                "Invalid frames from {}s to {}s at {} frames per second",
+                start, end, frame_rate
+            ),
+            Error::RegionOutsideImage {
+                region,
+                width,
+                height,
+            } => write!(
+                f,
+                "Region {:?} is outside the {}x{} image",
+                region, width, height
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Scene(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<SceneError> for Error {
+    fn from(e: SceneError) -> Self {
+        Error::Scene(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::scene::SceneError;
+    use std::error::Error as _;
+    use std::io;
+
+    #[test]
+    fn errors_convert_and_keep_their_source() {
+        let e: Error = io::Error::new(io::ErrorKind::NotFound, "no such file").into();
+        assert_eq!("no such file", e.to_string());
+        assert!(e.source().is_some());
+
+        let e: Error = SceneError::new(3, "Unknown shape").into();
+        assert_eq!("line 3: Unknown shape", e.to_string());
+        assert!(matches!(e, Error::Scene(_)));
+        assert!(Error::NonInvertibleMatrix.source().is_none());
+    }
+}
@@ -0,0 +1,525 @@
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::error::Error;
+use crate::ppm::Parser;
+use std::io::{self, BufWriter, Read, Write};
+
+// Radiance RGBE with run-length encoded scanlines
+pub fn write_radiance<W: Write>(canvas: &Canvas, out: W) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        canvas.height(),
+        canvas.width()
+    )?;
+
+    let width = canvas.width();
+    let mut scanline = vec![[0u8; 4]; width];
+    for y in 0..canvas.height() {
+        for (x, rgbe) in scanline.iter_mut().enumerate() {
+            *rgbe = to_rgbe(canvas.get_pixel(x, y));
+        }
+        // Only widths in 8..=32767 can be run-length encoded
+        if !(8..=0x7fff).contains(&width) {
+            for rgbe in &scanline {
+                out.write_all(rgbe)?;
+            }
+            continue;
+        }
+        out.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        for component in 0..4 {
+            let values: Vec<u8> = scanline.iter().map(|p| p[component]).collect();
+            write_rle_component(&mut out, &values)?;
+        }
+    }
+    out.flush()
+}
+
+fn write_rle_component<W: Write>(out: &mut W, values: &[u8]) -> io::Result<()> {
+    const MIN_RUN: usize = 4;
+    let mut i = 0;
+    while i < values.len() {
+        // Find the next run long enough to be worth encoding
+        let mut run_start = i;
+        let mut run_len = 0;
+        while run_start < values.len() {
+            run_len = 1;
+            while run_start + run_len < values.len()
+                && run_len < 127
+                && values[run_start + run_len] == values[run_start]
+            {
+                run_len += 1;
+            }
+            if run_len >= MIN_RUN {
+                break;
+            }
+            run_start += run_len;
+        }
+        if run_start >= values.len() {
+            run_len = 0;
+        }
+
+        // Literals before the run, at most 128 at a time
+        while i < run_start {
+            let count = (run_start - i).min(128);
+            out.write_all(&[count as u8])?;
+            out.write_all(&values[i..i + count])?;
+            i += count;
+        }
+        if run_len >= MIN_RUN {
+            out.write_all(&[128 + run_len as u8, values[run_start]])?;
+            i += run_len;
+        }
+    }
+    Ok(())
+}
+
+fn to_rgbe(c: Color) -> [u8; 4] {
+    let (r, g, b) = (c.r.max(0.), c.g.max(0.), c.b.max(0.));
+    let v = r.max(g).max(b);
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    if v / 2f64.powi(e) >= 1. {
+        e += 1;
+    } else if v / 2f64.powi(e) < 0.5 {
+        e -= 1;
+    }
+    let scale = 256. / 2f64.powi(e);
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (e + 128).clamp(0, 255) as u8,
+    ]
+}
+
+fn from_rgbe(rgbe: [u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::black();
+    }
+    let f = 2f64.powi(rgbe[3] as i32 - (128 + 8));
+    Color::new(
+        (rgbe[0] as f64 + 0.5) * f,
+        (rgbe[1] as f64 + 0.5) * f,
+        (rgbe[2] as f64 + 0.5) * f,
+    )
+}
+
+// Reads flat and run-length encoded 32-bit_rle_rgbe files
+pub fn read_radiance<R: Read>(mut input: R) -> Result<Canvas, Error> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    decode_radiance(&data).map_err(Error::Parse)
+}
+
+fn decode_radiance(data: &[u8]) -> Result<Canvas, String> {
+    let mut pos = 0;
+    let next_line = |pos: &mut usize| -> Result<String, String> {
+        let start = *pos;
+        while *pos < data.len() && data[*pos] != b'\n' {
+            *pos += 1;
+        }
+        if *pos >= data.len() {
+            return Err("Unexpected end of Radiance header".to_string());
+        }
+        *pos += 1;
+        Ok(String::from_utf8_lossy(&data[start..*pos - 1]).into_owned())
+    };
+
+    let magic = next_line(&mut pos)?;
+    if !magic.starts_with("#?") {
+        return Err("Missing Radiance signature".to_string());
+    }
+    loop {
+        let line = next_line(&mut pos)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("Unsupported Radiance format '{}'", format));
+            }
+        }
+    }
+    let resolution = next_line(&mut pos)?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    let (flip, height, width) = match fields.as_slice() {
+        [y, h, "+X", w] if *y == "-Y" || *y == "+Y" => (
+            *y == "+Y",
+            h.parse::<usize>()
+                .map_err(|_| format!("Invalid height '{}'", h))?,
+            w.parse::<usize>()
+                .map_err(|_| format!("Invalid width '{}'", w))?,
+        ),
+        _ => return Err(format!("Unsupported Radiance resolution '{}'", resolution)),
+    };
+
+    if width.checked_mul(height).is_none() {
+        return Err(format!("Image size {}x{} is too large", width, height));
+    }
+
+    // Run-length encoding keeps the header sizes from being checked against
+    // the data up front, so rows are only stored once they have been decoded
+    let mut pixels = Vec::new();
+    let mut scanline = Vec::new();
+    for _ in 0..height {
+        pos = read_scanline(data, pos, width, &mut scanline)?;
+        pixels.extend_from_slice(&scanline);
+    }
+    let mut canvas = Canvas::new(width, height);
+    for (row, rgbes) in pixels.chunks(width.max(1)).enumerate() {
+        let y = if flip { height - 1 - row } else { row };
+        for (x, rgbe) in rgbes.iter().enumerate() {
+            canvas.set_pixel(x, y, from_rgbe(*rgbe));
+        }
+    }
+    Ok(canvas)
+}
+
+// Decodes one scanline of `width` pixels into `scanline`, growing it only as
+// far as the data goes
+fn read_scanline(
+    data: &[u8],
+    mut pos: usize,
+    width: usize,
+    scanline: &mut Vec<[u8; 4]>,
+) -> Result<usize, String> {
+    let eof = || "Unexpected end of Radiance data".to_string();
+    scanline.clear();
+    let header = data.get(pos..pos + 4).ok_or_else(eof)?;
+    let encoded_width = (header[2] as usize) << 8 | header[3] as usize;
+
+    if header[0] == 2 && header[1] == 2 && header[2] & 0x80 == 0 && (8..=0x7fff).contains(&width) {
+        if encoded_width != width {
+            return Err("Radiance scanline width mismatch".to_string());
+        }
+        pos += 4;
+        for component in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = *data.get(pos).ok_or_else(eof)? as usize;
+                pos += 1;
+                if count > 128 {
+                    let run = count - 128;
+                    let value = *data.get(pos).ok_or_else(eof)?;
+                    pos += 1;
+                    if x + run > width {
+                        return Err("Radiance run overflows the scanline".to_string());
+                    }
+                    if component == 0 {
+                        scanline.resize(x + run, [value, 0, 0, 0]);
+                    }
+                    for pixel in &mut scanline[x..x + run] {
+                        pixel[component] = value;
+                    }
+                    x += run;
+                } else {
+                    if count == 0 || x + count > width {
+                        return Err("Invalid Radiance literal run".to_string());
+                    }
+                    let values = data.get(pos..pos + count).ok_or_else(eof)?;
+                    if component == 0 {
+                        scanline.resize(x + count, [0; 4]);
+                    }
+                    for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                        pixel[component] = *value;
+                    }
+                    pos += count;
+                    x += count;
+                }
+            }
+        }
+        return Ok(pos);
+    }
+
+    // Flat pixels, possibly with old style (1, 1, 1, n) repeat markers
+    let mut shift = 0;
+    while scanline.len() < width {
+        let p = data.get(pos..pos + 4).ok_or_else(eof)?;
+        pos += 4;
+        if p[0] == 1 && p[1] == 1 && p[2] == 1 {
+            let previous = *scanline
+                .last()
+                .ok_or("Radiance repeat marker at start of scanline")?;
+            // Every further consecutive marker counts in units 256 times larger
+            let count = u64::from(p[3]) << shift;
+            if shift > 24 || count > (width - scanline.len()) as u64 {
+                return Err("Radiance run overflows the scanline".to_string());
+            }
+            let count = count as usize;
+            scanline
+                .try_reserve(count)
+                .map_err(|_| "Radiance scanline is too large to decode".to_string())?;
+            scanline.resize(scanline.len() + count, previous);
+            shift += 8;
+        } else {
+            scanline.push([p[0], p[1], p[2], p[3]]);
+            shift = 0;
+        }
+    }
+    Ok(pos)
+}
+
+// Portable Float Map, little-endian 32-bit floats stored bottom row first
+pub fn write_pfm<W: Write>(canvas: &Canvas, out: W) -> io::Result<()> {
+    write_float_map(out, canvas.width(), canvas.height(), 3, |x, y| {
+        let p = canvas.get_pixel(x, y);
+        [p.r, p.g, p.b]
+    })
+}
+
+// Writes a color (PF) map for 3 channels, or a grayscale (Pf) map of the
+// first value of every pixel for 1
+pub(crate) fn write_float_map<W, F>(
+    out: W,
+    width: usize,
+    height: usize,
+    channels: usize,
+    pixel: F,
+) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(usize, usize) -> [f64; 3],
+{
+    let mut out = BufWriter::new(out);
+    let magic = if channels == 1 { "Pf" } else { "PF" };
+    write!(out, "{}\n{} {}\n-1.0\n", magic, width, height)?;
+    for y in (0..height).rev() {
+        for x in 0..width {
+            for v in &pixel(x, y)[..channels] {
+                out.write_all(&(*v as f32).to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+// Reads color (PF) and grayscale (Pf) maps of either byte order. The
+// magnitude of the scale field is ignored.
+pub fn read_pfm<R: Read>(mut input: R) -> Result<Canvas, Error> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    decode_pfm(&data).map_err(Error::Parse)
+}
+
+fn decode_pfm(data: &[u8]) -> Result<Canvas, String> {
+    let mut parser = Parser::new(data);
+
+    let channels = match parser.token()?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        other => return Err(format!("Unsupported image format '{}'", other)),
+    };
+    let mut number = |what: &str| -> Result<String, String> {
+        parser.token().map_err(|_| format!("Missing {}", what))
+    };
+    let width_token = number("width")?;
+    let height_token = number("height")?;
+    let scale_token = number("scale")?;
+    let width: usize = width_token
+        .parse()
+        .map_err(|_| format!("Invalid width '{}'", width_token))?;
+    let height: usize = height_token
+        .parse()
+        .map_err(|_| format!("Invalid height '{}'", height_token))?;
+    let scale: f64 = scale_token
+        .parse()
+        .map_err(|_| format!("Invalid scale '{}'", scale_token))?;
+    if scale == 0. {
+        return Err("Invalid scale 0".to_string());
+    }
+    let little_endian = scale < 0.;
+
+    match parser.data.get(parser.pos) {
+        Some(b) if b.is_ascii_whitespace() => parser.pos += 1,
+        _ => return Err("Expected whitespace after scale".to_string()),
+    }
+    let raster = &parser.data[parser.pos..];
+    let size = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels * 4))
+        .ok_or_else(|| format!("Image size {}x{} is too large", width, height))?;
+    if raster.len() < size {
+        return Err("Unexpected end of image data".to_string());
+    }
+
+    let mut canvas = Canvas::new(width, height);
+    let mut floats = raster.chunks(4).map(|b| {
+        let bytes = [b[0], b[1], b[2], b[3]];
+        f64::from(if little_endian {
+            f32::from_le_bytes(bytes)
+        } else {
+            f32::from_be_bytes(bytes)
+        })
+    });
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let color = if channels == 1 {
+                let v = floats.next().unwrap();
+                Color::new(v, v, v)
+            } else {
+                let (r, g, b) = (floats.next(), floats.next(), floats.next());
+                Color::new(r.unwrap(), g.unwrap(), b.unwrap())
+            };
+            canvas.set_pixel(x, y, color);
+        }
+    }
+    Ok(canvas)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::canvas::Canvas;
+    use crate::color::Color;
+    use crate::hdr::{from_rgbe, read_pfm, read_radiance, to_rgbe, write_pfm, write_radiance};
+    use crate::test_utils::assert_color_near;
+
+    fn hdr_canvas(width: usize) -> Canvas {
+        let mut canvas = Canvas::new(width, 3);
+        for x in 0..width {
+            canvas.set_pixel(x, 0, Color::new(0.25, 0.5, 1.));
+            canvas.set_pixel(x, 1, Color::new(x as f64 * 0.37, 12.5, 0.003));
+        }
+        canvas.set_pixel(0, 2, Color::new(150., 3., -1.));
+        canvas
+    }
+
+    fn assert_relative_near(expected: Color, actual: Color) {
+        let tol = 0.01 * expected.r.max(expected.g).max(expected.b).max(0.) + 1e-9;
+        assert_color_near(
+            Color::new(expected.r.max(0.), expected.g.max(0.), expected.b.max(0.)),
+            actual,
+            tol,
+        );
+    }
+
+    #[test]
+    fn rgbe_conversion_keeps_values_above_one() {
+        assert_eq!([0, 0, 0, 0], to_rgbe(Color::black()));
+        assert_eq!([128, 64, 0, 129], to_rgbe(Color::new(1., 0.5, 0.)));
+        assert_relative_near(
+            Color::new(5.5, 1.25, 0.1),
+            from_rgbe(to_rgbe(Color::new(5.5, 1.25, 0.1))),
+        );
+        assert_eq!(Color::black(), from_rgbe([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn radiance_header() {
+        let mut out = Vec::new();
+        write_radiance(&hdr_canvas(10), &mut out).unwrap();
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 3 +X 10\n";
+        assert_eq!(&header[..], &out[..header.len()]);
+        assert_eq!([2, 2, 0, 10], out[header.len()..header.len() + 4]);
+    }
+
+    #[test]
+    fn radiance_round_trips_with_and_without_rle() {
+        for width in &[3, 10, 300] {
+            let canvas = hdr_canvas(*width);
+            let mut out = Vec::new();
+            write_radiance(&canvas, &mut out).unwrap();
+            let read = read_radiance(&out[..]).unwrap();
+            assert_eq!(canvas.width(), read.width());
+            assert_eq!(canvas.height(), read.height());
+            for y in 0..canvas.height() {
+                for x in 0..canvas.width() {
+                    assert_relative_near(canvas.get_pixel(x, y), read.get_pixel(x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn radiance_rle_compresses_flat_rows() {
+        let canvas = Canvas::new(300, 2);
+        let mut out = Vec::new();
+        write_radiance(&canvas, &mut out).unwrap();
+        assert!(out.len() < 150);
+    }
+
+    #[test]
+    fn radiance_reads_old_style_runs() {
+        let mut data = b"#?RGBE\n\n-Y 1 +X 4\n".to_vec();
+        data.extend_from_slice(&[128, 64, 0, 129, 1, 1, 1, 3]);
+        let canvas = read_radiance(&data[..]).unwrap();
+        for x in 0..4 {
+            assert_relative_near(Color::new(1., 0.5, 0.), canvas.get_pixel(x, 0));
+        }
+    }
+
+    #[test]
+    fn radiance_rejects_invalid_files() {
+        assert!(read_radiance(&b"P3\n"[..]).is_err());
+        assert!(read_radiance(&b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n"[..]).is_err());
+        assert!(read_radiance(&b"#?RADIANCE\n\n+X 1 -Y 1\n"[..]).is_err());
+        assert!(read_radiance(&b"#?RADIANCE\n\n-Y 1 +X 2\n\x01\x02\x03\x80"[..]).is_err());
+    }
+
+    #[test]
+    fn radiance_rejects_sizes_the_data_does_not_hold() {
+        let huge = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 100000 +X 100000\n";
+        assert!(read_radiance(&huge[..]).is_err());
+        let overflow = b"#?RADIANCE\n\n-Y 18446744073709551615 +X 2\n";
+        assert!(read_radiance(&overflow[..]).is_err());
+
+        // Repeat markers may not grow a row past its width
+        let mut data = b"#?RGBE\n\n-Y 1 +X 100\n".to_vec();
+        data.extend_from_slice(&[128, 64, 0, 129, 1, 1, 1, 255]);
+        assert!(read_radiance(&data[..]).is_err());
+    }
+
+    #[test]
+    fn pfm_header_and_row_order() {
+        let mut canvas = Canvas::new(2, 2);
+        canvas.set_pixel(0, 1, Color::new(2.5, 0., -1.));
+        let mut out = Vec::new();
+        write_pfm(&canvas, &mut out).unwrap();
+        assert_eq!(b"PF\n2 2\n-1.0\n", &out[..12]);
+        assert_eq!(12 + 2 * 2 * 3 * 4, out.len());
+        // Bottom row first
+        assert_eq!(2.5f32.to_le_bytes(), out[12..16]);
+        assert_eq!((-1f32).to_le_bytes(), out[20..24]);
+    }
+
+    #[test]
+    fn pfm_round_trips_exactly_at_f32_precision() {
+        let canvas = hdr_canvas(5);
+        let mut out = Vec::new();
+        write_pfm(&canvas, &mut out).unwrap();
+        let read = read_pfm(&out[..]).unwrap();
+        for y in 0..canvas.height() {
+            for x in 0..canvas.width() {
+                assert_color_near(canvas.get_pixel(x, y), read.get_pixel(x, y), 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn pfm_reads_big_endian_grayscale() {
+        let mut data = b"Pf\n2 1\n1.0\n".to_vec();
+        data.extend_from_slice(&0.5f32.to_be_bytes());
+        data.extend_from_slice(&4f32.to_be_bytes());
+        let canvas = read_pfm(&data[..]).unwrap();
+        assert_eq!(Color::new(0.5, 0.5, 0.5), canvas.get_pixel(0, 0));
+        assert_eq!(Color::new(4., 4., 4.), canvas.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn pfm_rejects_invalid_files() {
+        assert!(read_pfm(&b"P6\n1 1\n255\n"[..]).is_err());
+        assert!(read_pfm(&b"PF\n1 1\n0\n"[..]).is_err());
+        assert!(read_pfm(&b"PF\n1 1\n-1.0\n\x00\x00"[..]).is_err());
+    }
+
+    #[test]
+    fn pfm_rejects_sizes_the_data_does_not_hold() {
+        assert!(read_pfm(&b"PF 4000000000 4000000000 -1.0\n"[..]).is_err());
+        assert!(read_pfm(&b"PF 18446744073709551615 2 -1.0\n"[..]).is_err());
+        assert!(read_pfm(&b"Pf 100000 100000 -1.0\n\x00\x00\x00\x00"[..]).is_err());
+    }
+}
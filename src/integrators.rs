@@ -0,0 +1,296 @@
+use crate::color::Color;
+use crate::occlusion::AmbientOcclusion;
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::world::World;
+
+// Computes the color seen along a camera ray. `Camera::render_with` drives an
+// integrator for every sample of every pixel, possibly from several threads.
+pub trait Integrator: Sync {
+    // The color and whether the ray hit a shape, which the camera turns into
+    // coverage for transparent backgrounds
+    fn sample(&self, world: &World, ray: Ray, rng: &mut Rng) -> (Color, bool);
+
+    fn color_at(&self, world: &World, ray: Ray, rng: &mut Rng) -> Color {
+        self.sample(world, ray, rng).0
+    }
+
+    // Minimum number of samples per pixel the integrator needs
+    fn samples_per_pixel(&self) -> u32 {
+        1
+    }
+
+    // Mixed into the random seed of every pixel, so that renders with
+    // different seeds get independent noise
+    fn seed(&self) -> u64 {
+        0
+    }
+}
+
+// Phong direct lighting with recursive reflection and refraction
+#[derive(Clone, Copy, Debug)]
+pub struct WhittedIntegrator {
+    pub max_depth: i8,
+}
+
+impl WhittedIntegrator {
+    pub fn new(max_depth: i8) -> Self {
+        WhittedIntegrator { max_depth }
+    }
+}
+
+impl Default for WhittedIntegrator {
+    fn default() -> Self {
+        Self::new(5)
+    }
+}
+
+impl Integrator for WhittedIntegrator {
+    fn sample(&self, world: &World, ray: Ray, _rng: &mut Rng) -> (Color, bool) {
+        match world.first_hit(ray) {
+            Some(comps) => (world.shade_hit(comps, self.max_depth), true),
+            None => (Color::black(), false),
+        }
+    }
+}
+
+// World space surface normal, mapped from [-1, 1] to [0, 1]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NormalIntegrator;
+
+impl Integrator for NormalIntegrator {
+    fn sample(&self, world: &World, ray: Ray, _rng: &mut Rng) -> (Color, bool) {
+        match world.first_hit(ray) {
+            Some(comps) => {
+                let n = comps.normalv;
+                let color = Color::new(n.x() * 0.5 + 0.5, n.y() * 0.5 + 0.5, n.z() * 0.5 + 0.5);
+                (color, true)
+            }
+            None => (Color::black(), false),
+        }
+    }
+}
+
+// Grayscale hit distance, white at the camera fading to black at `max_distance`
+#[derive(Clone, Copy, Debug)]
+pub struct DepthIntegrator {
+    pub max_distance: f64,
+}
+
+impl DepthIntegrator {
+    pub fn new(max_distance: f64) -> Self {
+        DepthIntegrator { max_distance }
+    }
+}
+
+impl Integrator for DepthIntegrator {
+    fn sample(&self, world: &World, ray: Ray, _rng: &mut Rng) -> (Color, bool) {
+        match world.first_hit(ray) {
+            Some(comps) => {
+                let distance = comps.t * ray.direction.magnitude();
+                let color = Color::white() * (1. - distance / self.max_distance).clamp(0., 1.);
+                (color, true)
+            }
+            None => (Color::black(), false),
+        }
+    }
+}
+
+// Unlit surface color, the material color or its pattern
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AlbedoIntegrator;
+
+impl Integrator for AlbedoIntegrator {
+    fn sample(&self, world: &World, ray: Ray, _rng: &mut Rng) -> (Color, bool) {
+        match world.first_hit(ray) {
+            Some(comps) => {
+                let shape = comps.shape.at_time(comps.time);
+                (shape.material.color_at(&shape, comps.point), true)
+            }
+            None => (Color::black(), false),
+        }
+    }
+}
+
+// A distinct flat color for every shape index in the world
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ObjectIdIntegrator;
+
+impl ObjectIdIntegrator {
+    pub fn id_color(index: usize) -> Color {
+        // Golden ratio steps around the hue circle keep neighbouring ids apart
+        let h = (index as f64 * 0.618_033_988_749_895).fract() * 6.;
+        Color::new(
+            ((h - 3.).abs() - 1.).clamp(0., 1.),
+            (2. - (h - 2.).abs()).clamp(0., 1.),
+            (2. - (h - 4.).abs()).clamp(0., 1.),
+        )
+    }
+}
+
+impl Integrator for ObjectIdIntegrator {
+    fn sample(&self, world: &World, ray: Ray, _rng: &mut Rng) -> (Color, bool) {
+        match world.first_hit(ray) {
+            Some(comps) => {
+                let index = world.shape_index(comps.shape);
+                (index.map_or(Color::black(), Self::id_color), true)
+            }
+            None => (Color::black(), false),
+        }
+    }
+}
+
+// Grayscale unoccluded fraction at the first hit, white where rays escape
+#[derive(Clone, Copy, Debug)]
+pub struct AmbientOcclusionIntegrator {
+    pub occlusion: AmbientOcclusion,
+}
+
+impl AmbientOcclusionIntegrator {
+    pub fn new(samples: u32, max_distance: f64) -> Self {
+        AmbientOcclusionIntegrator {
+            occlusion: AmbientOcclusion::new(samples, max_distance),
+        }
+    }
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+    fn sample(&self, world: &World, ray: Ray, _rng: &mut Rng) -> (Color, bool) {
+        match world.first_hit(ray) {
+            Some(comps) => {
+                let unoccluded = self.occlusion.unoccluded_fraction(
+                    world,
+                    comps.over_point,
+                    comps.normalv,
+                    comps.time,
+                );
+                (Color::white() * unoccluded, true)
+            }
+            None => (Color::white(), false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::Color;
+    use crate::integrators::{
+        AlbedoIntegrator, AmbientOcclusionIntegrator, DepthIntegrator, Integrator,
+        NormalIntegrator, ObjectIdIntegrator, WhittedIntegrator,
+    };
+    use crate::ray::Ray;
+    use crate::rng::Rng;
+    use crate::shape::{Shape, ShapeType};
+    use crate::test_utils::assert_color_near;
+    use crate::transform::translate;
+    use crate::tuple::{point, vector};
+    use crate::world::World;
+
+    fn hitting_ray() -> Ray {
+        Ray::new(point(0., 0., -5.), vector(0., 0., 1.))
+    }
+
+    fn missing_ray() -> Ray {
+        Ray::new(point(0., 0., -5.), vector(0., 1., 0.))
+    }
+
+    #[test]
+    fn whitted_integrator_matches_world_color_at() {
+        let w = World::default();
+        let c = WhittedIntegrator::default().color_at(&w, hitting_ray(), &mut Rng::new(0));
+        assert_color_near(Color::new(0.38066, 0.47583, 0.2855), c, 0.0001);
+    }
+
+    #[test]
+    fn whitted_integrator_with_zero_depth_ignores_refraction() {
+        let mut w = World::default();
+        w.shapes[0].material.transparency = 1.;
+        w.shapes[0].material.refractive_index = 1.5;
+        let deep = WhittedIntegrator::new(5).color_at(&w, hitting_ray(), &mut Rng::new(0));
+        let flat = WhittedIntegrator::new(0).color_at(&w, hitting_ray(), &mut Rng::new(0));
+        assert_ne!(deep, flat);
+    }
+
+    #[test]
+    fn normal_integrator_maps_normal_to_color() {
+        let w = World::default();
+        let c = NormalIntegrator.color_at(&w, hitting_ray(), &mut Rng::new(0));
+        assert_color_near(Color::new(0.5, 0.5, 0.), c, 0.00001);
+    }
+
+    #[test]
+    fn depth_integrator_fades_with_distance() {
+        let w = World::default();
+        let c = DepthIntegrator::new(8.).color_at(&w, hitting_ray(), &mut Rng::new(0));
+        assert_color_near(Color::new(0.5, 0.5, 0.5), c, 0.00001);
+        let far = DepthIntegrator::new(2.).color_at(&w, hitting_ray(), &mut Rng::new(0));
+        assert_eq!(Color::black(), far);
+    }
+
+    #[test]
+    fn albedo_integrator_returns_unlit_material_color() {
+        let w = World::default();
+        let c = AlbedoIntegrator.color_at(&w, hitting_ray(), &mut Rng::new(0));
+        assert_eq!(Color::new(0.8, 1.0, 0.6), c);
+    }
+
+    #[test]
+    fn object_id_integrator_colors_by_shape_index() {
+        let w = World::default();
+        let c = ObjectIdIntegrator.color_at(&w, hitting_ray(), &mut Rng::new(0));
+        assert_eq!(ObjectIdIntegrator::id_color(0), c);
+        assert_ne!(
+            ObjectIdIntegrator::id_color(0),
+            ObjectIdIntegrator::id_color(1)
+        );
+    }
+
+    #[test]
+    fn ambient_occlusion_integrator_is_grayscale() {
+        let w = World::default();
+        let ao = AmbientOcclusionIntegrator::new(16, 10.);
+        let c = ao.color_at(&w, hitting_ray(), &mut Rng::new(0));
+        assert_eq!(Color::white(), c);
+        assert_eq!(
+            Color::white(),
+            ao.color_at(&w, missing_ray(), &mut Rng::new(0))
+        );
+    }
+
+    #[test]
+    fn ambient_occlusion_integrator_darkens_crevices() {
+        let mut w = World::new();
+        w.shapes.push(Shape::new(ShapeType::Plane));
+        let mut ball = Shape::new(ShapeType::Sphere);
+        ball.transform = translate(0., 1., 0.);
+        w.shapes.push(ball);
+        let ao = AmbientOcclusionIntegrator::new(64, 10.);
+        let r = Ray::new(point(0.5, 1., -5.), vector(0., -1., 5.).normalize());
+        let c = ao.color_at(&w, r, &mut Rng::new(0));
+        assert_eq!(c.r, c.g);
+        assert_eq!(c.g, c.b);
+        assert!(c.r < 1.);
+    }
+
+    #[test]
+    fn debug_integrators_are_black_on_miss() {
+        let w = World::default();
+        let mut rng = Rng::new(0);
+        assert_eq!(
+            Color::black(),
+            NormalIntegrator.color_at(&w, missing_ray(), &mut rng)
+        );
+        assert_eq!(
+            Color::black(),
+            DepthIntegrator::new(10.).color_at(&w, missing_ray(), &mut rng)
+        );
+        assert_eq!(
+            Color::black(),
+            AlbedoIntegrator.color_at(&w, missing_ray(), &mut rng)
+        );
+        assert_eq!(
+            Color::black(),
+            ObjectIdIntegrator.color_at(&w, missing_ray(), &mut rng)
+        );
+    }
+}
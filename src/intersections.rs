@@ -1,13 +1,21 @@
 use crate::shape::Shape;
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub struct Intersection<'a> {
     pub t: f64,
     pub shape: &'a Shape,
 }
 
+// Same distance on the same shape, told apart from identical copies, even
+// ones that share an id, by address
+impl PartialEq for Intersection<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.t == other.t && std::ptr::eq(self.shape, other.shape)
+    }
+}
+
 impl Intersection<'_> {
-    pub fn new(t: f64, shape: &Shape) -> Intersection {
+    pub fn new(t: f64, shape: &Shape) -> Intersection<'_> {
         Intersection { t, shape }
     }
 }
@@ -16,7 +24,7 @@ pub fn hit(intersections: Vec<Intersection>) -> Option<Intersection> {
     let min_intersection = intersections
         .iter()
         .filter(|i| i.t >= 0.0)
-        .min_by(|x, y| x.t.partial_cmp(&y.t).unwrap());
+        .min_by(|x, y| x.t.total_cmp(&y.t));
 
     min_intersection.cloned()
 }
@@ -83,6 +91,13 @@ mod tests {
         assert_eq!(i4, hit(xs).unwrap());
     }
 
+    #[test]
+    fn hit_skips_nan_intersections() {
+        let s = Shape::new(ShapeType::Sphere);
+        let xs = vec![Intersection::new(f64::NAN, &s), Intersection::new(2.0, &s)];
+        assert_eq!(2.0, hit(xs).unwrap().t);
+    }
+
     macro_rules! find_n1_n2_test {
         ($($name:ident: $value:expr,)*) => {
             $(
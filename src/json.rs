@@ -0,0 +1,424 @@
+// Minimal JSON reader and writer. Numbers are f64 and are written in their
+// shortest round-trip form, so values survive a write and read unchanged.
+use crate::scene::{SceneError, MAX_NESTING};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+// A value and the line (1-based) it starts on, 0 for values built in code
+#[derive(Clone, Debug, PartialEq)]
+pub struct Json {
+    pub value: JsonValue,
+    pub line: usize,
+}
+
+impl Json {
+    pub fn new(value: JsonValue) -> Self {
+        Json { value, line: 0 }
+    }
+
+    pub fn number(n: f64) -> Self {
+        Json::new(JsonValue::Number(n))
+    }
+
+    pub fn string(s: &str) -> Self {
+        Json::new(JsonValue::String(s.to_string()))
+    }
+
+    pub fn array(items: Vec<Json>) -> Self {
+        Json::new(JsonValue::Array(items))
+    }
+
+    pub fn object(entries: Vec<(&str, Json)>) -> Self {
+        Json::new(JsonValue::Object(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        ))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match &self.value {
+            JsonValue::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    // Indented with two spaces. Arrays holding only numbers, strings, booleans
+    // and nulls are kept on one line. Fails on infinities and NaN, which JSON
+    // has no representation for.
+    pub fn to_string_pretty(&self) -> Result<String, SceneError> {
+        let mut out = String::new();
+        self.write(&mut out, 0)?;
+        out.push('\n');
+        Ok(out)
+    }
+
+    fn write(&self, out: &mut String, indent: usize) -> Result<(), SceneError> {
+        let pad = |out: &mut String, n: usize| out.extend(std::iter::repeat_n(' ', n));
+        match &self.value {
+            JsonValue::Null => out.push_str("null"),
+            JsonValue::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            JsonValue::Number(n) if n.is_finite() => out.push_str(&format!("{:?}", n)),
+            JsonValue::Number(n) => {
+                return Err(SceneError::new(
+                    0,
+                    &format!("{} cannot be written as a JSON number", n),
+                ))
+            }
+            JsonValue::String(s) => write_string(out, s),
+            JsonValue::Array(items) if items.is_empty() => out.push_str("[]"),
+            JsonValue::Array(items) => {
+                let flat = items
+                    .iter()
+                    .all(|i| !matches!(i.value, JsonValue::Array(_) | JsonValue::Object(_)));
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    if flat {
+                        if i > 0 {
+                            out.push(' ');
+                        }
+                    } else {
+                        out.push('\n');
+                        pad(out, indent + 2);
+                    }
+                    item.write(out, indent + 2)?;
+                }
+                if !flat {
+                    out.push('\n');
+                    pad(out, indent);
+                }
+                out.push(']');
+            }
+            JsonValue::Object(entries) if entries.is_empty() => out.push_str("{}"),
+            JsonValue::Object(entries) => {
+                out.push('{');
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push('\n');
+                    pad(out, indent + 2);
+                    write_string(out, key);
+                    out.push_str(": ");
+                    value.write(out, indent + 2).map_err(|e| e.or_key(key))?;
+                }
+                out.push('\n');
+                pad(out, indent);
+                out.push('}');
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+pub fn parse(source: &str) -> Result<Json, SceneError> {
+    let mut parser = Parser {
+        chars: source.chars().collect(),
+        pos: 0,
+        line: 1,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("Unexpected data after the JSON value"));
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    // Arrays and objects around the current value
+    depth: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> SceneError {
+        SceneError::new(self.line, message)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.chars.get(self.pos) {
+            if !c.is_whitespace() {
+                break;
+            }
+            if c == '\n' {
+                self.line += 1;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), SceneError> {
+        self.skip_whitespace();
+        if self.chars.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", c)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, SceneError> {
+        self.skip_whitespace();
+        let line = self.line;
+        let value = match self.chars.get(self.pos) {
+            Some('{') => {
+                self.open()?;
+                let mut entries: Vec<(String, Json)> = Vec::new();
+                if !self.close('}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key_line = self.line;
+                        let key = self.string()?;
+                        if entries.iter().any(|(k, _)| *k == key) {
+                            return Err(SceneError::with_key(key_line, &key, "Duplicate key"));
+                        }
+                        self.expect(':')?;
+                        let value = self.value().map_err(|e| e.or_key(&key))?;
+                        entries.push((key, value));
+                        if self.close('}') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                self.depth -= 1;
+                JsonValue::Object(entries)
+            }
+            Some('[') => {
+                self.open()?;
+                let mut items = Vec::new();
+                if !self.close(']') {
+                    loop {
+                        items.push(self.value()?);
+                        if self.close(']') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                self.depth -= 1;
+                JsonValue::Array(items)
+            }
+            Some('"') => JsonValue::String(self.string()?),
+            Some(c) if *c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while let Some(c) = self.chars.get(self.pos) {
+                    if c.is_ascii_digit() || "+-.eE".contains(*c) {
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                JsonValue::Number(
+                    text.parse()
+                        .map_err(|_| self.error(&format!("Invalid number '{}'", text)))?,
+                )
+            }
+            Some(_) => {
+                let start = self.pos;
+                while self.pos < self.chars.len() && self.chars[self.pos].is_ascii_alphabetic() {
+                    self.pos += 1;
+                }
+                let word: String = self.chars[start..self.pos].iter().collect();
+                match word.as_str() {
+                    "null" => JsonValue::Null,
+                    "true" => JsonValue::Bool(true),
+                    "false" => JsonValue::Bool(false),
+                    _ => return Err(self.error("Expected a JSON value")),
+                }
+            }
+            None => return Err(self.error("Unexpected end of JSON data")),
+        };
+        Ok(Json { value, line })
+    }
+
+    // Consumes the opening brace or bracket of an object or array. Errors end
+    // the parse, so the depth is only restored after a complete value.
+    fn open(&mut self) -> Result<(), SceneError> {
+        if self.depth >= MAX_NESTING {
+            return Err(self.error("JSON is nested too deeply"));
+        }
+        self.depth += 1;
+        self.pos += 1;
+        Ok(())
+    }
+
+    // Consumes `c` if it is the next non-whitespace character
+    fn close(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.chars.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn string(&mut self) -> Result<String, SceneError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let c = match self.chars.get(self.pos) {
+                Some(c) => *c,
+                None => return Err(self.error("Unterminated string")),
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\n' => return Err(self.error("Unterminated string")),
+                '\\' => {
+                    let escape = self.chars.get(self.pos).copied();
+                    self.pos += 1;
+                    s.push(match escape {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let hex: String = self
+                                .chars
+                                .get(self.pos..self.pos + 4)
+                                .map(|h| h.iter().collect())
+                                .unwrap_or_default();
+                            self.pos += 4;
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(std::char::from_u32)
+                                .ok_or_else(|| self.error("Invalid unicode escape"))?
+                        }
+                        _ => return Err(self.error("Invalid escape sequence")),
+                    });
+                }
+                c => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json::{parse, Json, JsonValue};
+
+    #[test]
+    fn writing_pretty_json() {
+        let json = Json::object(vec![
+            ("name", Json::string("a \"b\"\n")),
+            (
+                "values",
+                Json::array(vec![Json::number(1.), Json::number(0.1)]),
+            ),
+            (
+                "nested",
+                Json::array(vec![Json::object(vec![("x", Json::new(JsonValue::Null))])]),
+            ),
+            ("empty", Json::array(vec![])),
+        ]);
+        assert_eq!(
+            "{\n  \"name\": \"a \\\"b\\\"\\n\",\n  \"values\": [1.0, 0.1],\n  \"nested\": [\n    {\n      \"x\": null\n    }\n  ],\n  \"empty\": []\n}\n",
+            json.to_string_pretty().unwrap()
+        );
+    }
+
+    #[test]
+    fn numbers_round_trip_exactly() {
+        for n in &[0.1, 1. / 3., -2.5e-300, 1e21, std::f64::consts::PI] {
+            let text = Json::number(*n).to_string_pretty().unwrap();
+            assert_eq!(JsonValue::Number(*n), parse(&text).unwrap().value);
+        }
+    }
+
+    #[test]
+    fn non_finite_numbers_cannot_be_written() {
+        for n in &[f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            let json = Json::object(vec![("a", Json::array(vec![Json::number(*n)]))]);
+            let e = json.to_string_pretty().unwrap_err();
+            assert_eq!(Some("a".to_string()), e.key);
+        }
+    }
+
+    #[test]
+    fn parsing_json() {
+        let json =
+            parse("{\"a\": [1, -2.5e2, true, null],\n \"b\": {\"c\": \"\\u00e9\\t\"}}").unwrap();
+        assert_eq!(
+            JsonValue::Array(vec![
+                Json {
+                    value: JsonValue::Number(1.),
+                    line: 1
+                },
+                Json {
+                    value: JsonValue::Number(-250.),
+                    line: 1
+                },
+                Json {
+                    value: JsonValue::Bool(true),
+                    line: 1
+                },
+                Json {
+                    value: JsonValue::Null,
+                    line: 1
+                },
+            ]),
+            json.get("a").unwrap().value
+        );
+        let c = json.get("b").unwrap().get("c").unwrap();
+        assert_eq!(JsonValue::String("é\t".to_string()), c.value);
+        assert_eq!(2, c.line);
+    }
+
+    #[test]
+    fn parse_errors_report_line_and_key() {
+        let e = parse("{\n\"a\": 1,\n\"b\": [1, 2,,]\n}").unwrap_err();
+        assert_eq!(3, e.line);
+        assert_eq!(Some("b".to_string()), e.key);
+        assert!(parse("{\"a\": 1, \"a\": 2}").is_err());
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("[1] 2").is_err());
+        assert!(parse("nope").is_err());
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        assert!(parse(&"[".repeat(100_000)).is_err());
+        assert!(parse(&"{\"a\": ".repeat(100_000)).is_err());
+        let nested = format!("{}{}", "[".repeat(100), "]".repeat(100));
+        assert!(parse(&nested).is_ok());
+    }
+}
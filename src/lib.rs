@@ -1,15 +1,34 @@
+pub mod animation;
 pub mod camera;
 pub mod canvas;
 pub mod color;
+pub mod compare;
+pub mod error;
+pub mod hdr;
+pub mod integrators;
 pub mod intersections;
+pub mod json;
 pub mod lights;
 pub mod materials;
 pub mod matrix;
+pub mod occlusion;
+pub mod passes;
+pub mod pathtracer;
 pub mod patterns;
+pub mod png;
+pub mod ppm;
+pub mod progress;
+pub mod quaternion;
 pub mod ray;
+pub mod rng;
+pub mod scene;
 pub mod shape;
+pub mod stats;
+pub mod tonemap;
 pub mod transform;
 pub mod tuple;
+pub mod validation;
 pub mod world;
+pub mod yaml;
 
 mod test_utils;
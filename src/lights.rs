@@ -1,14 +1,14 @@
 use crate::color::Color;
-use crate::tuple::Tuple;
+use crate::tuple::Point3;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct PointLight {
     pub intensity: Color,
-    pub position: Tuple,
+    pub position: Point3,
 }
 
 impl PointLight {
-    pub fn new(intensity: Color, position: Tuple) -> Self {
+    pub fn new(intensity: Color, position: Point3) -> Self {
         PointLight {
             intensity,
             position,
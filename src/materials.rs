@@ -2,7 +2,7 @@ use crate::color::Color;
 use crate::lights::PointLight;
 use crate::patterns::{Pattern, PatternTrait};
 use crate::shape::Shape;
-use crate::tuple::Tuple;
+use crate::tuple::{Normal3, Point3, Vector3};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Material {
@@ -32,20 +32,23 @@ impl Material {
         }
     }
 
+    pub fn color_at(&self, object: &Shape, point: Point3) -> Color {
+        match self.pattern {
+            Some(pattern) => pattern.color_at_object(object, point),
+            None => self.color,
+        }
+    }
+
     pub fn lighting(
         material: Material,
         object: &Shape,
         light: PointLight,
-        point: Tuple,
-        eyev: Tuple,
-        normalv: Tuple,
+        point: Point3,
+        eyev: Vector3,
+        normalv: Normal3,
         in_shadow: bool,
     ) -> Color {
-        let color = match material.pattern {
-            Some(pattern) => pattern.color_at_object(object, point),
-            None => material.color,
-        };
-        let effective_color = color * light.intensity;
+        let effective_color = material.color_at(object, point) * light.intensity;
         let lightv = (light.position - point).normalize();
         let ambient = effective_color * material.ambient;
 
@@ -84,7 +87,7 @@ mod tests {
     use crate::patterns::{Pattern, StripedPattern};
     use crate::shape::{Shape, ShapeType};
     use crate::test_utils::assert_color_near;
-    use crate::tuple::{point, vector};
+    use crate::tuple::{point, vector, Normal3};
 
     #[test]
     fn defaut_material() {
@@ -104,7 +107,7 @@ mod tests {
         let m = Material::new();
         let position = point(0., 0., 0.);
         let eyev = vector(0., 0., -1.);
-        let normalv = vector(0., 0., -1.);
+        let normalv = Normal3::new(0., 0., -1.);
         let light = PointLight::new(Color::new(1., 1., 1.), point(0., 0., -10.));
         let object = Shape::new(ShapeType::Sphere);
         let result = Material::lighting(m, &object, light, position, eyev, normalv, false);
@@ -116,7 +119,7 @@ mod tests {
         let m = Material::new();
         let position = point(0., 0., 0.);
         let eyev = vector(0., 2_f64.sqrt() / 2., 2_f64.sqrt() / 2.);
-        let normalv = vector(0., 0., -1.);
+        let normalv = Normal3::new(0., 0., -1.);
         let light = PointLight::new(Color::new(1., 1., 1.), point(0., 0., -10.));
         let object = Shape::new(ShapeType::Sphere);
         let result = Material::lighting(m, &object, light, position, eyev, normalv, false);
@@ -128,7 +131,7 @@ mod tests {
         let m = Material::new();
         let position = point(0., 0., 0.);
         let eyev = vector(0., 0., -1.);
-        let normalv = vector(0., 0., -1.);
+        let normalv = Normal3::new(0., 0., -1.);
         let light = PointLight::new(Color::new(1., 1., 1.), point(0., 10., -10.));
         let object = Shape::new(ShapeType::Sphere);
         let result = Material::lighting(m, &object, light, position, eyev, normalv, false);
@@ -140,7 +143,7 @@ mod tests {
         let m = Material::new();
         let position = point(0., 0., 0.);
         let eyev = vector(0., -2_f64.sqrt() / 2., -2_f64.sqrt() / 2.);
-        let normalv = vector(0., 0., -1.);
+        let normalv = Normal3::new(0., 0., -1.);
         let light = PointLight::new(Color::new(1., 1., 1.), point(0., 10., -10.));
         let object = Shape::new(ShapeType::Sphere);
         let result = Material::lighting(m, &object, light, position, eyev, normalv, false);
@@ -152,7 +155,7 @@ mod tests {
         let m = Material::new();
         let position = point(0., 0., 0.);
         let eyev = vector(0., 0., -1.);
-        let normalv = vector(0., 0., -1.);
+        let normalv = Normal3::new(0., 0., -1.);
         let light = PointLight::new(Color::new(1., 1., 1.), point(0., 0., 10.));
         let object = Shape::new(ShapeType::Sphere);
         let result = Material::lighting(m, &object, light, position, eyev, normalv, false);
@@ -164,7 +167,7 @@ mod tests {
         let m = Material::new();
         let position = point(0., 0., 0.);
         let eyev = vector(0., 0., -1.);
-        let normalv = vector(0., 0., -1.);
+        let normalv = Normal3::new(0., 0., -1.);
         let light = PointLight::new(Color::new(1., 1., 1.), point(0., 0., -10.));
         let object = Shape::new(ShapeType::Sphere);
         let result = Material::lighting(m, &object, light, position, eyev, normalv, true);
@@ -183,7 +186,7 @@ mod tests {
         m.specular = 0.;
 
         let eyev = vector(0., 0., -1.);
-        let normalv = vector(0., 0., -1.);
+        let normalv = Normal3::new(0., 0., -1.);
         let light = PointLight::new(Color::new(1., 1., 1.), point(0., 0., -10.));
         let object = Shape::new(ShapeType::Sphere);
         let c1 = Material::lighting(m, &object, light, point(0.9, 0., 0.), eyev, normalv, false);
@@ -191,4 +194,19 @@ mod tests {
         assert_eq!(Color::white(), c1);
         assert_eq!(Color::black(), c2);
     }
+
+    #[test]
+    fn color_at_uses_pattern_when_present() {
+        let mut m = Material::new();
+        m.color = Color::new(0.5, 0.5, 0.5);
+        let object = Shape::new(ShapeType::Sphere);
+        assert_eq!(m.color, m.color_at(&object, point(1.1, 0., 0.)));
+
+        m.pattern = Some(Pattern::Stripe(StripedPattern::new(
+            Color::white(),
+            Color::black(),
+        )));
+        assert_eq!(Color::white(), m.color_at(&object, point(0.9, 0., 0.)));
+        assert_eq!(Color::black(), m.color_at(&object, point(1.1, 0., 0.)));
+    }
 }
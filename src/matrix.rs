@@ -1,3 +1,4 @@
+use crate::error::Error;
 use std::ops::{Index, IndexMut, Mul};
 
 macro_rules! define_square_matrix_struct {
@@ -88,6 +89,7 @@ macro_rules! define_square_matrix_struct {
     };
 }
 
+#[cfg(test)]
 macro_rules! impl_sub_matrix {
     ($parent:ident, $child:ident) => {
         impl $parent {
@@ -110,7 +112,8 @@ macro_rules! impl_sub_matrix {
     };
 }
 
-macro_rules! impl_determinant {
+#[cfg(test)]
+macro_rules! impl_cofactor {
     ($parent:ident) => {
         impl $parent {
             fn minor(&self, row: usize, col: usize) -> f64 {
@@ -125,14 +128,6 @@ macro_rules! impl_determinant {
                     -minor
                 }
             }
-
-            fn determinant(&self) -> f64 {
-                let mut res = 0.0;
-                for i in 0..Self::order() {
-                    res += (self[0][i] * self.cofactor(0, i));
-                }
-                res
-            }
         }
     };
 }
@@ -140,43 +135,185 @@ macro_rules! impl_determinant {
 define_square_matrix_struct!(Mat2x2, 2);
 define_square_matrix_struct!(Mat3x3, 3);
 define_square_matrix_struct!(Mat4x4, 4);
+
+// Cofactor expansion is the textbook route to the inverse but far too slow
+// for per-ray use; it is kept to check the closed-form versions against.
+#[cfg(test)]
 impl_sub_matrix!(Mat4x4, Mat3x3);
+#[cfg(test)]
 impl_sub_matrix!(Mat3x3, Mat2x2);
-impl_determinant!(Mat3x3);
-impl_determinant!(Mat4x4);
+#[cfg(test)]
+impl_cofactor!(Mat3x3);
+#[cfg(test)]
+impl_cofactor!(Mat4x4);
 
+#[cfg(test)]
 impl Mat2x2 {
     fn determinant(&self) -> f64 {
         self[0][0] * self[1][1] - self[0][1] * self[1][0]
     }
 }
 
+#[cfg(test)]
+impl Mat3x3 {
+    fn determinant(&self) -> f64 {
+        (0..Self::order())
+            .map(|i| self[0][i] * self.cofactor(0, i))
+            .sum()
+    }
+}
+
+// A determinant this small relative to the matrix's own scale means the
+// rows are (numerically) linearly dependent.
+const SINGULAR_TOLERANCE: f64 = 1e-12;
+
+// The determinant is a signed volume and is at most the product of the row
+// lengths, so comparing against that product works the same for a scene
+// modelled in millimetres as for one in kilometres.
+fn is_singular(det: f64, rows: &[&[f64]]) -> bool {
+    let bound: f64 = rows
+        .iter()
+        .map(|r| r.iter().map(|v| v * v).sum::<f64>().sqrt())
+        .product();
+    !det.is_finite() || det.abs() <= SINGULAR_TOLERANCE * bound
+}
+
 impl Mat4x4 {
-    fn invertible(&self) -> bool {
-        self.determinant().abs() > std::f64::EPSILON
-    }
-
-    pub fn inverse(&self) -> Result<Self, &str> {
-        if self.invertible() {
-            let mut res = Mat4x4::zero();
-            let det = self.determinant();
-            for r in 0..Self::order() {
-                for c in 0..Self::order() {
-                    let cof = self.cofactor(r, c);
-                    res[c][r] = cof / det;
-                }
-            }
-            Ok(res)
+    // 2x2 determinants of the top two rows (s) and bottom two rows (c),
+    // shared by the determinant and every cofactor.
+    fn sub_determinants(&self) -> ([f64; 6], [f64; 6]) {
+        let a = &self.data;
+        let s = [
+            a[0][0] * a[1][1] - a[1][0] * a[0][1],
+            a[0][0] * a[1][2] - a[1][0] * a[0][2],
+            a[0][0] * a[1][3] - a[1][0] * a[0][3],
+            a[0][1] * a[1][2] - a[1][1] * a[0][2],
+            a[0][1] * a[1][3] - a[1][1] * a[0][3],
+            a[0][2] * a[1][3] - a[1][2] * a[0][3],
+        ];
+        let c = [
+            a[2][0] * a[3][1] - a[3][0] * a[2][1],
+            a[2][0] * a[3][2] - a[3][0] * a[2][2],
+            a[2][0] * a[3][3] - a[3][0] * a[2][3],
+            a[2][1] * a[3][2] - a[3][1] * a[2][2],
+            a[2][1] * a[3][3] - a[3][1] * a[2][3],
+            a[2][2] * a[3][3] - a[3][2] * a[2][3],
+        ];
+        (s, c)
+    }
+
+    pub fn determinant(&self) -> f64 {
+        let (s, c) = self.sub_determinants();
+        s[0] * c[5] - s[1] * c[4] + s[2] * c[3] + s[3] * c[2] - s[4] * c[1] + s[5] * c[0]
+    }
+
+    pub fn invertible(&self) -> bool {
+        if self.is_affine() {
+            !is_singular(self.linear_determinant(), &self.linear_rows())
         } else {
-            Err("Matrix is not invertible")
+            let rows: Vec<&[f64]> = self.data.iter().map(|r| &r[..]).collect();
+            !is_singular(self.determinant(), &rows)
+        }
+    }
+
+    // Whether the bottom row is (0, 0, 0, 1), as for every transform built
+    // from translations, rotations, scales and skews.
+    pub fn is_affine(&self) -> bool {
+        self.data[3] == [0., 0., 0., 1.]
+    }
+
+    pub fn inverse(&self) -> Result<Self, Error> {
+        if self.is_affine() {
+            return self.affine_inverse();
+        }
+        let rows: Vec<&[f64]> = self.data.iter().map(|r| &r[..]).collect();
+        let det = self.determinant();
+        if is_singular(det, &rows) {
+            return Err(Error::NonInvertibleMatrix);
+        }
+        let a = &self.data;
+        let (s, c) = self.sub_determinants();
+        Ok(Mat4x4::new([
+            [
+                (a[1][1] * c[5] - a[1][2] * c[4] + a[1][3] * c[3]) / det,
+                (-a[0][1] * c[5] + a[0][2] * c[4] - a[0][3] * c[3]) / det,
+                (a[3][1] * s[5] - a[3][2] * s[4] + a[3][3] * s[3]) / det,
+                (-a[2][1] * s[5] + a[2][2] * s[4] - a[2][3] * s[3]) / det,
+            ],
+            [
+                (-a[1][0] * c[5] + a[1][2] * c[2] - a[1][3] * c[1]) / det,
+                (a[0][0] * c[5] - a[0][2] * c[2] + a[0][3] * c[1]) / det,
+                (-a[3][0] * s[5] + a[3][2] * s[2] - a[3][3] * s[1]) / det,
+                (a[2][0] * s[5] - a[2][2] * s[2] + a[2][3] * s[1]) / det,
+            ],
+            [
+                (a[1][0] * c[4] - a[1][1] * c[2] + a[1][3] * c[0]) / det,
+                (-a[0][0] * c[4] + a[0][1] * c[2] - a[0][3] * c[0]) / det,
+                (a[3][0] * s[4] - a[3][1] * s[2] + a[3][3] * s[0]) / det,
+                (-a[2][0] * s[4] + a[2][1] * s[2] - a[2][3] * s[0]) / det,
+            ],
+            [
+                (-a[1][0] * c[3] + a[1][1] * c[1] - a[1][2] * c[0]) / det,
+                (a[0][0] * c[3] - a[0][1] * c[1] + a[0][2] * c[0]) / det,
+                (-a[3][0] * s[3] + a[3][1] * s[1] - a[3][2] * s[0]) / det,
+                (a[2][0] * s[3] - a[2][1] * s[1] + a[2][2] * s[0]) / det,
+            ],
+        ]))
+    }
+
+    fn linear_rows(&self) -> [&[f64]; 3] {
+        [&self.data[0][..3], &self.data[1][..3], &self.data[2][..3]]
+    }
+
+    // Determinant of the upper-left 3x3 block
+    fn linear_determinant(&self) -> f64 {
+        let a = &self.data;
+        a[0][0] * (a[1][1] * a[2][2] - a[1][2] * a[2][1])
+            - a[0][1] * (a[1][0] * a[2][2] - a[1][2] * a[2][0])
+            + a[0][2] * (a[1][0] * a[2][1] - a[1][1] * a[2][0])
+    }
+
+    // For [A t; 0 1] the inverse is [A^-1 -A^-1 t; 0 1], so only the 3x3
+    // block needs inverting.
+    fn affine_inverse(&self) -> Result<Self, Error> {
+        let a = &self.data;
+        let det = self.linear_determinant();
+        if is_singular(det, &self.linear_rows()) {
+            return Err(Error::NonInvertibleMatrix);
+        }
+        let mut res = Mat4x4::identity();
+        res[0][0] = (a[1][1] * a[2][2] - a[1][2] * a[2][1]) / det;
+        res[0][1] = (a[0][2] * a[2][1] - a[0][1] * a[2][2]) / det;
+        res[0][2] = (a[0][1] * a[1][2] - a[0][2] * a[1][1]) / det;
+        res[1][0] = (a[1][2] * a[2][0] - a[1][0] * a[2][2]) / det;
+        res[1][1] = (a[0][0] * a[2][2] - a[0][2] * a[2][0]) / det;
+        res[1][2] = (a[0][2] * a[1][0] - a[0][0] * a[1][2]) / det;
+        res[2][0] = (a[1][0] * a[2][1] - a[1][1] * a[2][0]) / det;
+        res[2][1] = (a[0][1] * a[2][0] - a[0][0] * a[2][1]) / det;
+        res[2][2] = (a[0][0] * a[1][1] - a[0][1] * a[1][0]) / det;
+        for r in 0..3 {
+            res[r][3] = -(0..3).map(|c| res[r][c] * a[c][3]).sum::<f64>();
+        }
+        Ok(res)
+    }
+
+    pub fn lerp(&self, other: &Self, t: f64) -> Self {
+        let mut res = Mat4x4::zero();
+        for r in 0..Self::order() {
+            for c in 0..Self::order() {
+                res[r][c] = self[r][c] + (other[r][c] - self[r][c]) * t;
+            }
         }
+        res
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::error::Error;
     use crate::matrix::{Mat2x2, Mat3x3, Mat4x4};
     use crate::test_utils::assert_mat4x4_near;
+    use crate::transform;
     use crate::tuple::Tuple;
 
     #[test]
@@ -393,7 +530,7 @@ mod tests {
             [9., 1., 7., -6.],
         ]);
         assert_eq!(-2120., a.determinant());
-        assert_eq!(true, a.invertible());
+        assert!(a.invertible());
     }
 
     #[test]
@@ -405,7 +542,7 @@ mod tests {
             [0., 0., 0., 0.],
         ]);
         assert_eq!(0., a.determinant());
-        assert_eq!(false, a.invertible());
+        assert!(!a.invertible());
     }
 
     #[test]
@@ -474,10 +611,7 @@ mod tests {
             [0., -5., 1., -5.],
             [0., 0., 0., 0.],
         ]);
-        match a.inverse() {
-            Err(s) => assert_eq!("Matrix is not invertible", s),
-            _ => assert!(false),
-        }
+        assert!(matches!(a.inverse(), Err(Error::NonInvertibleMatrix)));
     }
 
     #[test]
@@ -498,4 +632,81 @@ mod tests {
         let d = c * b.inverse().unwrap();
         assert_eq!(a, d);
     }
+
+    #[test]
+    fn closed_form_inverse_matches_cofactors() {
+        let a = Mat4x4::new([
+            [2., -1., 0.5, 3.],
+            [0.25, 4., -2., 1.],
+            [-3., 1.5, 1., -0.5],
+            [1., 2., -1., 0.75],
+        ]);
+        let det = a.determinant();
+        assert!((det - (0..4).map(|i| a[0][i] * a.cofactor(0, i)).sum::<f64>()).abs() < 1e-9);
+        let b = a.inverse().unwrap();
+        for r in 0..4 {
+            for c in 0..4 {
+                assert!((b[c][r] - a.cofactor(r, c) / det).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn affine_inverse_matches_general_inverse() {
+        let a = Mat4x4::new([
+            [0., -2., 0.5, 3.],
+            [1.5, 0., 1., -4.],
+            [0.25, 1., 3., 2.],
+            [0., 0., 0., 1.],
+        ]);
+        assert!(a.is_affine());
+        let b = a.inverse().unwrap();
+        let det = a.determinant();
+        for r in 0..4 {
+            for c in 0..4 {
+                assert!((b[c][r] - a.cofactor(r, c) / det).abs() < 1e-12);
+            }
+        }
+        assert_eq!(Mat4x4::identity(), a * a.inverse().unwrap());
+    }
+
+    #[test]
+    fn invertibility_is_relative_to_scale() {
+        let tiny = transform::scale(1e-6, 1e-6, 1e-6);
+        assert!(tiny.invertible());
+        assert_eq!(Mat4x4::identity(), tiny * tiny.inverse().unwrap());
+
+        let mut projective = tiny;
+        projective[3] = [0., 0., 1e-6, 1e-6];
+        assert!(projective.invertible());
+
+        let flat = Mat4x4::new([
+            [1e6, 0., 0., 0.],
+            [0., 1e6, 0., 0.],
+            [1e6, 1e6, 1e-8, 0.],
+            [0., 0., 0., 1.],
+        ]);
+        assert!(!flat.invertible());
+        assert!(matches!(flat.inverse(), Err(Error::NonInvertibleMatrix)));
+    }
+
+    #[test]
+    fn lerp_between_two_matrices() {
+        let a = Mat4x4::identity();
+        let b = Mat4x4::new([
+            [3., 0., 0., 4.],
+            [0., 3., 0., -2.],
+            [0., 0., 3., 0.],
+            [0., 0., 0., 1.],
+        ]);
+        let expected = Mat4x4::new([
+            [1.5, 0., 0., 1.],
+            [0., 1.5, 0., -0.5],
+            [0., 0., 1.5, 0.],
+            [0., 0., 0., 1.],
+        ]);
+        assert_eq!(a, a.lerp(&b, 0.));
+        assert_eq!(b, a.lerp(&b, 1.));
+        assert_eq!(expected, a.lerp(&b, 0.25));
+    }
 }
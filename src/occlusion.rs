@@ -0,0 +1,108 @@
+use crate::intersections::hit;
+use crate::pathtracer::cosine_sample_hemisphere;
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::stats::{self, RayKind};
+use crate::tuple::{Normal3, Point3};
+use crate::world::World;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmbientOcclusion {
+    pub samples: u32,
+    // Occluders further away than this do not darken the point
+    pub max_distance: f64,
+}
+
+impl AmbientOcclusion {
+    pub fn new(samples: u32, max_distance: f64) -> Self {
+        AmbientOcclusion {
+            samples,
+            max_distance,
+        }
+    }
+
+    // Fraction of cosine-distributed rays from `p` around `normal` that escape
+    // within `max_distance`. Seeded from the point so renders are repeatable.
+    pub fn unoccluded_fraction(&self, world: &World, p: Point3, normal: Normal3, time: f64) -> f64 {
+        if self.samples == 0 {
+            return 1.;
+        }
+        let mut rng = Rng::new(point_seed(p));
+        let mut unoccluded = 0;
+        for _ in 0..self.samples {
+            let direction = cosine_sample_hemisphere(normal, &mut rng);
+            let ray = Ray::with_time(p, direction, time);
+            stats::count_ray(RayKind::Occlusion, 0);
+            match hit(world.intersect(ray)) {
+                Some(i) if i.t < self.max_distance => {}
+                _ => unoccluded += 1,
+            }
+        }
+        f64::from(unoccluded) / f64::from(self.samples)
+    }
+}
+
+fn point_seed(p: Point3) -> u64 {
+    p.x.to_bits() ^ p.y.to_bits().rotate_left(21) ^ p.z.to_bits().rotate_left(42)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::occlusion::AmbientOcclusion;
+    use crate::shape::{Shape, ShapeType};
+    use crate::test_utils::assert_f64_near;
+    use crate::transform::translate;
+    use crate::tuple::{point, Normal3};
+    use crate::world::World;
+
+    #[test]
+    fn open_point_is_unoccluded() {
+        let mut w = World::new();
+        w.shapes.push(Shape::new(ShapeType::Plane));
+        let ao = AmbientOcclusion::new(32, 10.);
+        let f = ao.unoccluded_fraction(&w, point(0., 0.0001, 0.), Normal3::new(0., 1., 0.), 0.);
+        assert_eq!(1., f);
+    }
+
+    #[test]
+    fn point_under_a_low_ceiling_is_occluded() {
+        let mut w = World::new();
+        w.shapes.push(Shape::new(ShapeType::Plane));
+        let mut ceiling = Shape::new(ShapeType::Plane);
+        ceiling.transform = translate(0., 1., 0.);
+        w.shapes.push(ceiling);
+        let ao = AmbientOcclusion::new(32, 10.);
+        let f = ao.unoccluded_fraction(&w, point(0., 0.0001, 0.), Normal3::new(0., 1., 0.), 0.);
+        assert_eq!(0., f);
+    }
+
+    #[test]
+    fn occluders_beyond_max_distance_are_ignored() {
+        let mut w = World::new();
+        let mut ceiling = Shape::new(ShapeType::Plane);
+        ceiling.transform = translate(0., 1., 0.);
+        w.shapes.push(ceiling);
+        let ao = AmbientOcclusion::new(32, 0.5);
+        let f = ao.unoccluded_fraction(&w, point(0., 0., 0.), Normal3::new(0., 1., 0.), 0.);
+        assert_eq!(1., f);
+    }
+
+    #[test]
+    fn point_next_to_a_sphere_is_partially_occluded() {
+        let mut w = World::new();
+        let mut ball = Shape::new(ShapeType::Sphere);
+        ball.transform = translate(0., 1., 1.2);
+        w.shapes.push(ball);
+        let ao = AmbientOcclusion::new(256, 10.);
+        let f = ao.unoccluded_fraction(&w, point(0., 0., 0.), Normal3::new(0., 1., 0.), 0.);
+        assert!(f > 0.2 && f < 0.9);
+    }
+
+    #[test]
+    fn zero_samples_is_unoccluded() {
+        let w = World::default();
+        let ao = AmbientOcclusion::new(0, 10.);
+        let f = ao.unoccluded_fraction(&w, point(0., 0., -1.), Normal3::new(0., 0., -1.), 0.);
+        assert_f64_near(1., f, 0.00001);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::error::Error;
+use crate::hdr;
+use crate::integrators::ObjectIdIntegrator;
+use crate::ppm;
+use crate::tuple::{vector, Vector3};
+use std::fs::File;
+use std::io;
+
+// Per-pixel buffer of arbitrary data, row-major
+#[derive(Clone, Debug, PartialEq)]
+pub struct Buffer<T> {
+    width: usize,
+    height: usize,
+    data: Vec<T>,
+}
+
+impl<T: Clone> Buffer<T> {
+    pub fn new(width: usize, height: usize, value: T) -> Self {
+        Buffer {
+            width,
+            height,
+            data: vec![value; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // x: column, y: row
+    pub fn get(&self, x: usize, y: usize) -> T {
+        self.data[y * self.width + x].clone()
+    }
+
+    // x: column, y: row
+    pub fn set(&mut self, x: usize, y: usize, value: T) {
+        self.data[y * self.width + x] = value
+    }
+
+    pub fn to_canvas<F: Fn(&T) -> Color>(&self, f: F) -> Canvas {
+        let mut canvas = Canvas::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                canvas.set_pixel(x, y, f(&self.data[y * self.width + x]));
+            }
+        }
+        canvas
+    }
+}
+
+// Beauty image plus the arbitrary output variables of the primary hit of
+// every pixel, for compositing.
+pub struct RenderPasses {
+    pub color: Canvas,
+    // Distance from the camera to the hit, infinite where the ray misses
+    pub depth: Buffer<f64>,
+    // World space normal facing the camera, zero where the ray misses
+    pub normal: Buffer<Vector3>,
+    // Unlit material or pattern color
+    pub albedo: Canvas,
+    // Index into `World::shapes` of the hit shape
+    pub shape_index: Buffer<Option<usize>>,
+}
+
+impl RenderPasses {
+    pub fn new(width: usize, height: usize) -> Self {
+        RenderPasses {
+            color: Canvas::new(width, height),
+            depth: Buffer::new(width, height, f64::INFINITY),
+            normal: Buffer::new(width, height, vector(0., 0., 0.)),
+            albedo: Canvas::new(width, height),
+            shape_index: Buffer::new(width, height, None),
+        }
+    }
+
+    // Grayscale depth, white at the camera and black at `max_distance` or beyond
+    pub fn depth_canvas(&self, max_distance: f64) -> Canvas {
+        self.depth
+            .to_canvas(|d| Color::white() * (1. - d / max_distance).clamp(0., 1.))
+    }
+
+    // Normal mapped from [-1, 1] to [0, 1]
+    pub fn normal_canvas(&self) -> Canvas {
+        self.normal.to_canvas(|n| {
+            if n.magnitude() == 0. {
+                Color::black()
+            } else {
+                Color::new(n.x * 0.5 + 0.5, n.y * 0.5 + 0.5, n.z * 0.5 + 0.5)
+            }
+        })
+    }
+
+    pub fn shape_index_canvas(&self) -> Canvas {
+        self.shape_index
+            .to_canvas(|i| i.map_or(Color::black(), ObjectIdIntegrator::id_color))
+    }
+
+    // Writes the passes for compositing, unclamped and unencoded:
+    // `<prefix>_color.pfm`, `_albedo.pfm` and `_normal.pfm` as color float
+    // maps, `_depth.pfm` as a grayscale float map with infinity for misses,
+    // and `_id.pgm` as a 16-bit graymap holding the shape index plus one, 0
+    // for misses.
+    pub fn write(&self, prefix: &str) -> Result<(), Error> {
+        self.color.write_pfm(format!("{}_color.pfm", prefix))?;
+        self.albedo.write_pfm(format!("{}_albedo.pfm", prefix))?;
+        let (width, height) = (self.normal.width, self.normal.height);
+        hdr::write_float_map(
+            File::create(format!("{}_normal.pfm", prefix))?,
+            width,
+            height,
+            3,
+            |x, y| {
+                let n = self.normal.get(x, y);
+                [n.x, n.y, n.z]
+            },
+        )?;
+        hdr::write_float_map(
+            File::create(format!("{}_depth.pfm", prefix))?,
+            width,
+            height,
+            1,
+            |x, y| [self.depth.get(x, y); 3],
+        )?;
+        let ids = self
+            .shape_index
+            .data
+            .iter()
+            .map(|i| match i {
+                None => Ok(0),
+                Some(i) if *i < usize::from(u16::MAX) => Ok(*i as u16 + 1),
+                Some(i) => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Shape index {} does not fit the 16-bit id pass", i),
+                )),
+            })
+            .collect::<io::Result<Vec<u16>>>()?;
+        let file = File::create(format!("{}_id.pgm", prefix))?;
+        Ok(ppm::write_p5_16(width, height, &ids, file)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::canvas::Canvas;
+    use crate::color::Color;
+    use crate::integrators::ObjectIdIntegrator;
+    use crate::passes::{Buffer, RenderPasses};
+    use crate::tuple::vector;
+
+    #[test]
+    fn buffer_stores_values_per_pixel() {
+        let mut b = Buffer::new(4, 3, 0.);
+        b.set(3, 2, 5.);
+        assert_eq!(4, b.width());
+        assert_eq!(3, b.height());
+        assert_eq!(5., b.get(3, 2));
+        assert_eq!(0., b.get(2, 2));
+    }
+
+    #[test]
+    fn new_passes_are_empty() {
+        let p = RenderPasses::new(2, 2);
+        assert_eq!(f64::INFINITY, p.depth.get(1, 1));
+        assert_eq!(None, p.shape_index.get(1, 1));
+        assert_eq!(Color::black(), p.albedo.get_pixel(1, 1));
+    }
+
+    #[test]
+    fn passes_convert_to_canvases() {
+        let mut p = RenderPasses::new(2, 1);
+        p.depth.set(0, 0, 5.);
+        p.normal.set(0, 0, vector(0., 1., 0.));
+        p.shape_index.set(0, 0, Some(3));
+
+        assert_eq!(
+            Color::new(0.5, 0.5, 0.5),
+            p.depth_canvas(10.).get_pixel(0, 0)
+        );
+        assert_eq!(Color::black(), p.depth_canvas(10.).get_pixel(1, 0));
+        assert_eq!(Color::new(0.5, 1., 0.5), p.normal_canvas().get_pixel(0, 0));
+        assert_eq!(Color::black(), p.normal_canvas().get_pixel(1, 0));
+        assert_eq!(
+            ObjectIdIntegrator::id_color(3),
+            p.shape_index_canvas().get_pixel(0, 0)
+        );
+    }
+
+    #[test]
+    fn passes_are_written_without_losing_range() {
+        let mut p = RenderPasses::new(2, 1);
+        p.color.set_pixel(0, 0, Color::new(2.5, 0.5, 0.));
+        p.depth.set(0, 0, 250.);
+        p.normal.set(0, 0, vector(-0.6, 0., -0.8));
+        p.albedo.set_pixel(1, 0, Color::new(0.25, 0.5, 1.));
+        p.shape_index.set(0, 0, Some(300));
+        let prefix = std::env::temp_dir().join("rust_raytracer_passes_test");
+        let prefix = prefix.to_str().unwrap();
+        p.write(prefix).unwrap();
+
+        let read = |pass: &str| Canvas::read(format!("{}_{}", prefix, pass)).unwrap();
+        let color = read("color.pfm");
+        let depth = read("depth.pfm");
+        let normal = read("normal.pfm");
+        let albedo = read("albedo.pfm");
+        let ids = read("id.pgm");
+        for pass in &[
+            "color.pfm",
+            "depth.pfm",
+            "normal.pfm",
+            "albedo.pfm",
+            "id.pgm",
+        ] {
+            std::fs::remove_file(format!("{}_{}", prefix, pass)).unwrap();
+        }
+
+        assert_eq!(Color::new(2.5, 0.5, 0.), color.get_pixel(0, 0));
+        assert_eq!(250., depth.get_pixel(0, 0).r);
+        assert_eq!(f64::INFINITY, depth.get_pixel(1, 0).r);
+        let n = normal.get_pixel(0, 0);
+        assert_eq!(
+            (-0.6f32, 0f32, -0.8f32),
+            (n.r as f32, n.g as f32, n.b as f32)
+        );
+        assert_eq!(Color::new(0.25, 0.5, 1.), albedo.get_pixel(1, 0));
+        assert_eq!(301., (ids.get_pixel(0, 0).r * 65535.).round());
+        assert_eq!(0., ids.get_pixel(1, 0).r);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::camera::Camera;
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::integrators::Integrator;
+use crate::materials::Material;
+use crate::ray::Ray;
+use crate::rng::Rng;
+use crate::stats::{self, RayKind};
+use crate::tuple::{vector, Normal3, Point3, Vector3};
+use crate::world::{Comps, World};
+
+// Unidirectional path tracer with cosine-weighted diffuse bounces and
+// next-event estimation toward the world light.
+//
+// The point light is treated the same way as in `Material::lighting`, i.e.
+// without distance falloff, so scenes keep the brightness they have with the
+// Whitted renderer. The constant ambient term is replaced by the indirect
+// light gathered by the diffuse bounces.
+#[derive(Clone, Copy, Debug)]
+pub struct PathTracer {
+    // Samples per pixel for each progressive pass
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    // Bounces before Russian roulette may terminate a path
+    pub russian_roulette_depth: u32,
+    // Changes the noise pattern, both here and in `Camera::render_with`
+    pub seed: u64,
+}
+
+impl PathTracer {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        PathTracer {
+            samples_per_pixel,
+            max_depth: 16,
+            russian_roulette_depth: 3,
+            seed: 0,
+        }
+    }
+
+    pub fn trace(&self, world: &World, ray: Ray, rng: &mut Rng) -> Color {
+        self.trace_path(world, ray, rng).0
+    }
+
+    // The path's color and whether its first ray hit a shape
+    fn trace_path(&self, world: &World, ray: Ray, rng: &mut Rng) -> (Color, bool) {
+        let mut hit = false;
+        let mut color = Color::black();
+        let mut throughput = Color::white();
+        let mut ray = ray;
+
+        for depth in 0..self.max_depth {
+            let comps = match world.first_hit(ray) {
+                Some(comps) => comps,
+                None => break,
+            };
+            hit = true;
+            let shape = comps.shape.at_time(comps.time);
+            let material = shape.material;
+
+            let mut direct_material = material;
+            direct_material.ambient = 0.;
+            let shadowed = world.is_shadowed(comps.over_point, comps.time);
+            let direct = Material::lighting(
+                direct_material,
+                &shape,
+                world.light,
+                comps.over_point,
+                comps.eyev,
+                comps.normalv,
+                shadowed,
+            );
+            color = color + throughput * direct;
+
+            let albedo = material.color_at(&shape, comps.over_point) * material.diffuse;
+            let (direction, origin, weight, kind) = match sample_lobe(&comps, albedo, rng) {
+                Some(lobe) => lobe,
+                None => break,
+            };
+            throughput = throughput * weight;
+
+            if depth + 1 >= self.russian_roulette_depth {
+                let survival = max_component(throughput).min(0.95);
+                if survival <= 0. || rng.next_f64() >= survival {
+                    break;
+                }
+                throughput = throughput * (1. / survival);
+            }
+
+            stats::count_ray(kind, depth + 1);
+            ray = Ray::with_time(origin, direction, comps.time);
+        }
+
+        (color, hit)
+    }
+
+    pub fn render(&self, camera: &Camera, world: &World) -> Canvas {
+        self.render_progressive(camera, world, 1, |_, _| {})
+    }
+
+    // Renders `passes` passes of `samples_per_pixel` samples each. After every
+    // pass `on_pass` receives the pass count and the running average. Every
+    // pass is a `Camera::render_with` render with its own seed.
+    pub fn render_progressive<F>(
+        &self,
+        camera: &Camera,
+        world: &World,
+        passes: u32,
+        mut on_pass: F,
+    ) -> Canvas
+    where
+        F: FnMut(u32, &Canvas),
+    {
+        let width = camera.hsize as usize;
+        let height = camera.vsize as usize;
+        let mut sum = vec![(Color::black(), 0.); width * height];
+        let mut image = Canvas::new(width, height);
+
+        for pass in 0..passes {
+            let tracer = PathTracer {
+                seed: self.seed ^ (u64::from(pass) << 48),
+                ..*self
+            };
+            let part = camera.render_with(world, &tracer);
+            let scale = 1. / f64::from(pass + 1);
+            for y in 0..height {
+                for x in 0..width {
+                    let (color, alpha) = &mut sum[y * width + x];
+                    *color = *color + part.get_pixel(x, y);
+                    *alpha += part.get_alpha(x, y);
+                    image.set_pixel(x, y, *color * scale);
+                    image.set_alpha(x, y, *alpha * scale);
+                }
+            }
+            on_pass(pass + 1, &image);
+        }
+
+        image
+    }
+}
+
+impl Integrator for PathTracer {
+    fn sample(&self, world: &World, ray: Ray, rng: &mut Rng) -> (Color, bool) {
+        self.trace_path(world, ray, rng)
+    }
+
+    fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl Default for PathTracer {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+// Picks one of the diffuse, reflected and refracted lobes with probability
+// proportional to its weight and returns the new direction, ray origin,
+// throughput weight and the kind of ray.
+fn sample_lobe(
+    comps: &Comps,
+    albedo: Color,
+    rng: &mut Rng,
+) -> Option<(Vector3, Point3, Color, RayKind)> {
+    let material = comps.shape.material;
+    let (mut reflect_weight, mut transmit_weight) = (material.reflective, material.transparency);
+    if material.reflective > 0. && material.transparency > 0. {
+        let reflectance = World::schlick(comps);
+        reflect_weight = reflectance;
+        transmit_weight = 1. - reflectance;
+    }
+    let diffuse_weight = max_component(albedo);
+
+    let total = diffuse_weight + reflect_weight + transmit_weight;
+    if total <= 0. {
+        return None;
+    }
+
+    let u = rng.next_f64() * total;
+    if u < diffuse_weight {
+        let direction = cosine_sample_hemisphere(comps.normalv, rng);
+        Some((
+            direction,
+            comps.over_point,
+            albedo * (total / diffuse_weight),
+            RayKind::Diffuse,
+        ))
+    } else if u < diffuse_weight + reflect_weight {
+        Some((
+            comps.reflectv,
+            comps.over_point,
+            Color::white() * total,
+            RayKind::Reflection,
+        ))
+    } else {
+        let weight = Color::white() * total;
+        match refract(comps) {
+            Some(direction) => Some((direction, comps.under_point, weight, RayKind::Refraction)),
+            // Total internal reflection
+            None => Some((
+                comps.reflectv,
+                comps.over_point,
+                weight,
+                RayKind::Reflection,
+            )),
+        }
+    }
+}
+
+fn refract(comps: &Comps) -> Option<Vector3> {
+    let n_ratio = comps.n1 / comps.n2;
+    let cos_i = comps.eyev.dot(comps.normalv);
+    let sin2_t = n_ratio.powi(2) * (1. - cos_i.powi(2));
+    if sin2_t > 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some((comps.normalv * (n_ratio * cos_i - cos_t) - comps.eyev * n_ratio).normalize())
+}
+
+pub fn cosine_sample_hemisphere(normal: Normal3, rng: &mut Rng) -> Vector3 {
+    let r1 = rng.next_f64();
+    let r2 = rng.next_f64();
+    let phi = 2. * std::f64::consts::PI * r1;
+    let r = r2.sqrt();
+    let (x, y, z) = (r * phi.cos(), r * phi.sin(), (1. - r2).sqrt());
+
+    let normal = normal.to_vector();
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    (tangent * x + bitangent * y + normal * z).normalize()
+}
+
+fn orthonormal_basis(n: Vector3) -> (Vector3, Vector3) {
+    let helper = if n.x.abs() > 0.9 {
+        vector(0., 1., 0.)
+    } else {
+        vector(1., 0., 0.)
+    };
+    let tangent = helper.cross(n).normalize();
+    let bitangent = n.cross(tangent);
+    (tangent, bitangent)
+}
+
+fn max_component(c: Color) -> f64 {
+    c.r.max(c.g).max(c.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::{view_transform, Camera};
+    use crate::color::Color;
+    use crate::pathtracer::{cosine_sample_hemisphere, PathTracer};
+    use crate::ray::Ray;
+    use crate::rng::Rng;
+    use crate::shape::{Shape, ShapeType};
+    use crate::test_utils::{assert_color_near, assert_f64_near};
+    use crate::transform::translate;
+    use crate::tuple::{point, vector, Normal3};
+    use crate::world::World;
+
+    #[test]
+    fn cosine_samples_lie_in_the_hemisphere_of_the_normal() {
+        let mut rng = Rng::new(7);
+        let n = Normal3::new(0., 1., 0.);
+        let mut mean_cos = 0.;
+        for _ in 0..10000 {
+            let d = cosine_sample_hemisphere(n, &mut rng);
+            assert_f64_near(1., d.magnitude(), 0.00001);
+            assert!(d.dot(n) >= 0.);
+            mean_cos += d.dot(n);
+        }
+        // E[cos] for a cosine-weighted hemisphere is 2/3
+        assert_f64_near(2. / 3., mean_cos / 10000., 0.01);
+    }
+
+    #[test]
+    fn ray_that_misses_is_black() {
+        let w = World::default();
+        let tracer = PathTracer::new(1);
+        let r = Ray::new(point(0., 0., -5.), vector(0., 1., 0.));
+        assert_eq!(Color::black(), tracer.trace(&w, r, &mut Rng::new(0)));
+    }
+
+    #[test]
+    fn tracing_is_deterministic_for_a_seed() {
+        let w = World::default();
+        let tracer = PathTracer::new(1);
+        let r = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
+        let c1 = tracer.trace(&w, r, &mut Rng::new(3));
+        let c2 = tracer.trace(&w, r, &mut Rng::new(3));
+        assert_eq!(c1, c2);
+    }
+
+    #[test]
+    fn convex_shape_gets_direct_light_without_ambient() {
+        let mut w = World::default();
+        w.shapes.truncate(1);
+        let tracer = PathTracer::new(1);
+        let r = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
+        let c = tracer.trace(&w, r, &mut Rng::new(0));
+        assert_color_near(Color::new(0.30066, 0.37583, 0.2255), c, 0.0001);
+    }
+
+    #[test]
+    fn indirect_light_brightens_a_surface_facing_away_from_the_light() {
+        let mut w = World::new();
+        w.light.position = point(0., 10., 0.);
+        let mut floor = Shape::new(ShapeType::Plane);
+        floor.material.specular = 0.;
+        w.shapes.push(floor);
+        let mut ceiling = Shape::new(ShapeType::Plane);
+        ceiling.transform = translate(0., 2., 0.);
+        ceiling.material.specular = 0.;
+        w.shapes.push(ceiling);
+
+        let tracer = PathTracer::new(1);
+        let r = Ray::new(point(0., 1., 0.), vector(0., 1., 0.));
+        let mut rng = Rng::new(11);
+        let mut sum = Color::black();
+        for _ in 0..200 {
+            sum = sum + tracer.trace(&w, r, &mut rng);
+        }
+        // The underside of the ceiling is lit only by the floor bouncing light back up
+        assert!(sum.r / 200. > 0.1);
+    }
+
+    #[test]
+    fn seed_changes_the_noise_of_camera_renders() {
+        let w = World::default();
+        let mut c = Camera::new(5, 5, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.), point(0., 0., 0.), vector(0., 1., 0.));
+        let mut tracer = PathTracer::new(2);
+        let first = c.render_with(&w, &tracer);
+        assert_eq!(
+            first.get_pixel(2, 2),
+            c.render_with(&w, &tracer).get_pixel(2, 2)
+        );
+        tracer.seed = 1;
+        assert_ne!(
+            first.get_pixel(2, 2),
+            c.render_with(&w, &tracer).get_pixel(2, 2)
+        );
+    }
+
+    #[test]
+    fn progressive_render_reports_every_pass() {
+        let w = World::default();
+        let mut c = Camera::new(5, 5, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.), point(0., 0., 0.), vector(0., 1., 0.));
+        let tracer = PathTracer::new(2);
+        let mut reported = vec![];
+        let image = tracer.render_progressive(&c, &w, 3, |pass, _| reported.push(pass));
+        assert_eq!(vec![1, 2, 3], reported);
+        assert!(image.get_pixel(2, 2).g > 0.);
+        assert_eq!(Color::black(), image.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn progressive_passes_render_like_the_camera() {
+        let w = World::default();
+        let mut c = Camera::new(5, 5, std::f64::consts::PI / 2.);
+        c.transform = view_transform(point(0., 0., -5.), point(0., 0., 0.), vector(0., 1., 0.));
+        c.transparent_background = true;
+        let tracer = PathTracer::new(2);
+        let image = tracer.render(&c, &w);
+        let camera_image = c.render_with(&w, &tracer);
+        for (x, y, color) in camera_image.pixels() {
+            assert_eq!(color, image.get_pixel(x, y));
+        }
+        assert_eq!(0., image.get_alpha(0, 0));
+        assert_eq!(1., image.get_alpha(2, 2));
+    }
+}
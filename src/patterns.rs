@@ -1,10 +1,22 @@
 use crate::color::Color;
 use crate::matrix::Mat4x4;
 use crate::shape::Shape;
-use crate::tuple::Tuple;
+use crate::tuple::Point3;
 
 pub trait PatternTrait {
-    fn color_at_object(&self, shape: &Shape, world_point: Tuple) -> Color;
+    fn color_at_object(&self, shape: &Shape, world_point: Point3) -> Color;
+}
+
+// Moves a world point into object and then pattern space. A transform that
+// cannot be inverted is skipped; `Shape::validate` reports those.
+fn pattern_point(shape: &Shape, transform: &Mat4x4, world_point: Point3) -> Point3 {
+    let object_point = shape
+        .transform
+        .inverse()
+        .map_or(world_point, |inv| inv * world_point);
+    transform
+        .inverse()
+        .map_or(object_point, |inv| inv * object_point)
 }
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -23,7 +35,7 @@ impl StripedPattern {
         }
     }
 
-    pub fn color_at(&self, point: Tuple) -> Color {
+    pub fn color_at(&self, point: Point3) -> Color {
         if point.x.floor() % 2. == 0. {
             self.a
         } else {
@@ -33,10 +45,8 @@ impl StripedPattern {
 }
 
 impl PatternTrait for StripedPattern {
-    fn color_at_object(&self, shape: &Shape, world_point: Tuple) -> Color {
-        let object_point = shape.transform.inverse().unwrap() * world_point;
-        let pattern_point = self.transform.inverse().unwrap() * object_point;
-        self.color_at(pattern_point)
+    fn color_at_object(&self, shape: &Shape, world_point: Point3) -> Color {
+        self.color_at(pattern_point(shape, &self.transform, world_point))
     }
 }
 
@@ -56,7 +66,7 @@ impl GradientPattern {
         }
     }
 
-    pub fn color_at(&self, point: Tuple) -> Color {
+    pub fn color_at(&self, point: Point3) -> Color {
         let distance = self.b - self.a;
         let fraction = point.x - point.x.floor();
         self.a + distance * fraction
@@ -64,10 +74,8 @@ impl GradientPattern {
 }
 
 impl PatternTrait for GradientPattern {
-    fn color_at_object(&self, shape: &Shape, world_point: Tuple) -> Color {
-        let object_point = shape.transform.inverse().unwrap() * world_point;
-        let pattern_point = self.transform.inverse().unwrap() * object_point;
-        self.color_at(pattern_point)
+    fn color_at_object(&self, shape: &Shape, world_point: Point3) -> Color {
+        self.color_at(pattern_point(shape, &self.transform, world_point))
     }
 }
 
@@ -87,7 +95,7 @@ impl RingPattern {
         }
     }
 
-    pub fn color_at(&self, point: Tuple) -> Color {
+    pub fn color_at(&self, point: Point3) -> Color {
         let fac = (point.x * point.x + point.z * point.z).sqrt();
         if fac.floor() % 2. == 0. {
             self.a
@@ -98,10 +106,8 @@ impl RingPattern {
 }
 
 impl PatternTrait for RingPattern {
-    fn color_at_object(&self, shape: &Shape, world_point: Tuple) -> Color {
-        let object_point = shape.transform.inverse().unwrap() * world_point;
-        let pattern_point = self.transform.inverse().unwrap() * object_point;
-        self.color_at(pattern_point)
+    fn color_at_object(&self, shape: &Shape, world_point: Point3) -> Color {
+        self.color_at(pattern_point(shape, &self.transform, world_point))
     }
 }
 
@@ -121,7 +127,7 @@ impl CheckerPattern {
         }
     }
 
-    pub fn color_at(&self, point: Tuple) -> Color {
+    pub fn color_at(&self, point: Point3) -> Color {
         let fac = point.x.floor() + point.y.floor() + point.z.floor();
         if fac % 2. == 0. {
             self.a
@@ -132,10 +138,8 @@ impl CheckerPattern {
 }
 
 impl PatternTrait for CheckerPattern {
-    fn color_at_object(&self, shape: &Shape, world_point: Tuple) -> Color {
-        let object_point = shape.transform.inverse().unwrap() * world_point;
-        let pattern_point = self.transform.inverse().unwrap() * object_point;
-        self.color_at(pattern_point)
+    fn color_at_object(&self, shape: &Shape, world_point: Point3) -> Color {
+        self.color_at(pattern_point(shape, &self.transform, world_point))
     }
 }
 
@@ -147,8 +151,19 @@ pub enum Pattern {
     Checker(CheckerPattern),
 }
 
+impl Pattern {
+    pub fn transform(&self) -> Mat4x4 {
+        match self {
+            Pattern::Stripe(s) => s.transform,
+            Pattern::Gradient(g) => g.transform,
+            Pattern::Ring(r) => r.transform,
+            Pattern::Checker(c) => c.transform,
+        }
+    }
+}
+
 impl PatternTrait for Pattern {
-    fn color_at_object(&self, shape: &Shape, world_point: Tuple) -> Color {
+    fn color_at_object(&self, shape: &Shape, world_point: Point3) -> Color {
         match self {
             Pattern::Stripe(s) => s.color_at_object(shape, world_point),
             Pattern::Gradient(g) => g.color_at_object(shape, world_point),
@@ -0,0 +1,513 @@
+use crate::canvas::Canvas;
+use crate::color::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+pub fn encode(canvas: &Canvas, depth: BitDepth) -> Vec<u8> {
+    let mut out = SIGNATURE.to_vec();
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(canvas.width() as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(canvas.height() as u32).to_be_bytes());
+    ihdr.push(match depth {
+        BitDepth::Eight => 8,
+        BitDepth::Sixteen => 16,
+    });
+    // Truecolor, with alpha if any pixel is transparent, deflate, adaptive
+    // filtering, no interlace
+    let alpha = canvas.has_transparency();
+    let color_type = if alpha { 6 } else { 2 };
+    ihdr.extend_from_slice(&[color_type, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &ihdr);
+
+    write_chunk(
+        &mut out,
+        b"IDAT",
+        &zlib_compress(&filtered_scanlines(canvas, depth, alpha)),
+    );
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Appends the samples of row `y` to `row`, with an alpha channel if `alpha`
+fn raw_scanline(canvas: &Canvas, y: usize, depth: BitDepth, alpha: bool, row: &mut Vec<u8>) {
+    let output = canvas.output_transform();
+    let mut push = |v: f64| match depth {
+        BitDepth::Eight => row.push(canvas.convert(v)),
+        BitDepth::Sixteen => row.extend_from_slice(&canvas.convert16(v).to_be_bytes()),
+    };
+    for (c, a) in canvas.row(y).iter().zip(canvas.alpha_row(y)) {
+        if alpha {
+            // PNG stores straight, not premultiplied, alpha
+            let c = if *a > 0. {
+                *c * (1. / a)
+            } else {
+                Color::black()
+            };
+            let p = output.apply(c);
+            for v in &[p.r, p.g, p.b, *a] {
+                push(*v);
+            }
+        } else {
+            let p = output.apply(*c);
+            for v in &[p.r, p.g, p.b] {
+                push(*v);
+            }
+        }
+    }
+}
+
+// Picks the filter per row with the smallest sum of absolute values, the
+// heuristic suggested by the PNG specification.
+fn filtered_scanlines(canvas: &Canvas, depth: BitDepth, alpha: bool) -> Vec<u8> {
+    let channels = if alpha { 4 } else { 3 };
+    let bpp = match depth {
+        BitDepth::Eight => channels,
+        BitDepth::Sixteen => channels * 2,
+    };
+    let mut out = Vec::new();
+    let mut prev = vec![0; canvas.width() * bpp];
+    for y in 0..canvas.height() {
+        let mut row = Vec::with_capacity(canvas.width() * bpp);
+        raw_scanline(canvas, y, depth, alpha, &mut row);
+        let best = (0..5)
+            .map(|filter| (filter, filter_row(filter, &row, &prev, bpp)))
+            .min_by_key(|(_, f)| {
+                f.iter()
+                    .map(|&b| (b as i8).unsigned_abs() as u64)
+                    .sum::<u64>()
+            })
+            .unwrap();
+        out.push(best.0);
+        out.extend_from_slice(&best.1);
+        prev = row;
+    }
+    out
+}
+
+fn filter_row(filter: u8, row: &[u8], prev: &[u8], bpp: usize) -> Vec<u8> {
+    (0..row.len())
+        .map(|i| {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = prev[i];
+            let c = if i >= bpp { prev[i - bpp] } else { 0 };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                _ => paeth(a, b, c),
+            };
+            row[i].wrapping_sub(predictor)
+        })
+        .collect()
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window, default compression level
+    let mut out = vec![0x78, 0x9c];
+    out.extend_from_slice(&deflate_fixed(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            out: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    // Writes `count` bits of `value`, least significant bit first
+    fn write(&mut self, value: u32, count: u32) {
+        self.acc |= value << self.bits;
+        self.bits += count;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    // Huffman codes are packed starting from their most significant bit
+    fn write_code(&mut self, code: u32, len: u32) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.write(reversed, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+const MAX_CHAIN: usize = 64;
+
+fn write_literal(w: &mut BitWriter, lit: u16) {
+    let lit = lit as u32;
+    match lit {
+        0..=143 => w.write_code(0x30 + lit, 8),
+        144..=255 => w.write_code(0x190 + lit - 144, 9),
+        256..=279 => w.write_code(lit - 256, 7),
+        _ => w.write_code(0xc0 + lit - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, length: usize, distance: usize) {
+    let li = LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .unwrap();
+    write_literal(w, 257 + li as u16);
+    w.write(
+        (length - LENGTH_BASE[li] as usize) as u32,
+        LENGTH_EXTRA[li] as u32,
+    );
+
+    let di = DIST_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap();
+    w.write_code(di as u32, 5);
+    w.write(
+        (distance - DIST_BASE[di] as usize) as u32,
+        DIST_EXTRA[di] as u32,
+    );
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+    (v.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn insert_hash(data: &[u8], i: usize, head: &mut [usize], prev: &mut [usize]) {
+    if i + MIN_MATCH <= data.len() {
+        let h = hash(data, i);
+        prev[i] = head[h];
+        head[h] = i;
+    }
+}
+
+// A single final block with the fixed Huffman codes and LZ77 matches found
+// through hash chains.
+fn deflate_fixed(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter::new();
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman)
+    w.write(1, 1);
+    w.write(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(data, i)];
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = (0..max_len)
+                    .take_while(|&k| data[candidate + k] == data[i + k])
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(&mut w, best_len, best_dist);
+            for k in i..i + best_len {
+                insert_hash(data, k, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            write_literal(&mut w, data[i] as u16);
+            insert_hash(data, i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+
+    write_literal(&mut w, 256);
+    w.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::canvas::Canvas;
+    use crate::color::Color;
+    use crate::png::{adler32, crc32, encode, zlib_compress, BitDepth};
+    use crate::tonemap::OutputTransform;
+
+    // Minimal inflate for fixed Huffman blocks, enough to check the encoder
+    fn inflate_fixed(data: &[u8]) -> Vec<u8> {
+        let mut pos = 0;
+        let mut bit = |n: u32| {
+            let mut v = 0;
+            for k in 0..n {
+                let b = (data[pos / 8] >> (pos % 8)) & 1;
+                v |= (b as u32) << k;
+                pos += 1;
+            }
+            v
+        };
+        assert_eq!(1, bit(1));
+        assert_eq!(1, bit(2));
+
+        let length_base = [
+            3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99,
+            115, 131, 163, 195, 227, 258,
+        ];
+        let length_extra = [
+            0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+        ];
+        let dist_base = [
+            1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025,
+            1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+        ];
+        let dist_extra = [
+            0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12,
+            12, 13, 13,
+        ];
+
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            // Read the code MSB first and decode by the fixed code ranges
+            let mut code = 0;
+            let mut len = 0;
+            let sym = loop {
+                code = (code << 1) | bit(1);
+                len += 1;
+                match len {
+                    7 if code <= 0b0010111 => break code + 256,
+                    8 if (0x30..=0xbf).contains(&code) => break code - 0x30,
+                    8 if (0xc0..=0xc7).contains(&code) => break code - 0xc0 + 280,
+                    9 if (0x190..=0x1ff).contains(&code) => break code - 0x190 + 144,
+                    _ => {}
+                }
+            };
+            if sym < 256 {
+                out.push(sym as u8);
+            } else if sym == 256 {
+                break;
+            } else {
+                let li = (sym - 257) as usize;
+                let length = length_base[li] + bit(length_extra[li]) as usize;
+                let mut dcode = 0;
+                for _ in 0..5 {
+                    dcode = (dcode << 1) | bit(1);
+                }
+                let di = dcode as usize;
+                let distance = dist_base[di] + bit(dist_extra[di]) as usize;
+                for _ in 0..length {
+                    out.push(out[out.len() - distance]);
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn crc32_of_check_string() {
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+        assert_eq!(0, crc32(b""));
+    }
+
+    #[test]
+    fn adler32_of_known_string() {
+        assert_eq!(0x11e6_0398, adler32(b"Wikipedia"));
+        assert_eq!(1, adler32(b""));
+    }
+
+    #[test]
+    fn zlib_stream_round_trips() {
+        let mut data = b"a raytracer traces rays, a raytracer traces rays".to_vec();
+        data.extend((0..2000).map(|i| (i % 7) as u8));
+        data.extend(vec![0; 1000]);
+        let z = zlib_compress(&data);
+        assert_eq!([0x78, 0x9c], z[0..2]);
+        assert_eq!(0, (z[0] as u16 * 256 + z[1] as u16) % 31);
+        assert!(z.len() < data.len() / 4);
+        let inflated = inflate_fixed(&z[2..z.len() - 4]);
+        assert_eq!(data, inflated);
+        assert_eq!(&adler32(&data).to_be_bytes(), &z[z.len() - 4..]);
+    }
+
+    #[test]
+    fn png_starts_with_signature_and_header() {
+        let canvas = Canvas::new(5, 3);
+        let png = encode(&canvas, BitDepth::Eight);
+        assert_eq!([137, 80, 78, 71, 13, 10, 26, 10], png[0..8]);
+        assert_eq!(b"IHDR", &png[12..16]);
+        assert_eq!([0, 0, 0, 5, 0, 0, 0, 3, 8, 2, 0, 0, 0], png[16..29]);
+        assert_eq!(&crc32(&png[12..29]).to_be_bytes(), &png[29..33]);
+        assert_eq!(b"IEND", &png[png.len() - 8..png.len() - 4]);
+    }
+
+    // Inflates and unfilters the image data of an encoded canvas
+    fn decode_rows(png: &[u8], height: usize, row_len: usize) -> Vec<Vec<u8>> {
+        let idat_len = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        assert_eq!(b"IDAT", &png[37..41]);
+        let z = &png[41..41 + idat_len];
+        let raw = inflate_fixed(&z[2..z.len() - 4]);
+        let bpp = match png[24] {
+            8 => [0, 0, 3, 0, 0, 0, 4][png[25] as usize],
+            _ => [0, 0, 6, 0, 0, 0, 8][png[25] as usize],
+        };
+        let mut rows: Vec<Vec<u8>> = Vec::new();
+        let mut prev = vec![0; row_len];
+        for y in 0..height {
+            let start = y * (row_len + 1);
+            let filter = raw[start];
+            let row = &raw[start + 1..start + 1 + row_len];
+            let mut out: Vec<u8> = vec![0; row_len];
+            for i in 0..row_len {
+                let a = if i >= bpp { out[i - bpp] } else { 0 };
+                let b = prev[i];
+                let c = if i >= bpp { prev[i - bpp] } else { 0 };
+                let pred = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => super::paeth(a, b, c),
+                };
+                out[i] = row[i].wrapping_add(pred);
+            }
+            prev = out.clone();
+            rows.push(out);
+        }
+        rows
+    }
+
+    #[test]
+    fn png_image_data_decodes_to_pixels() {
+        let mut canvas = Canvas::new(2, 2);
+        canvas.set_pixel(0, 0, Color::new(1.5, 0., 0.));
+        canvas.set_pixel(1, 1, Color::new(0., 0.5, 1.));
+        canvas.set_output_transform(OutputTransform::linear());
+        for (depth, expected) in [
+            (
+                BitDepth::Eight,
+                vec![255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 255],
+            ),
+            (
+                BitDepth::Sixteen,
+                vec![
+                    255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 0, 255,
+                    255,
+                ],
+            ),
+        ] {
+            let row_len = expected.len() / 2;
+            let rows = decode_rows(&encode(&canvas, depth), 2, row_len);
+            assert_eq!(&expected[..row_len], &rows[0][..]);
+            assert_eq!(&expected[row_len..], &rows[1][..]);
+        }
+    }
+
+    #[test]
+    fn transparent_canvas_is_written_with_straight_alpha() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.set_pixel(0, 0, Color::new(0.5, 0.25, 0.));
+        canvas.set_alpha(0, 0, 0.5);
+        canvas.set_alpha(1, 0, 0.);
+        canvas.set_output_transform(OutputTransform::linear());
+        let png = encode(&canvas, BitDepth::Eight);
+        assert_eq!([8, 6], png[24..26]);
+        let rows = decode_rows(&png, 1, 8);
+        assert_eq!(vec![255, 128, 0, 128, 0, 0, 0, 0], rows[0]);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::error::Error;
+use std::io::{self, BufWriter, Read, Write};
+
+// Plain (P3) PPM, one image row per line
+pub fn write_p3<W: Write>(canvas: &Canvas, out: W) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    write!(out, "P3\n{} {}\n255\n", canvas.width(), canvas.height())?;
+    for y in 0..canvas.height() {
+        for x in 0..canvas.width() {
+            let p = canvas.output_pixel(x, y);
+            if x > 0 {
+                out.write_all(b" ")?;
+            }
+            write!(
+                out,
+                "{} {} {}",
+                canvas.convert(p.r),
+                canvas.convert(p.g),
+                canvas.convert(p.b)
+            )?;
+        }
+        out.write_all(b"\n")?;
+    }
+    out.flush()
+}
+
+// Binary (P6) PPM with 8 bits per channel
+pub fn write_p6<W: Write>(canvas: &Canvas, out: W) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    write!(out, "P6\n{} {}\n255\n", canvas.width(), canvas.height())?;
+    let mut row = Vec::with_capacity(canvas.width() * 3);
+    for y in 0..canvas.height() {
+        row.clear();
+        for x in 0..canvas.width() {
+            let p = canvas.output_pixel(x, y);
+            row.extend_from_slice(&[
+                canvas.convert(p.r),
+                canvas.convert(p.g),
+                canvas.convert(p.b),
+            ]);
+        }
+        out.write_all(&row)?;
+    }
+    out.flush()
+}
+
+// Binary (P5) PGM with 16 bits per sample, for integer data such as ids
+pub fn write_p5_16<W: Write>(
+    width: usize,
+    height: usize,
+    samples: &[u16],
+    out: W,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(out);
+    write!(out, "P5\n{} {}\n65535\n", width, height)?;
+    for sample in &samples[..width * height] {
+        out.write_all(&sample.to_be_bytes())?;
+    }
+    out.flush()
+}
+
+// Reads plain and binary PPM (P3, P6) and PGM (P2, P5) images with any
+// maxval up to 65535. Gray images are loaded with equal channels.
+pub fn read<R: Read>(mut input: R) -> Result<Canvas, Error> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    decode(&data).map_err(Error::Parse)
+}
+
+fn decode(data: &[u8]) -> Result<Canvas, String> {
+    let mut parser = Parser::new(data);
+
+    let magic = parser.token()?;
+    let (channels, binary) = match magic.as_str() {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        _ => return Err(format!("Unsupported image format '{}'", magic)),
+    };
+    let width = parser.number("width")? as usize;
+    let height = parser.number("height")? as usize;
+    let maxval = parser.number("maxval")?;
+    if maxval == 0 || maxval > 65535 {
+        return Err(format!("Invalid maxval {}", maxval));
+    }
+
+    let sample_count = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels))
+        .ok_or_else(|| format!("Image size {}x{} is too large", width, height))?;
+    // Nothing is allocated from the header sizes until the data is known to
+    // hold that many samples
+    let mut samples = Vec::new();
+    if binary {
+        // Exactly one whitespace byte separates the header from the raster
+        match parser.data.get(parser.pos) {
+            Some(b) if b.is_ascii_whitespace() => parser.pos += 1,
+            _ => return Err("Expected whitespace after maxval".to_string()),
+        }
+        let sample_size = if maxval > 255 { 2 } else { 1 };
+        let raster = &parser.data[parser.pos..];
+        match sample_count.checked_mul(sample_size) {
+            Some(size) if size <= raster.len() => {}
+            _ => return Err("Unexpected end of image data".to_string()),
+        }
+        samples.reserve_exact(sample_count);
+        for chunk in raster.chunks(sample_size).take(sample_count) {
+            let v = if sample_size == 2 {
+                u32::from(chunk[0]) << 8 | u32::from(chunk[1])
+            } else {
+                u32::from(chunk[0])
+            };
+            samples.push(v);
+        }
+    } else {
+        for _ in 0..sample_count {
+            samples.push(parser.number("sample")?);
+        }
+    }
+
+    let mut canvas = Canvas::new(width, height);
+    let scale = 1. / f64::from(maxval);
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) * channels;
+            let value = |c: usize| -> Result<f64, String> {
+                let v = samples[i + c];
+                if v > maxval {
+                    Err(format!("Sample {} exceeds maxval {}", v, maxval))
+                } else {
+                    Ok(f64::from(v) * scale)
+                }
+            };
+            let color = if channels == 1 {
+                let v = value(0)?;
+                Color::new(v, v, v)
+            } else {
+                Color::new(value(0)?, value(1)?, value(2)?)
+            };
+            canvas.set_pixel(x, y, color);
+        }
+    }
+    Ok(canvas)
+}
+
+pub(crate) struct Parser<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Parser { data, pos: 0 }
+    }
+
+    // Skips whitespace and `#` comments, which run to the end of the line
+    fn skip_separators(&mut self) {
+        while let Some(&b) = self.data.get(self.pos) {
+            if b == b'#' {
+                while let Some(&c) = self.data.get(self.pos) {
+                    if c == b'\n' || c == b'\r' {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+            } else if b.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub(crate) fn token(&mut self) -> Result<String, String> {
+        self.skip_separators();
+        let start = self.pos;
+        while let Some(&b) = self.data.get(self.pos) {
+            if b.is_ascii_whitespace() || b == b'#' {
+                break;
+            }
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err("Unexpected end of image data".to_string());
+        }
+        Ok(String::from_utf8_lossy(&self.data[start..self.pos]).into_owned())
+    }
+
+    fn number(&mut self, what: &str) -> Result<u32, String> {
+        let token = self.token()?;
+        token
+            .parse()
+            .map_err(|_| format!("Invalid {} '{}'", what, token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::canvas::Canvas;
+    use crate::color::Color;
+    use crate::ppm::{read, write_p3, write_p6};
+    use crate::test_utils::assert_color_near;
+    use crate::tonemap::OutputTransform;
+
+    fn sample_canvas() -> Canvas {
+        let mut canvas = Canvas::new(3, 2);
+        canvas.set_pixel(0, 0, Color::new(1., 0., 0.));
+        canvas.set_pixel(1, 0, Color::new(0., 0.5, 0.));
+        canvas.set_pixel(2, 1, Color::new(0.2, 0.4, 1.5));
+        canvas.set_output_transform(OutputTransform::linear());
+        canvas
+    }
+
+    fn assert_canvas_near(a: &Canvas, b: &Canvas, tol: f64) {
+        assert_eq!(a.width(), b.width());
+        assert_eq!(a.height(), b.height());
+        for y in 0..a.height() {
+            for x in 0..a.width() {
+                assert_color_near(a.get_pixel(x, y), b.get_pixel(x, y), tol);
+            }
+        }
+    }
+
+    #[test]
+    fn p6_has_header_and_binary_pixels() {
+        let mut out = Vec::new();
+        write_p6(&sample_canvas(), &mut out).unwrap();
+        assert_eq!(b"P6\n3 2\n255\n", &out[..11]);
+        assert_eq!(11 + 3 * 2 * 3, out.len());
+        assert_eq!([255, 0, 0, 0, 128, 0], out[11..17]);
+        assert_eq!([51, 102, 255], out[out.len() - 3..]);
+    }
+
+    #[test]
+    fn p3_and_p6_round_trip() {
+        let canvas = sample_canvas();
+        let mut expected = sample_canvas();
+        expected.set_pixel(2, 1, Color::new(0.2, 0.4, 1.));
+
+        let mut p3 = Vec::new();
+        write_p3(&canvas, &mut p3).unwrap();
+        assert_canvas_near(&expected, &read(&p3[..]).unwrap(), 0.5 / 255.);
+
+        let mut p6 = Vec::new();
+        write_p6(&canvas, &mut p6).unwrap();
+        assert_canvas_near(&expected, &read(&p6[..]).unwrap(), 0.5 / 255.);
+    }
+
+    #[test]
+    fn reading_skips_comments_and_mixed_whitespace() {
+        let data = b"P3 # comment after magic\n# full line comment\n2\t1\r\n15 # max\n15 0 0\n\n 0 15 15\n";
+        let canvas = read(&data[..]).unwrap();
+        assert_eq!(2, canvas.width());
+        assert_eq!(1, canvas.height());
+        assert_eq!(Color::new(1., 0., 0.), canvas.get_pixel(0, 0));
+        assert_eq!(Color::new(0., 1., 1.), canvas.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn reading_binary_data_after_a_single_whitespace() {
+        // The raster starts with a byte that looks like whitespace
+        let mut data = b"P6 1 1 255\n".to_vec();
+        data.extend_from_slice(&[b'\n', 255, 0]);
+        let canvas = read(&data[..]).unwrap();
+        assert_color_near(Color::new(10. / 255., 1., 0.), canvas.get_pixel(0, 0), 1e-9);
+    }
+
+    #[test]
+    fn reading_sixteen_bit_samples() {
+        let mut data = b"P6\n1 1\n65535\n".to_vec();
+        data.extend_from_slice(&[255, 255, 128, 0, 0, 0]);
+        let canvas = read(&data[..]).unwrap();
+        assert_color_near(
+            Color::new(1., 32768. / 65535., 0.),
+            canvas.get_pixel(0, 0),
+            1e-9,
+        );
+    }
+
+    #[test]
+    fn reading_graymaps() {
+        let canvas = read(&b"P2\n2 1\n4\n0 2\n"[..]).unwrap();
+        assert_eq!(Color::new(0.5, 0.5, 0.5), canvas.get_pixel(1, 0));
+
+        let mut data = b"P5 2 1 100 ".to_vec();
+        data.extend_from_slice(&[100, 25]);
+        let canvas = read(&data[..]).unwrap();
+        assert_eq!(Color::new(1., 1., 1.), canvas.get_pixel(0, 0));
+        assert_eq!(Color::new(0.25, 0.25, 0.25), canvas.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn reading_invalid_images_fails() {
+        assert!(read(&b"P7\n1 1\n255\n"[..]).is_err());
+        assert!(read(&b"P3\n1 x\n255\n"[..]).is_err());
+        assert!(read(&b"P3\n1 1\n0\n"[..]).is_err());
+        assert!(read(&b"P3\n1 1\n255\n1 2\n"[..]).is_err());
+        assert!(read(&b"P3\n1 1\n255\n1 2 300\n"[..]).is_err());
+        assert!(read(&b"P6\n2 1\n255\n\x01\x02\x03"[..]).is_err());
+    }
+
+    #[test]
+    fn reading_huge_sizes_from_a_short_file_fails() {
+        assert!(read(&b"P6 4000000000 4000000000 255\n"[..]).is_err());
+        assert!(read(&b"P6 4000000000 4000000000 65535\n\x00"[..]).is_err());
+        assert!(read(&b"P5 100000 100000 255\n\x00\x00"[..]).is_err());
+        assert!(read(&b"P3 100000 100000 255\n1 2 3\n"[..]).is_err());
+        assert!(read(&b"P2 4000000000 4000000000 255\n1\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::canvas::Canvas;
+use crate::stats::RenderStats;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Snapshot passed to progress callbacks after every completed row
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    pub rows_done: u32,
+    pub total_rows: u32,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f64 {
+        if self.total_rows == 0 {
+            1.
+        } else {
+            self.rows_done as f64 / self.total_rows as f64
+        }
+    }
+
+    // Remaining time assuming the remaining rows take as long as the done ones
+    pub fn eta(&self) -> Option<Duration> {
+        if self.rows_done == 0 {
+            return None;
+        }
+        let remaining = (self.total_rows - self.rows_done) as f64 / self.rows_done as f64;
+        Some(self.elapsed.mul_f64(remaining))
+    }
+}
+
+// Shared flag to stop a render early. Clones refer to the same flag, so one
+// can be handed to another thread to cancel the render from there.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+pub struct RenderOutcome {
+    // Rows that were not rendered before cancellation are black and fully
+    // transparent
+    pub canvas: Canvas,
+    pub cancelled: bool,
+    // Only collected by `Camera::render_instrumented`
+    pub stats: Option<RenderStats>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{CancelToken, Progress};
+    use std::time::Duration;
+
+    #[test]
+    fn progress_fraction_and_eta() {
+        let p = Progress {
+            rows_done: 25,
+            total_rows: 100,
+            elapsed: Duration::from_secs(10),
+        };
+        assert_eq!(0.25, p.fraction());
+        assert_eq!(Some(Duration::from_secs(30)), p.eta());
+        assert_eq!(None, Progress { rows_done: 0, ..p }.eta());
+    }
+
+    #[test]
+    fn cancel_token_clones_share_the_flag() {
+        let token = CancelToken::new();
+        let other = token.clone();
+        assert!(!token.is_cancelled());
+        other.cancel();
+        assert!(token.is_cancelled());
+    }
+}
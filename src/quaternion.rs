@@ -0,0 +1,361 @@
+// Unit quaternions for rotations that can be interpolated, and the
+// decomposition of affine transforms into translation, rotation and scale.
+use crate::error::Error;
+use crate::matrix::Mat4x4;
+use crate::transform::{scale, translate};
+use crate::tuple::{vector, Vector3};
+use std::ops::{Mul, Neg};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub fn new(w: f64, x: f64, y: f64, z: f64) -> Self {
+        Quaternion { w, x, y, z }
+    }
+
+    pub fn identity() -> Self {
+        Quaternion::new(1., 0., 0., 0.)
+    }
+
+    // Same direction of rotation as `transform::rotate`
+    pub fn from_axis_angle(axis: Vector3, r: f64) -> Self {
+        let a = axis.normalize();
+        let (sin, cos) = (r / 2.).sin_cos();
+        Quaternion::new(cos, a.x * sin, a.y * sin, a.z * sin)
+    }
+
+    // Axis and angle in [0, pi]. The axis is arbitrary for the identity.
+    pub fn to_axis_angle(&self) -> (Vector3, f64) {
+        let q = if self.w < 0. { -*self } else { *self };
+        let sin = (1. - q.w * q.w).max(0.).sqrt();
+        if sin < 1e-12 {
+            return (vector(1., 0., 0.), 0.);
+        }
+        (
+            vector(q.x / sin, q.y / sin, q.z / sin),
+            2. * q.w.clamp(-1., 1.).acos(),
+        )
+    }
+
+    // Rotation part of `m`, which must be orthonormal; use `decompose` for
+    // transforms that also scale
+    pub fn from_matrix(m: &Mat4x4) -> Self {
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0. {
+            let s = 2. * (trace + 1.).sqrt();
+            Quaternion::new(
+                s / 4.,
+                (m[2][1] - m[1][2]) / s,
+                (m[0][2] - m[2][0]) / s,
+                (m[1][0] - m[0][1]) / s,
+            )
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = 2. * (1. + m[0][0] - m[1][1] - m[2][2]).sqrt();
+            Quaternion::new(
+                (m[2][1] - m[1][2]) / s,
+                s / 4.,
+                (m[0][1] + m[1][0]) / s,
+                (m[0][2] + m[2][0]) / s,
+            )
+        } else if m[1][1] > m[2][2] {
+            let s = 2. * (1. + m[1][1] - m[0][0] - m[2][2]).sqrt();
+            Quaternion::new(
+                (m[0][2] - m[2][0]) / s,
+                (m[0][1] + m[1][0]) / s,
+                s / 4.,
+                (m[1][2] + m[2][1]) / s,
+            )
+        } else {
+            let s = 2. * (1. + m[2][2] - m[0][0] - m[1][1]).sqrt();
+            Quaternion::new(
+                (m[1][0] - m[0][1]) / s,
+                (m[0][2] + m[2][0]) / s,
+                (m[1][2] + m[2][1]) / s,
+                s / 4.,
+            )
+        };
+        q.normalize()
+    }
+
+    pub fn to_matrix(&self) -> Mat4x4 {
+        let Quaternion { w, x, y, z } = self.normalize();
+        Mat4x4::new([
+            [
+                1. - 2. * (y * y + z * z),
+                2. * (x * y - w * z),
+                2. * (x * z + w * y),
+                0.,
+            ],
+            [
+                2. * (x * y + w * z),
+                1. - 2. * (x * x + z * z),
+                2. * (y * z - w * x),
+                0.,
+            ],
+            [
+                2. * (x * z - w * y),
+                2. * (y * z + w * x),
+                1. - 2. * (x * x + y * y),
+                0.,
+            ],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    pub fn dot(&self, other: Quaternion) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        let m = self.dot(*self).sqrt();
+        Quaternion::new(self.w / m, self.x / m, self.y / m, self.z / m)
+    }
+
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn rotate(&self, v: Vector3) -> Vector3 {
+        let p = *self * Quaternion::new(0., v.x, v.y, v.z) * self.conjugate();
+        vector(p.x, p.y, p.z)
+    }
+
+    // Constant angular speed along the shorter arc, t in [0, 1]
+    pub fn slerp(&self, other: Quaternion, t: f64) -> Quaternion {
+        let (a, mut b) = (self.normalize(), other.normalize());
+        let mut cos = a.dot(b);
+        if cos < 0. {
+            b = -b;
+            cos = -cos;
+        }
+        // Nearly equal rotations, where sin(theta) loses precision
+        if cos > 0.9995 {
+            return Quaternion::new(
+                a.w + (b.w - a.w) * t,
+                a.x + (b.x - a.x) * t,
+                a.y + (b.y - a.y) * t,
+                a.z + (b.z - a.z) * t,
+            )
+            .normalize();
+        }
+        let theta = cos.acos();
+        let (wa, wb) = (
+            ((1. - t) * theta).sin() / theta.sin(),
+            (t * theta).sin() / theta.sin(),
+        );
+        Quaternion::new(
+            a.w * wa + b.w * wb,
+            a.x * wa + b.x * wb,
+            a.y * wa + b.y * wb,
+            a.z * wa + b.z * wb,
+        )
+    }
+}
+
+// Hamilton product, `a * b` rotates by `b` and then by `a`
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, b: Quaternion) -> Quaternion {
+        let a = self;
+        Quaternion::new(
+            a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        )
+    }
+}
+
+// The same rotation
+impl Neg for Quaternion {
+    type Output = Quaternion;
+
+    fn neg(self) -> Quaternion {
+        Quaternion::new(-self.w, -self.x, -self.y, -self.z)
+    }
+}
+
+// An affine transform as `translate * rotate * scale`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decomposition {
+    pub translation: Vector3,
+    pub rotation: Quaternion,
+    pub scale: Vector3,
+}
+
+impl Decomposition {
+    pub fn to_matrix(&self) -> Mat4x4 {
+        let (t, s) = (self.translation, self.scale);
+        translate(t.x, t.y, t.z) * self.rotation.to_matrix() * scale(s.x, s.y, s.z)
+    }
+
+    // Linear translation and scale, spherical rotation
+    pub fn interpolate(&self, other: &Decomposition, t: f64) -> Decomposition {
+        Decomposition {
+            translation: self.translation + (other.translation - self.translation) * t,
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale + (other.scale - self.scale) * t,
+        }
+    }
+}
+
+// Splits `m` into translation, rotation and scale. The bottom row is assumed
+// to be (0, 0, 0, 1) and shear is dropped. Mirroring shows up as a negative x
+// scale. Fails when the transform flattens space.
+pub fn decompose(m: &Mat4x4) -> Result<Decomposition, Error> {
+    let column = |c: usize| vector(m[0][c], m[1][c], m[2][c]);
+    let (c0, c1, c2) = (column(0), column(1), column(2));
+
+    // Gram-Schmidt, so that sheared inputs still give a rotation
+    let mut sx = c0.magnitude();
+    if sx < 1e-12 {
+        return Err(Error::NonInvertibleMatrix);
+    }
+    let mut x = c0 / sx;
+    let c1 = c1 - x * x.dot(c1);
+    let sy = c1.magnitude();
+    if sy < 1e-12 {
+        return Err(Error::NonInvertibleMatrix);
+    }
+    let y = c1 / sy;
+    let c2 = c2 - x * x.dot(c2) - y * y.dot(c2);
+    let sz = c2.magnitude();
+    if sz < 1e-12 {
+        return Err(Error::NonInvertibleMatrix);
+    }
+    let z = c2 / sz;
+    if x.cross(y).dot(z) < 0. {
+        sx = -sx;
+        x = -x;
+    }
+
+    let rotation = Mat4x4::new([
+        [x.x, y.x, z.x, 0.],
+        [x.y, y.y, z.y, 0.],
+        [x.z, y.z, z.z, 0.],
+        [0., 0., 0., 1.],
+    ]);
+    Ok(Decomposition {
+        translation: column(3),
+        rotation: Quaternion::from_matrix(&rotation),
+        scale: vector(sx, sy, sz),
+    })
+}
+
+// Interpolates between two affine transforms at t in [0, 1], slerping the
+// rotation so that rotating shapes keep their size. Falls back to a
+// component-wise lerp when either transform cannot be decomposed exactly,
+// e.g. because it is sheared.
+pub fn interpolate(a: &Mat4x4, b: &Mat4x4, t: f64) -> Mat4x4 {
+    let exact = |m: &Mat4x4| decompose(m).ok().filter(|d| d.to_matrix() == *m);
+    match (exact(a), exact(b)) {
+        (Some(da), Some(db)) => da.interpolate(&db, t).to_matrix(),
+        _ => a.lerp(b, t),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quaternion::{decompose, interpolate, Quaternion};
+    use crate::test_utils::{assert_f64_near, assert_mat4x4_near};
+    use crate::transform::{
+        identity, rotate, rotate_x, rotate_y, rotate_z, scale, skew, translate,
+    };
+    use crate::tuple::test_utils::assert_tuple_eq;
+    use crate::tuple::{point, vector};
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+    #[test]
+    fn axis_angle_quaternions_match_rotation_matrices() {
+        let q = Quaternion::from_axis_angle(vector(1., 0., 0.), FRAC_PI_2);
+        assert_mat4x4_near(rotate_x(FRAC_PI_2), q.to_matrix());
+        let q = Quaternion::from_axis_angle(vector(0., 1., 0.), 0.3);
+        assert_mat4x4_near(rotate_y(0.3), q.to_matrix());
+        let axis = vector(1., -2., 0.5);
+        let q = Quaternion::from_axis_angle(axis, 2.);
+        assert_mat4x4_near(rotate(axis, 2.), q.to_matrix());
+        assert_tuple_eq(
+            rotate(axis, 2.) * vector(3., 1., 2.),
+            q.rotate(vector(3., 1., 2.)),
+        );
+
+        let (a, r) = q.to_axis_angle();
+        assert_tuple_eq(axis.normalize(), a);
+        assert_f64_near(2., r, 1e-12);
+    }
+
+    #[test]
+    fn matrices_round_trip_through_quaternions() {
+        for m in &[
+            identity(),
+            rotate_x(PI),
+            rotate_y(-2.5),
+            rotate_z(PI * 0.99),
+            rotate(vector(1., 1., 1.), 2.),
+            rotate_x(0.4).rotate_y(1.3).rotate_z(-2.),
+        ] {
+            assert_mat4x4_near(*m, Quaternion::from_matrix(m).to_matrix());
+        }
+    }
+
+    #[test]
+    fn composing_quaternions_composes_rotations() {
+        let a = Quaternion::from_axis_angle(vector(0., 0., 1.), 0.5);
+        let b = Quaternion::from_axis_angle(vector(1., 0., 0.), 1.1);
+        assert_mat4x4_near(rotate_z(0.5) * rotate_x(1.1), (a * b).to_matrix());
+    }
+
+    #[test]
+    fn slerp_rotates_at_constant_speed_along_the_short_arc() {
+        let a = Quaternion::identity();
+        let b = Quaternion::from_axis_angle(vector(0., 1., 0.), FRAC_PI_2);
+        assert_mat4x4_near(rotate_y(FRAC_PI_4), a.slerp(b, 0.5).to_matrix());
+        assert_mat4x4_near(rotate_y(FRAC_PI_2 * 0.25), a.slerp(b, 0.25).to_matrix());
+        assert_mat4x4_near(rotate_y(FRAC_PI_4), a.slerp(-b, 0.5).to_matrix());
+        assert_eq!(a, a.slerp(a, 0.3));
+    }
+
+    #[test]
+    fn decomposing_an_affine_transform() {
+        let m = identity()
+            .scale(2., 3., 4.)
+            .rotate(vector(1., 2., 3.), 1.)
+            .translate(5., -6., 7.);
+        let d = decompose(&m).unwrap();
+        assert_tuple_eq(vector(5., -6., 7.), d.translation);
+        assert_tuple_eq(vector(2., 3., 4.), d.scale);
+        assert_mat4x4_near(rotate(vector(1., 2., 3.), 1.), d.rotation.to_matrix());
+        assert_mat4x4_near(m, d.to_matrix());
+
+        let mirrored = scale(-1., 1., 1.) * rotate_z(0.3);
+        assert_mat4x4_near(mirrored, decompose(&mirrored).unwrap().to_matrix());
+        assert!(decompose(&scale(1., 0., 1.)).is_err());
+    }
+
+    #[test]
+    fn interpolating_rotations_keeps_the_size() {
+        let a = translate(0., 0., 0.);
+        let b = translate(4., 0., 0.) * rotate_z(FRAC_PI_2);
+        let half = interpolate(&a, &b, 0.5);
+        assert_mat4x4_near(translate(2., 0., 0.) * rotate_z(FRAC_PI_4), half);
+        // A matrix lerp would shrink the point towards the axis
+        assert_f64_near(
+            1.,
+            (half * point(1., 0., 0.) - point(2., 0., 0.)).magnitude(),
+            1e-12,
+        );
+        assert_mat4x4_near(
+            a.lerp(&scale(1., 0., 1.), 0.5),
+            interpolate(&a, &scale(1., 0., 1.), 0.5),
+        );
+        let sheared = skew(1., 0., 0., 0., 0., 0.);
+        assert_mat4x4_near(sheared, interpolate(&sheared, &b, 0.));
+    }
+}
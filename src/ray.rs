@@ -1,18 +1,29 @@
-use crate::{matrix, tuple};
+use crate::matrix;
+use crate::tuple::{Point3, Vector3};
 use std::ops::Mul;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
-    pub origin: tuple::Tuple,
-    pub direction: tuple::Tuple,
+    pub origin: Point3,
+    pub direction: Vector3,
+    pub time: f64,
 }
 
 impl Ray {
-    pub fn new(origin: tuple::Tuple, direction: tuple::Tuple) -> Self {
-        Ray { origin, direction }
+    pub fn new(origin: Point3, direction: Vector3) -> Self {
+        Ray::with_time(origin, direction, 0.)
     }
 
-    pub fn position(&self, t: f64) -> tuple::Tuple {
+    // time: position within the shutter interval, 0 at shutter open
+    pub fn with_time(origin: Point3, direction: Vector3, time: f64) -> Self {
+        Ray {
+            origin,
+            direction,
+            time,
+        }
+    }
+
+    pub fn position(&self, t: f64) -> Point3 {
         self.origin + self.direction * t
     }
 }
@@ -21,7 +32,7 @@ impl Mul<Ray> for matrix::Mat4x4 {
     type Output = Ray;
 
     fn mul(self, rhs: Ray) -> Ray {
-        Ray::new(self * rhs.origin, self * rhs.direction)
+        Ray::with_time(self * rhs.origin, self * rhs.direction, rhs.time)
     }
 }
 
@@ -39,6 +50,13 @@ mod tests {
         let r = Ray::new(origin, direction);
         assert_eq!(origin, r.origin);
         assert_eq!(direction, r.direction);
+        assert_eq!(0., r.time);
+    }
+
+    #[test]
+    fn ray_created_at_a_time() {
+        let r = Ray::with_time(point(1.0, 2.0, 3.0), vector(4.0, 5.0, 6.0), 0.25);
+        assert_eq!(0.25, r.time);
     }
 
     #[test]
@@ -68,4 +86,11 @@ mod tests {
         assert_eq!(point(2.0, 6.0, 12.0), r2.origin);
         assert_eq!(vector(0.0, 3.0, 0.0), r2.direction);
     }
+
+    #[test]
+    fn transforming_a_ray_keeps_its_time() {
+        let r = Ray::with_time(point(1.0, 2.0, 3.0), vector(0.0, 1.0, 0.0), 0.5);
+        let r2 = transform::translate(3.0, 4.0, 5.0) * r;
+        assert_eq!(0.5, r2.time);
+    }
 }
@@ -0,0 +1,1287 @@
+// Scene files in the YAML format from The Ray Tracer Challenge. A scene is a
+// list of commands:
+//
+//   - add: camera | light | sphere | plane
+//   - define: <name>, with an optional `extend: <name>` and a `value`
+//
+// Transforms are lists of `[translate|scale, x, y, z]`, `[rotate-x|rotate-y|
+// rotate-z, radians]` and `[shear, xy, xz, yx, yz, zx, zy]`, applied in the
+// order they are listed, and may include the names of defined transforms.
+// Materials are mappings or the name of a defined material. Shapes may have
+// a `name`, which must be unique, to find them in the world.
+use crate::camera::{view_transform, Camera};
+use crate::color::Color;
+use crate::error::Error;
+use crate::json::{self, Json, JsonValue};
+use crate::lights::PointLight;
+use crate::materials::Material;
+use crate::matrix::Mat4x4;
+use crate::occlusion::AmbientOcclusion;
+use crate::patterns::{CheckerPattern, GradientPattern, Pattern, RingPattern, StripedPattern};
+use crate::shape::{Shape, ShapeType};
+use crate::transform::{rotate_x, rotate_y, rotate_z, scale, skew, translate};
+use crate::tuple::{point, vector, Point3};
+use crate::validation::Diagnostic;
+use crate::world::World;
+use crate::yaml::{self, Node, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+// Deepest nesting of collections, and of defines naming other defines, that
+// scene files may use, so that parsing cannot overflow the stack
+pub(crate) const MAX_NESTING: usize = 128;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneError {
+    // 1-based line of the offending value, 0 when not tied to a line
+    pub line: usize,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl SceneError {
+    pub(crate) fn new(line: usize, message: &str) -> Self {
+        SceneError {
+            line,
+            key: None,
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn with_key(line: usize, key: &str, message: &str) -> Self {
+        SceneError {
+            line,
+            key: Some(key.to_string()),
+            message: message.to_string(),
+        }
+    }
+
+    // Attributes the error to `key` unless a nested key was already recorded
+    pub(crate) fn or_key(mut self, key: &str) -> Self {
+        if self.key.is_none() {
+            self.key = Some(key.to_string());
+        }
+        self
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "line {}", self.line)?;
+            if let Some(key) = &self.key {
+                write!(f, ", key '{}'", key)?;
+            }
+            write!(f, ": ")?;
+        } else if let Some(key) = &self.key {
+            write!(f, "key '{}': ", key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+pub struct Scene {
+    pub camera: Camera,
+    pub world: World,
+}
+
+impl Scene {
+    pub fn parse(source: &str) -> Result<Scene, SceneError> {
+        let document = yaml::parse(source)?;
+        let commands = match &document.value {
+            Value::Sequence(items) => items,
+            _ => {
+                return Err(SceneError::new(
+                    document.line,
+                    "A scene must be a list of commands",
+                ))
+            }
+        };
+
+        let mut builder = Builder::default();
+        for command in commands {
+            if let Some(name) = command.get("define") {
+                builder.define(command, name)?;
+            } else if let Some(kind) = command.get("add") {
+                builder.add(command, kind)?;
+            } else {
+                return Err(SceneError::new(
+                    command.line,
+                    "Expected an 'add' or 'define' command",
+                ));
+            }
+        }
+
+        let camera = builder
+            .camera
+            .ok_or_else(|| SceneError::new(0, "The scene has no camera"))?;
+        let light = builder
+            .light
+            .ok_or_else(|| SceneError::new(0, "The scene has no light"))?;
+        let mut world = World::new();
+        world.light = light;
+        add_shapes(&mut world, builder.shapes);
+        Ok(Scene { camera, world })
+    }
+
+    // Reads JSON files by their `.json` extension and YAML otherwise
+    pub fn load(file: &str) -> Result<Scene, Error> {
+        let source = std::fs::read_to_string(file)?;
+        let is_json = Path::new(file)
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        if is_json {
+            Ok(Scene::from_json(&source)?)
+        } else {
+            Ok(Scene::parse(&source)?)
+        }
+    }
+
+    // Camera diagnostics followed by those of the world
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.camera.diagnostics();
+        diagnostics.extend(self.world.diagnostics());
+        diagnostics
+    }
+
+    // Every field of the camera, light, shapes and materials, so that
+    // `from_json` restores an identical scene. Fails if any number is
+    // infinite or NaN.
+    pub fn to_json(&self) -> Result<String, SceneError> {
+        let c = &self.camera;
+        let camera = Json::object(vec![
+            ("width", Json::number(c.hsize as f64)),
+            ("height", Json::number(c.vsize as f64)),
+            ("field_of_view", Json::number(c.field_of_view)),
+            ("transform", matrix_json(&c.transform)),
+            ("shutter_open", Json::number(c.shutter_open)),
+            ("shutter_close", Json::number(c.shutter_close)),
+            ("time_samples", Json::number(c.time_samples as f64)),
+            (
+                "transparent_background",
+                Json::new(JsonValue::Bool(c.transparent_background)),
+            ),
+        ]);
+        let w = &self.world;
+        let light = Json::object(vec![
+            ("position", point_json(w.light.position)),
+            ("intensity", color_json(w.light.intensity)),
+        ]);
+        let ambient_occlusion = match &w.ambient_occlusion {
+            Some(ao) => Json::object(vec![
+                ("samples", Json::number(ao.samples as f64)),
+                ("max_distance", Json::number(ao.max_distance)),
+            ]),
+            None => Json::new(JsonValue::Null),
+        };
+        let shapes = w
+            .shapes
+            .iter()
+            .map(|s| shape_json(w.name(s.id()), s))
+            .collect();
+        Json::object(vec![
+            ("camera", camera),
+            (
+                "world",
+                Json::object(vec![
+                    ("light", light),
+                    ("ambient_occlusion", ambient_occlusion),
+                    ("shapes", Json::array(shapes)),
+                ]),
+            ),
+        ])
+        .to_string_pretty()
+    }
+
+    pub fn save_json(&self, file: &str) -> Result<(), Error> {
+        Ok(std::fs::write(file, self.to_json()?)?)
+    }
+
+    // Reads the format written by `to_json`. Only the camera size and field
+    // of view, the light and the shape types are required.
+    pub fn from_json(source: &str) -> Result<Scene, SceneError> {
+        let root = json::parse(source)?;
+        json_check_keys(&root, &["camera", "world"])?;
+
+        let c = json_required(&root, "camera")?;
+        json_check_keys(
+            c,
+            &[
+                "width",
+                "height",
+                "field_of_view",
+                "transform",
+                "shutter_open",
+                "shutter_close",
+                "time_samples",
+                "transparent_background",
+            ],
+        )?;
+        let mut camera = Camera::new(
+            json_positive_integer(json_required(c, "width")?, "width")?,
+            json_positive_integer(json_required(c, "height")?, "height")?,
+            json_number(json_required(c, "field_of_view")?, "field_of_view")?,
+        );
+        if let Some(t) = c.get("transform") {
+            camera.transform = json_matrix(t, "transform")?;
+        }
+        if let Some(v) = c.get("shutter_open") {
+            camera.shutter_open = json_number(v, "shutter_open")?;
+        }
+        if let Some(v) = c.get("shutter_close") {
+            camera.shutter_close = json_number(v, "shutter_close")?;
+        }
+        if let Some(v) = c.get("time_samples") {
+            camera.time_samples = json_integer(v, "time_samples")?;
+        }
+        if let Some(v) = c.get("transparent_background") {
+            camera.transparent_background = json_bool(v, "transparent_background")?;
+        }
+
+        let w = json_required(&root, "world")?;
+        json_check_keys(w, &["light", "ambient_occlusion", "shapes"])?;
+        let l = json_required(w, "light")?;
+        json_check_keys(l, &["position", "intensity"])?;
+        let light = PointLight::new(
+            json_color(json_required(l, "intensity")?, "intensity")?,
+            json_tuple(json_required(l, "position")?, "position", point)?,
+        );
+        let ambient_occlusion = match w.get("ambient_occlusion") {
+            None => None,
+            Some(Json {
+                value: JsonValue::Null,
+                ..
+            }) => None,
+            Some(ao) => {
+                json_check_keys(ao, &["samples", "max_distance"])?;
+                Some(AmbientOcclusion::new(
+                    json_integer(json_required(ao, "samples")?, "samples")?,
+                    json_number(json_required(ao, "max_distance")?, "max_distance")?,
+                ))
+            }
+        };
+        let mut names = Vec::new();
+        let mut shapes = Vec::new();
+        if let Some(nodes) = w.get("shapes") {
+            for node in json_array(nodes, "shapes")? {
+                let (name, shape) = json_shape(node)?;
+                if let Some(name) = &name {
+                    check_unique_name(&mut names, name, node.line)?;
+                }
+                shapes.push((name, shape));
+            }
+        }
+
+        let mut world = World::new();
+        world.light = light;
+        world.ambient_occlusion = ambient_occlusion;
+        add_shapes(&mut world, shapes);
+        Ok(Scene { camera, world })
+    }
+}
+
+fn add_shapes(world: &mut World, shapes: Vec<(Option<String>, Shape)>) {
+    for (name, shape) in shapes {
+        match name {
+            Some(name) => world.add_named(&name, shape),
+            None => world.add(shape),
+        };
+    }
+}
+
+fn check_unique_name(names: &mut Vec<String>, name: &str, line: usize) -> Result<(), SceneError> {
+    if names.iter().any(|n| n == name) {
+        return Err(SceneError::with_key(
+            line,
+            "name",
+            &format!("Another shape is already named '{}'", name),
+        ));
+    }
+    names.push(name.to_string());
+    Ok(())
+}
+
+#[derive(Default)]
+struct Builder {
+    defines: HashMap<String, Node>,
+    camera: Option<Camera>,
+    light: Option<PointLight>,
+    shapes: Vec<(Option<String>, Shape)>,
+    names: Vec<String>,
+}
+
+impl Builder {
+    fn define(&mut self, command: &Node, name: &Node) -> Result<(), SceneError> {
+        check_keys(command, &["define", "extend", "value"])?;
+        let name = string(name, "define")?;
+        let mut value = required(command, "value")?.clone();
+        if let Some(base) = command.get("extend") {
+            let base_name = string(base, "extend")?;
+            let base = self.defines.get(&base_name).ok_or_else(|| {
+                SceneError::with_key(
+                    base.line,
+                    "extend",
+                    &format!("Unknown define '{}'", base_name),
+                )
+            })?;
+            value.value = match (&base.value, value.value) {
+                (Value::Mapping(base), Value::Mapping(entries)) => {
+                    let mut merged: Vec<(String, Node)> = base
+                        .iter()
+                        .filter(|(k, _)| entries.iter().all(|(key, _)| key != k))
+                        .cloned()
+                        .collect();
+                    merged.extend(entries);
+                    Value::Mapping(merged)
+                }
+                (Value::Sequence(base), Value::Sequence(items)) => {
+                    Value::Sequence(base.iter().cloned().chain(items).collect())
+                }
+                _ => {
+                    return Err(SceneError::with_key(
+                        value.line,
+                        "value",
+                        &format!(
+                            "Cannot extend '{}' with a different kind of value",
+                            base_name
+                        ),
+                    ))
+                }
+            };
+        }
+        self.defines.insert(name, value);
+        Ok(())
+    }
+
+    fn add(&mut self, command: &Node, kind: &Node) -> Result<(), SceneError> {
+        match string(kind, "add")?.as_str() {
+            "camera" => {
+                check_keys(
+                    command,
+                    &[
+                        "add",
+                        "width",
+                        "height",
+                        "field-of-view",
+                        "from",
+                        "to",
+                        "up",
+                    ],
+                )?;
+                let mut camera = Camera::new(
+                    positive_integer(required(command, "width")?, "width")?,
+                    positive_integer(required(command, "height")?, "height")?,
+                    number(required(command, "field-of-view")?, "field-of-view")?,
+                );
+                camera.transform = view_transform(
+                    tuple(required(command, "from")?, "from", point)?,
+                    tuple(required(command, "to")?, "to", point)?,
+                    tuple(required(command, "up")?, "up", vector)?,
+                );
+                self.camera = Some(camera);
+            }
+            "light" | "point-light" => {
+                check_keys(command, &["add", "at", "intensity"])?;
+                if self.light.is_some() {
+                    return Err(SceneError::with_key(
+                        kind.line,
+                        "add",
+                        "Only one light is supported",
+                    ));
+                }
+                self.light = Some(PointLight::new(
+                    color(required(command, "intensity")?, "intensity")?,
+                    tuple(required(command, "at")?, "at", point)?,
+                ));
+            }
+            "sphere" | "plane" => {
+                check_keys(
+                    command,
+                    &["add", "name", "material", "transform", "end-transform"],
+                )?;
+                let mut shape = Shape::new(if string(kind, "add")? == "sphere" {
+                    ShapeType::Sphere
+                } else {
+                    ShapeType::Plane
+                });
+                if let Some(material) = command.get("material") {
+                    shape.material = self.material(material)?;
+                }
+                if let Some(transform) = command.get("transform") {
+                    shape.transform = self.transform(transform, "transform")?;
+                }
+                if let Some(transform) = command.get("end-transform") {
+                    shape.end_transform = Some(self.transform(transform, "end-transform")?);
+                }
+                let name = match command.get("name") {
+                    Some(node) => {
+                        let name = string(node, "name")?;
+                        check_unique_name(&mut self.names, &name, node.line)?;
+                        Some(name)
+                    }
+                    None => None,
+                };
+                self.shapes.push((name, shape));
+            }
+            other => {
+                return Err(SceneError::with_key(
+                    kind.line,
+                    "add",
+                    &format!("Unsupported object '{}'", other),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve<'a>(&'a self, node: &'a Node, key: &str) -> Result<&'a Node, SceneError> {
+        match &node.value {
+            Value::Scalar(name) => self.defines.get(name).ok_or_else(|| {
+                SceneError::with_key(node.line, key, &format!("Unknown define '{}'", name))
+            }),
+            _ => Ok(node),
+        }
+    }
+
+    fn material(&self, node: &Node) -> Result<Material, SceneError> {
+        let node = self.resolve(node, "material")?;
+        let entries = mapping(node, "material")?;
+        let mut material = Material::new();
+        for (key, value) in entries {
+            match key.as_str() {
+                "color" => material.color = color(value, key)?,
+                "ambient" => material.ambient = number(value, key)?,
+                "diffuse" => material.diffuse = number(value, key)?,
+                "specular" => material.specular = number(value, key)?,
+                "shininess" => material.shininess = number(value, key)?,
+                "reflective" => material.reflective = number(value, key)?,
+                "transparency" => material.transparency = number(value, key)?,
+                "refractive-index" => material.refractive_index = number(value, key)?,
+                "pattern" => material.pattern = Some(self.pattern(value)?),
+                _ => return Err(SceneError::with_key(value.line, key, "Unknown key")),
+            }
+        }
+        Ok(material)
+    }
+
+    fn pattern(&self, node: &Node) -> Result<Pattern, SceneError> {
+        check_keys(node, &["type", "colors", "transform"])?;
+        let colors = sequence(required(node, "colors")?, "colors")?;
+        if colors.len() != 2 {
+            return Err(SceneError::with_key(
+                node.get("colors").unwrap().line,
+                "colors",
+                "Expected two colors",
+            ));
+        }
+        let (a, b) = (color(&colors[0], "colors")?, color(&colors[1], "colors")?);
+        let transform = match node.get("transform") {
+            Some(t) => self.transform(t, "transform")?,
+            None => Mat4x4::identity(),
+        };
+        let kind = required(node, "type")?;
+        Ok(match string(kind, "type")?.as_str() {
+            "stripes" | "stripe" => {
+                let mut p = StripedPattern::new(a, b);
+                p.transform = transform;
+                Pattern::Stripe(p)
+            }
+            "gradient" => {
+                let mut p = GradientPattern::new(a, b);
+                p.transform = transform;
+                Pattern::Gradient(p)
+            }
+            "rings" | "ring" => {
+                let mut p = RingPattern::new(a, b);
+                p.transform = transform;
+                Pattern::Ring(p)
+            }
+            "checkers" | "checker" => {
+                let mut p = CheckerPattern::new(a, b);
+                p.transform = transform;
+                Pattern::Checker(p)
+            }
+            other => {
+                return Err(SceneError::with_key(
+                    kind.line,
+                    "type",
+                    &format!("Unsupported pattern '{}'", other),
+                ))
+            }
+        })
+    }
+
+    // The first transform in the list is applied first
+    fn transform(&self, node: &Node, key: &str) -> Result<Mat4x4, SceneError> {
+        self.expand_transform(node, key, &mut Vec::new())
+    }
+
+    // `expanding` holds the names of the defines being expanded
+    fn expand_transform(
+        &self,
+        node: &Node,
+        key: &str,
+        expanding: &mut Vec<String>,
+    ) -> Result<Mat4x4, SceneError> {
+        let name = match &node.value {
+            Value::Scalar(name) => Some(name),
+            _ => None,
+        };
+        if let Some(name) = name {
+            if expanding.contains(name) {
+                let message = format!("Define '{}' refers to itself", name);
+                return Err(SceneError::with_key(node.line, key, &message));
+            }
+            if expanding.len() >= MAX_NESTING {
+                return Err(SceneError::with_key(
+                    node.line,
+                    key,
+                    "Defines are nested too deeply",
+                ));
+            }
+        }
+        let resolved = self.resolve(node, key)?;
+        if let Some(name) = name {
+            expanding.push(name.clone());
+        }
+        let mut m = Mat4x4::identity();
+        for item in sequence(resolved, key)? {
+            let step = match &item.value {
+                Value::Scalar(_) => self.expand_transform(item, key, expanding)?,
+                _ => transform_step(item, key)?,
+            };
+            m = m.then(step);
+        }
+        if name.is_some() {
+            expanding.pop();
+        }
+        Ok(m)
+    }
+}
+
+fn transform_step(node: &Node, key: &str) -> Result<Mat4x4, SceneError> {
+    let items = sequence(node, key)?;
+    let name = match items.first() {
+        Some(first) => string(first, key)?,
+        None => return Err(SceneError::with_key(node.line, key, "Empty transform")),
+    };
+    let args = items[1..]
+        .iter()
+        .map(|v| number(v, key))
+        .collect::<Result<Vec<f64>, SceneError>>()?;
+    let expect = |n: usize| {
+        if args.len() == n {
+            Ok(())
+        } else {
+            Err(SceneError::with_key(
+                node.line,
+                key,
+                &format!("'{}' takes {} numbers, found {}", name, n, args.len()),
+            ))
+        }
+    };
+    Ok(match name.as_str() {
+        "translate" => {
+            expect(3)?;
+            translate(args[0], args[1], args[2])
+        }
+        "scale" => {
+            expect(3)?;
+            scale(args[0], args[1], args[2])
+        }
+        "rotate-x" => {
+            expect(1)?;
+            rotate_x(args[0])
+        }
+        "rotate-y" => {
+            expect(1)?;
+            rotate_y(args[0])
+        }
+        "rotate-z" => {
+            expect(1)?;
+            rotate_z(args[0])
+        }
+        "shear" => {
+            expect(6)?;
+            skew(args[0], args[1], args[2], args[3], args[4], args[5])
+        }
+        _ => {
+            return Err(SceneError::with_key(
+                node.line,
+                key,
+                &format!("Unknown transform '{}'", name),
+            ))
+        }
+    })
+}
+
+fn check_keys(node: &Node, allowed: &[&str]) -> Result<(), SceneError> {
+    for (key, value) in mapping(node, "")? {
+        if !allowed.contains(&key.as_str()) {
+            return Err(SceneError::with_key(value.line, key, "Unknown key"));
+        }
+    }
+    Ok(())
+}
+
+fn required<'a>(node: &'a Node, key: &str) -> Result<&'a Node, SceneError> {
+    node.get(key)
+        .ok_or_else(|| SceneError::with_key(node.line, key, "Missing key"))
+}
+
+fn mapping<'a>(node: &'a Node, key: &str) -> Result<&'a [(String, Node)], SceneError> {
+    match &node.value {
+        Value::Mapping(entries) => Ok(entries),
+        _ => Err(SceneError::with_key(node.line, key, "Expected a mapping")),
+    }
+}
+
+fn sequence<'a>(node: &'a Node, key: &str) -> Result<&'a [Node], SceneError> {
+    match &node.value {
+        Value::Sequence(items) => Ok(items),
+        _ => Err(SceneError::with_key(node.line, key, "Expected a list")),
+    }
+}
+
+fn string(node: &Node, key: &str) -> Result<String, SceneError> {
+    match &node.value {
+        Value::Scalar(s) => Ok(s.clone()),
+        _ => Err(SceneError::with_key(node.line, key, "Expected a string")),
+    }
+}
+
+fn number(node: &Node, key: &str) -> Result<f64, SceneError> {
+    let s = string(node, key)?;
+    // Rust also parses `nan`, `inf` and `infinity`, which no scene can use
+    match s.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(n),
+        _ => Err(SceneError::with_key(
+            node.line,
+            key,
+            &format!("Expected a number, found '{}'", s),
+        )),
+    }
+}
+
+fn positive_integer(node: &Node, key: &str) -> Result<u32, SceneError> {
+    let s = string(node, key)?;
+    match s.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(SceneError::with_key(
+            node.line,
+            key,
+            &format!("Expected a positive integer, found '{}'", s),
+        )),
+    }
+}
+
+fn numbers3(node: &Node, key: &str) -> Result<[f64; 3], SceneError> {
+    let items = sequence(node, key)?;
+    if items.len() != 3 {
+        return Err(SceneError::with_key(
+            node.line,
+            key,
+            &format!("Expected 3 numbers, found {}", items.len()),
+        ));
+    }
+    Ok([
+        number(&items[0], key)?,
+        number(&items[1], key)?,
+        number(&items[2], key)?,
+    ])
+}
+
+fn tuple<T>(node: &Node, key: &str, f: fn(f64, f64, f64) -> T) -> Result<T, SceneError> {
+    let [x, y, z] = numbers3(node, key)?;
+    Ok(f(x, y, z))
+}
+
+fn color(node: &Node, key: &str) -> Result<Color, SceneError> {
+    let [r, g, b] = numbers3(node, key)?;
+    Ok(Color::new(r, g, b))
+}
+
+fn matrix_json(m: &Mat4x4) -> Json {
+    Json::array(
+        (0..4)
+            .map(|r| Json::array((0..4).map(|c| Json::number(m[r][c])).collect()))
+            .collect(),
+    )
+}
+
+fn point_json(t: Point3) -> Json {
+    Json::array(vec![
+        Json::number(t.x),
+        Json::number(t.y),
+        Json::number(t.z),
+    ])
+}
+
+fn color_json(c: Color) -> Json {
+    Json::array(vec![
+        Json::number(c.r),
+        Json::number(c.g),
+        Json::number(c.b),
+    ])
+}
+
+fn pattern_json(pattern: &Pattern) -> Json {
+    let (kind, a, b, transform) = match pattern {
+        Pattern::Stripe(p) => ("stripes", p.a, p.b, p.transform),
+        Pattern::Gradient(p) => ("gradient", p.a, p.b, p.transform),
+        Pattern::Ring(p) => ("rings", p.a, p.b, p.transform),
+        Pattern::Checker(p) => ("checkers", p.a, p.b, p.transform),
+    };
+    Json::object(vec![
+        ("type", Json::string(kind)),
+        ("a", color_json(a)),
+        ("b", color_json(b)),
+        ("transform", matrix_json(&transform)),
+    ])
+}
+
+fn shape_json(name: Option<&str>, shape: &Shape) -> Json {
+    let m = &shape.material;
+    let material = Json::object(vec![
+        ("color", color_json(m.color)),
+        ("ambient", Json::number(m.ambient)),
+        ("diffuse", Json::number(m.diffuse)),
+        ("specular", Json::number(m.specular)),
+        ("shininess", Json::number(m.shininess)),
+        ("reflective", Json::number(m.reflective)),
+        ("transparency", Json::number(m.transparency)),
+        ("refractive_index", Json::number(m.refractive_index)),
+        (
+            "pattern",
+            m.pattern
+                .as_ref()
+                .map_or(Json::new(JsonValue::Null), pattern_json),
+        ),
+    ]);
+    Json::object(vec![
+        (
+            "type",
+            Json::string(match shape.shape {
+                ShapeType::Sphere => "sphere",
+                ShapeType::Plane => "plane",
+            }),
+        ),
+        (
+            "name",
+            name.map_or(Json::new(JsonValue::Null), Json::string),
+        ),
+        ("transform", matrix_json(&shape.transform)),
+        (
+            "end_transform",
+            shape
+                .end_transform
+                .as_ref()
+                .map_or(Json::new(JsonValue::Null), matrix_json),
+        ),
+        ("material", material),
+    ])
+}
+
+fn json_shape(node: &Json) -> Result<(Option<String>, Shape), SceneError> {
+    json_check_keys(
+        node,
+        &["type", "name", "transform", "end_transform", "material"],
+    )?;
+    let kind = json_required(node, "type")?;
+    let mut shape = Shape::new(match json_string(kind, "type")?.as_str() {
+        "sphere" => ShapeType::Sphere,
+        "plane" => ShapeType::Plane,
+        other => {
+            return Err(SceneError::with_key(
+                kind.line,
+                "type",
+                &format!("Unsupported shape '{}'", other),
+            ))
+        }
+    });
+    if let Some(t) = node.get("transform") {
+        shape.transform = json_matrix(t, "transform")?;
+    }
+    if let Some(t) = node.get("end_transform").filter(|t| !json_is_null(t)) {
+        shape.end_transform = Some(json_matrix(t, "end_transform")?);
+    }
+    if let Some(m) = node.get("material") {
+        for (key, value) in json_object(m, "material")? {
+            let material = &mut shape.material;
+            match key.as_str() {
+                "color" => material.color = json_color(value, key)?,
+                "ambient" => material.ambient = json_number(value, key)?,
+                "diffuse" => material.diffuse = json_number(value, key)?,
+                "specular" => material.specular = json_number(value, key)?,
+                "shininess" => material.shininess = json_number(value, key)?,
+                "reflective" => material.reflective = json_number(value, key)?,
+                "transparency" => material.transparency = json_number(value, key)?,
+                "refractive_index" => material.refractive_index = json_number(value, key)?,
+                "pattern" if json_is_null(value) => material.pattern = None,
+                "pattern" => material.pattern = Some(json_pattern(value)?),
+                _ => return Err(SceneError::with_key(value.line, key, "Unknown key")),
+            }
+        }
+    }
+    let name = match node.get("name").filter(|n| !json_is_null(n)) {
+        Some(n) => Some(json_string(n, "name")?),
+        None => None,
+    };
+    Ok((name, shape))
+}
+
+fn json_pattern(node: &Json) -> Result<Pattern, SceneError> {
+    json_check_keys(node, &["type", "a", "b", "transform"])?;
+    let a = json_color(json_required(node, "a")?, "a")?;
+    let b = json_color(json_required(node, "b")?, "b")?;
+    let transform = match node.get("transform") {
+        Some(t) => json_matrix(t, "transform")?,
+        None => Mat4x4::identity(),
+    };
+    let kind = json_required(node, "type")?;
+    Ok(match json_string(kind, "type")?.as_str() {
+        "stripes" => Pattern::Stripe(StripedPattern { a, b, transform }),
+        "gradient" => Pattern::Gradient(GradientPattern { a, b, transform }),
+        "rings" => Pattern::Ring(RingPattern { a, b, transform }),
+        "checkers" => Pattern::Checker(CheckerPattern { a, b, transform }),
+        other => {
+            return Err(SceneError::with_key(
+                kind.line,
+                "type",
+                &format!("Unsupported pattern '{}'", other),
+            ))
+        }
+    })
+}
+
+fn json_is_null(node: &Json) -> bool {
+    node.value == JsonValue::Null
+}
+
+fn json_check_keys(node: &Json, allowed: &[&str]) -> Result<(), SceneError> {
+    for (key, value) in json_object(node, "")? {
+        if !allowed.contains(&key.as_str()) {
+            return Err(SceneError::with_key(value.line, key, "Unknown key"));
+        }
+    }
+    Ok(())
+}
+
+fn json_required<'a>(node: &'a Json, key: &str) -> Result<&'a Json, SceneError> {
+    node.get(key)
+        .ok_or_else(|| SceneError::with_key(node.line, key, "Missing key"))
+}
+
+fn json_object<'a>(node: &'a Json, key: &str) -> Result<&'a [(String, Json)], SceneError> {
+    match &node.value {
+        JsonValue::Object(entries) => Ok(entries),
+        _ => Err(SceneError::with_key(node.line, key, "Expected an object")),
+    }
+}
+
+fn json_array<'a>(node: &'a Json, key: &str) -> Result<&'a [Json], SceneError> {
+    match &node.value {
+        JsonValue::Array(items) => Ok(items),
+        _ => Err(SceneError::with_key(node.line, key, "Expected an array")),
+    }
+}
+
+fn json_string(node: &Json, key: &str) -> Result<String, SceneError> {
+    match &node.value {
+        JsonValue::String(s) => Ok(s.clone()),
+        _ => Err(SceneError::with_key(node.line, key, "Expected a string")),
+    }
+}
+
+fn json_bool(node: &Json, key: &str) -> Result<bool, SceneError> {
+    match node.value {
+        JsonValue::Bool(b) => Ok(b),
+        _ => Err(SceneError::with_key(
+            node.line,
+            key,
+            "Expected true or false",
+        )),
+    }
+}
+
+fn json_number(node: &Json, key: &str) -> Result<f64, SceneError> {
+    match node.value {
+        JsonValue::Number(n) if n.is_finite() => Ok(n),
+        JsonValue::Number(n) => Err(SceneError::with_key(
+            node.line,
+            key,
+            &format!("Expected a finite number, found {}", n),
+        )),
+        _ => Err(SceneError::with_key(node.line, key, "Expected a number")),
+    }
+}
+
+fn json_integer(node: &Json, key: &str) -> Result<u32, SceneError> {
+    let n = json_number(node, key)?;
+    if n.fract() != 0. || n < 0. || n > u32::MAX as f64 {
+        return Err(SceneError::with_key(
+            node.line,
+            key,
+            &format!("Expected a non-negative integer, found {}", n),
+        ));
+    }
+    Ok(n as u32)
+}
+
+fn json_positive_integer(node: &Json, key: &str) -> Result<u32, SceneError> {
+    match json_integer(node, key)? {
+        0 => Err(SceneError::with_key(
+            node.line,
+            key,
+            "Expected a positive integer, found 0",
+        )),
+        n => Ok(n),
+    }
+}
+
+fn json_numbers(node: &Json, key: &str, count: usize) -> Result<Vec<f64>, SceneError> {
+    let items = json_array(node, key)?;
+    if items.len() != count {
+        return Err(SceneError::with_key(
+            node.line,
+            key,
+            &format!("Expected {} numbers, found {}", count, items.len()),
+        ));
+    }
+    items.iter().map(|i| json_number(i, key)).collect()
+}
+
+fn json_tuple<T>(node: &Json, key: &str, f: fn(f64, f64, f64) -> T) -> Result<T, SceneError> {
+    let v = json_numbers(node, key, 3)?;
+    Ok(f(v[0], v[1], v[2]))
+}
+
+fn json_color(node: &Json, key: &str) -> Result<Color, SceneError> {
+    let v = json_numbers(node, key, 3)?;
+    Ok(Color::new(v[0], v[1], v[2]))
+}
+
+fn json_matrix(node: &Json, key: &str) -> Result<Mat4x4, SceneError> {
+    let rows = json_array(node, key)?;
+    if rows.len() != 4 {
+        return Err(SceneError::with_key(node.line, key, "Expected 4 rows"));
+    }
+    let mut m = Mat4x4::identity();
+    for (r, row) in rows.iter().enumerate() {
+        for (c, v) in json_numbers(row, key, 4)?.into_iter().enumerate() {
+            m[r][c] = v;
+        }
+    }
+    Ok(m)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::Color;
+    use crate::occlusion::AmbientOcclusion;
+    use crate::patterns::{Pattern, StripedPattern};
+    use crate::scene::Scene;
+    use crate::shape::ShapeType;
+    use crate::test_utils::{assert_color_near, assert_mat4x4_near};
+    use crate::transform::{rotate_y, scale, translate};
+    use crate::tuple::point;
+
+    const SCENE: &str = "
+- add: camera
+  width: 100
+  height: 50
+  field-of-view: 0.785
+  from: [ -6, 6, -10 ]
+  to: [ 6, 0, 6 ]
+  up: [ -0.45, 1, 0 ]
+
+- add: light
+  at: [ 50, 100, -50 ]
+  intensity: [ 1, 1, 1 ]
+
+- define: white-material
+  value:
+    color: [ 1, 1, 1 ]
+    diffuse: 0.7
+    ambient: 0.1
+
+- define: blue-material
+  extend: white-material
+  value:
+    color: [ 0.537, 0.831, 0.914 ]
+
+- define: standard-transform
+  value:
+    - [ translate, 1, -1, 1 ]
+    - [ scale, 0.5, 0.5, 0.5 ]
+
+- define: large-object
+  value:
+    - standard-transform
+    - [ scale, 3.5, 3.5, 3.5 ]
+
+- add: plane
+  material:
+    pattern:
+      type: checkers
+      colors:
+        - [ 0.35, 0.35, 0.35 ]
+        - [ 0.65, 0.65, 0.65 ]
+    specular: 0
+  transform:
+    - [ rotate-y, 0.31415 ]
+
+- add: sphere
+  material: blue-material
+  transform:
+    - large-object
+    - [ translate, 4, 0, 0 ]
+";
+
+    #[test]
+    fn parsing_a_scene() {
+        let scene = Scene::parse(SCENE).unwrap();
+        assert_eq!(100, scene.camera.hsize);
+        assert_eq!(50, scene.camera.vsize);
+        assert_eq!(0.785, scene.camera.field_of_view);
+        assert_eq!(point(50., 100., -50.), scene.world.light.position);
+        assert_eq!(2, scene.world.shapes.len());
+
+        let plane = scene.world.shapes[0];
+        assert_eq!(ShapeType::Plane, plane.shape);
+        assert_eq!(0., plane.material.specular);
+        assert!(matches!(plane.material.pattern, Some(Pattern::Checker(_))));
+        assert_mat4x4_near(rotate_y(0.31415), plane.transform);
+
+        let sphere = scene.world.shapes[1];
+        assert_eq!(ShapeType::Sphere, sphere.shape);
+        assert_color_near(
+            Color::new(0.537, 0.831, 0.914),
+            sphere.material.color,
+            1e-12,
+        );
+        assert_eq!(0.7, sphere.material.diffuse);
+        assert_mat4x4_near(
+            translate(4., 0., 0.)
+                * scale(3.5, 3.5, 3.5)
+                * scale(0.5, 0.5, 0.5)
+                * translate(1., -1., 1.),
+            sphere.transform,
+        );
+    }
+
+    #[test]
+    fn parse_errors_report_line_and_key() {
+        let e = Scene::parse(&SCENE.replace("diffuse: 0.7", "diffuse: high"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            "line 17, key 'diffuse': Expected a number, found 'high'",
+            e.to_string()
+        );
+        for value in &["nan", "inf", "-Infinity"] {
+            let e = Scene::parse(&SCENE.replace("diffuse: 0.7", &format!("diffuse: {}", value)))
+                .err()
+                .unwrap();
+            assert_eq!(
+                format!(
+                    "line 17, key 'diffuse': Expected a number, found '{}'",
+                    value
+                ),
+                e.to_string()
+            );
+        }
+
+        let e = Scene::parse(&SCENE.replace("rotate-y, 0.31415", "rotate-w, 1"))
+            .err()
+            .unwrap();
+        assert_eq!((44, Some("transform".to_string())), (e.line, e.key));
+
+        let e = Scene::parse(&SCENE.replace("material: blue-material", "material: red"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            "line 47, key 'material': Unknown define 'red'",
+            e.to_string()
+        );
+
+        let e = Scene::parse(&SCENE.replace("add: plane", "add: cube"))
+            .err()
+            .unwrap();
+        assert_eq!((35, Some("add".to_string())), (e.line, e.key));
+
+        let e = Scene::parse(&SCENE.replace("  height: 50\n", ""))
+            .err()
+            .unwrap();
+        assert_eq!("line 2, key 'height': Missing key", e.to_string());
+
+        let e = Scene::parse(&SCENE.replace("  up:", "  upp:"))
+            .err()
+            .unwrap();
+        assert_eq!("line 8, key 'upp': Unknown key", e.to_string());
+    }
+
+    #[test]
+    fn defines_that_refer_to_themselves_are_errors() {
+        let shape = "- add: sphere\n  transform: t\n";
+        let e = Scene::parse(&format!("- define: t\n  value: [t]\n{}", shape))
+            .err()
+            .unwrap();
+        assert_eq!(
+            "line 2, key 'transform': Define 't' refers to itself",
+            e.to_string()
+        );
+
+        let cycle = "- define: t\n  value: [u]\n- define: u\n  value: [t]\n";
+        let e = Scene::parse(&format!("{}{}", cycle, shape)).err().unwrap();
+        assert_eq!("Define 't' refers to itself", e.message);
+
+        let mut chain = "- define: t0\n  value: [[scale, 1, 1, 1]]\n".to_string();
+        for i in 1..1000 {
+            chain += &format!("- define: t{}\n  value: [t{}]\n", i, i - 1);
+        }
+        let e = Scene::parse(&format!("{}- add: sphere\n  transform: t999\n", chain))
+            .err()
+            .unwrap();
+        assert_eq!("Defines are nested too deeply", e.message);
+    }
+
+    #[test]
+    fn parsing_the_example_scene() {
+        let scene = Scene::parse(include_str!("../scenes/world.yml")).unwrap();
+        assert_eq!(1000, scene.camera.hsize);
+        assert_eq!(6, scene.world.shapes.len());
+        assert!(matches!(
+            scene.world.shapes[5].material.pattern,
+            Some(Pattern::Ring(_))
+        ));
+    }
+
+    #[test]
+    fn json_round_trips_losslessly() {
+        let mut scene = Scene::parse(SCENE).unwrap();
+        scene.camera.shutter_close = 0.5;
+        scene.camera.time_samples = 4;
+        scene.camera.transparent_background = true;
+        scene.world.ambient_occlusion = Some(AmbientOcclusion::new(8, 1.5));
+        scene.world.shapes[1].end_transform = Some(translate(0.1, 0.2, 1. / 3.));
+        let mut stripes = StripedPattern::new(Color::new(0.1, 0.2, 0.3), Color::white());
+        stripes.transform = rotate_y(0.7);
+        scene.world.shapes[1].material.pattern = Some(Pattern::Stripe(stripes));
+
+        let json = scene.to_json().unwrap();
+        let read = Scene::from_json(&json).unwrap();
+        assert_eq!(json, read.to_json().unwrap());
+        assert_eq!(scene.world.light, read.world.light);
+        assert_eq!(scene.world.ambient_occlusion, read.world.ambient_occlusion);
+        assert_eq!(scene.world.shapes, read.world.shapes);
+        assert_eq!(scene.camera.transform, read.camera.transform);
+        assert_eq!(scene.camera.pixel_size, read.camera.pixel_size);
+        for r in 0..4 {
+            for c in 0..4 {
+                assert_eq!(
+                    scene.world.shapes[1].transform[r][c].to_bits(),
+                    read.world.shapes[1].transform[r][c].to_bits()
+                );
+            }
+        }
+        assert!(read.camera.transparent_background);
+        assert_eq!(4, read.camera.time_samples);
+    }
+
+    #[test]
+    fn json_files_are_loaded_by_extension() {
+        let scene = Scene::parse(SCENE).unwrap();
+        let path = std::env::temp_dir().join("rust_raytracer_scene_test.json");
+        let path = path.to_str().unwrap();
+        scene.save_json(path).unwrap();
+        let read = Scene::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(scene.world.shapes, read.unwrap().world.shapes);
+    }
+
+    #[test]
+    fn json_errors_report_line_and_key() {
+        let json = Scene::parse(SCENE).unwrap().to_json().unwrap();
+        let e = Scene::from_json(&json.replacen("\"diffuse\": 0.7", "\"diffuse\": \"x\"", 1))
+            .err()
+            .unwrap();
+        assert_eq!(Some("diffuse".to_string()), e.key);
+        let line = json
+            .lines()
+            .position(|l| l.contains("\"diffuse\": 0.7"))
+            .unwrap()
+            + 1;
+        assert_eq!(line, e.line);
+        let e = Scene::from_json(&json.replacen("\"diffuse\": 0.7", "\"diffuse\": 1e400", 1))
+            .err()
+            .unwrap();
+        assert_eq!((line, Some("diffuse".to_string())), (e.line, e.key));
+
+        let e = Scene::from_json("{\"camera\": {\"width\": 10}}")
+            .err()
+            .unwrap();
+        assert_eq!("line 1, key 'height': Missing key", e.to_string());
+        let e = Scene::from_json(&json.replace("\"sphere\"", "\"cube\""))
+            .err()
+            .unwrap();
+        assert_eq!(Some("type".to_string()), e.key);
+    }
+
+    #[test]
+    fn json_rejects_what_cannot_be_read_back() {
+        let mut scene = Scene::parse(SCENE).unwrap();
+        scene.camera.shutter_close = f64::INFINITY;
+        let e = scene.to_json().unwrap_err();
+        assert_eq!(
+            "key 'shutter_close': inf cannot be written as a JSON number",
+            e.to_string()
+        );
+        assert!(scene.save_json("unused.json").is_err());
+
+        scene.camera.shutter_close = 0.;
+        let json = scene.to_json().unwrap();
+        let e = Scene::from_json(&json.replace("\"width\": 100.0", "\"width\": 0"))
+            .err()
+            .unwrap();
+        assert_eq!(Some("width".to_string()), e.key);
+    }
+
+    #[test]
+    fn shapes_can_be_named() {
+        let source = SCENE.replace("- add: sphere\n", "- add: sphere\n  name: ball\n");
+        let scene = Scene::parse(&source).unwrap();
+        let w = &scene.world;
+        assert_eq!(Some(&w.shapes[1]), w.shape_named("ball"));
+        assert_eq!(Some("ball"), w.name(w.shapes[1].id()));
+        assert_eq!(None, w.name(w.shapes[0].id()));
+
+        let read = Scene::from_json(&scene.to_json().unwrap()).unwrap();
+        assert_eq!(Some(&read.world.shapes[1]), read.world.shape_named("ball"));
+        assert_eq!(None, read.world.name(read.world.shapes[0].id()));
+
+        let twice = source.replace("- add: plane\n", "- add: plane\n  name: ball\n");
+        let e = Scene::parse(&twice).err().unwrap();
+        assert_eq!(
+            "line 48, key 'name': Another shape is already named 'ball'",
+            e.to_string()
+        );
+        let json = scene
+            .to_json()
+            .unwrap()
+            .replacen("\"name\": null", "\"name\": \"ball\"", 1);
+        let e = Scene::from_json(&json).err().unwrap();
+        assert_eq!(Some("name".to_string()), e.key);
+    }
+
+    #[test]
+    fn scenes_need_a_camera_and_a_light() {
+        let e = Scene::parse("- add: sphere\n").err().unwrap();
+        assert_eq!("The scene has no camera", e.to_string());
+        assert!(Scene::parse("add: sphere\n").is_err());
+        assert!(Scene::load("no_such_scene.yml").is_err());
+    }
+}
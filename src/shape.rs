@@ -1,8 +1,12 @@
+use crate::error::Error;
 use crate::intersections::Intersection;
 use crate::materials::Material;
 use crate::matrix::Mat4x4;
+use crate::quaternion;
 use crate::ray::Ray;
-use crate::tuple::{point, vector, Tuple};
+use crate::stats;
+use crate::tuple::{point, vector, Normal3, Point3, Vector3};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ShapeType {
@@ -10,11 +14,38 @@ pub enum ShapeType {
     Plane,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+// Identifies a shape for its whole life, unlike its index in
+// `World::shapes`, which changes when shapes are removed. Every `Shape::new`
+// gets an id no other shape has; copies of a shape share it until
+// `World::add` gives them their own.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ShapeId(u64);
+
+impl ShapeId {
+    pub(crate) fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        ShapeId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
 pub struct Shape {
     pub shape: ShapeType,
     pub transform: Mat4x4,
+    // Transform at the end of the shutter interval (time 1) for moving shapes
+    pub end_transform: Option<Mat4x4>,
     pub material: Material,
+    pub(crate) id: ShapeId,
+}
+
+// Shapes are equal when they look the same, whatever their ids
+impl PartialEq for Shape {
+    fn eq(&self, other: &Shape) -> bool {
+        self.shape == other.shape
+            && self.transform == other.transform
+            && self.end_transform == other.end_transform
+            && self.material == other.material
+    }
 }
 
 impl Shape {
@@ -22,27 +53,75 @@ impl Shape {
         Shape {
             shape,
             transform: Mat4x4::identity(),
+            end_transform: None,
             material: Material::new(),
+            id: ShapeId::next(),
+        }
+    }
+
+    pub fn id(&self) -> ShapeId {
+        self.id
+    }
+
+    // Rotations are interpolated along the arc, so spinning shapes keep their
+    // size throughout the shutter interval
+    pub fn transform_at(&self, time: f64) -> Mat4x4 {
+        match self.end_transform {
+            Some(end) => quaternion::interpolate(&self.transform, &end, time.clamp(0., 1.)),
+            None => self.transform,
+        }
+    }
+
+    // A static copy of the shape, frozen at the given time
+    pub fn at_time(&self, time: f64) -> Shape {
+        Shape {
+            transform: self.transform_at(time),
+            end_transform: None,
+            ..*self
         }
     }
 
-    pub fn normal(&self, p: Tuple) -> Tuple {
-        let tinv = self.transform.inverse().unwrap();
+    // Checks that the transforms, including the end transform and the
+    // pattern's, can be inverted
+    pub fn validate(&self) -> Result<(), Error> {
+        self.transform.inverse()?;
+        if let Some(end) = self.end_transform {
+            end.inverse()?;
+        }
+        if let Some(pattern) = self.material.pattern {
+            pattern.transform().inverse()?;
+        }
+        Ok(())
+    }
+
+    // Panics if the transform cannot be inverted, see `validate`. Only shapes
+    // that were hit have normals, and `intersect` never hits those.
+    pub fn normal(&self, p: Point3) -> Normal3 {
+        let tinv = self
+            .transform
+            .inverse()
+            .expect("Shape transform is not invertible, see Shape::validate");
         let local_point = tinv * p;
         let local_normal = match self.shape {
             ShapeType::Sphere => calculate_sphere_normal(local_point),
             ShapeType::Plane => calculate_plane_normal(),
         };
-        let mut world_normal = tinv.transpose() * local_normal;
-        world_normal.w = 0.;
-        world_normal.normalize()
+        Normal3::from_vector(tinv.transpose() * local_normal)
     }
 
-    pub fn intersect(&self, ray: Ray) -> Vec<Intersection> {
-        let local_ray = self.transform.inverse().unwrap() * ray;
+    // Panics if the transform cannot be inverted, see `validate`. A moving
+    // shape whose transforms at both ends can be inverted may still flatten
+    // part way, e.g. while its scale changes sign, and has nothing to hit then.
+    pub fn intersect(&self, ray: Ray) -> Vec<Intersection<'_>> {
+        stats::count_intersection_test(self.shape);
+        let local_ray = match self.transform_at(ray.time).inverse() {
+            Ok(inv) => inv * ray,
+            Err(_) if self.end_transform.is_some() => return Vec::new(),
+            Err(_) => panic!("Shape transform is not invertible, see Shape::validate"),
+        };
         match self.shape {
-            ShapeType::Sphere => intersect_sphere(&self, local_ray),
-            ShapeType::Plane => intersect_plane(&self, local_ray),
+            ShapeType::Sphere => intersect_sphere(self, local_ray),
+            ShapeType::Plane => intersect_plane(self, local_ray),
         }
     }
 }
@@ -54,15 +133,15 @@ pub fn glass_sphere() -> Shape {
     s
 }
 
-fn calculate_sphere_normal(p: Tuple) -> Tuple {
+fn calculate_sphere_normal(p: Point3) -> Vector3 {
     p - point(0., 0., 0.)
 }
 
-fn calculate_plane_normal() -> Tuple {
+fn calculate_plane_normal() -> Vector3 {
     vector(0., 1., 0.)
 }
 
-fn intersect_sphere(shape: &Shape, ray: Ray) -> Vec<Intersection> {
+fn intersect_sphere(shape: &Shape, ray: Ray) -> Vec<Intersection<'_>> {
     let sphere_to_ray = ray.origin - point(0.0, 0.0, 0.0);
     let a = ray.direction.dot(ray.direction);
     let b = 2. * ray.direction.dot(sphere_to_ray);
@@ -80,8 +159,8 @@ fn intersect_sphere(shape: &Shape, ray: Ray) -> Vec<Intersection> {
     vec![Intersection::new(t1, shape), Intersection::new(t2, shape)]
 }
 
-fn intersect_plane(shape: &Shape, ray: Ray) -> Vec<Intersection> {
-    if ray.direction.y.abs() > std::f64::EPSILON {
+fn intersect_plane(shape: &Shape, ray: Ray) -> Vec<Intersection<'_>> {
+    if ray.direction.y.abs() > f64::EPSILON {
         vec![Intersection::new(-ray.origin.y / ray.direction.y, shape)]
     } else {
         vec![]
@@ -90,13 +169,17 @@ fn intersect_plane(shape: &Shape, ray: Ray) -> Vec<Intersection> {
 
 #[cfg(test)]
 mod tests {
+    use crate::color::Color;
+    use crate::error::Error;
     use crate::materials::Material;
     use crate::matrix::Mat4x4;
+    use crate::patterns::{Pattern, StripedPattern};
     use crate::ray::Ray;
     use crate::shape::{calculate_plane_normal, glass_sphere, intersect_plane, Shape, ShapeType};
     use crate::transform;
     use crate::tuple::test_utils::assert_tuple_eq;
     use crate::tuple::{point, vector};
+    use std::f64::consts::FRAC_1_SQRT_2;
 
     #[test]
     fn shape_default_transformation() {
@@ -112,6 +195,42 @@ mod tests {
         assert_eq!(t, s.transform);
     }
 
+    #[test]
+    fn shape_transform_without_motion_is_constant() {
+        let mut s = Shape::new(ShapeType::Sphere);
+        s.transform = transform::translate(2.0, 3.0, 4.0);
+        assert_eq!(s.transform, s.transform_at(0.));
+        assert_eq!(s.transform, s.transform_at(0.7));
+    }
+
+    #[test]
+    fn moving_shape_interpolates_its_transform() {
+        let mut s = Shape::new(ShapeType::Sphere);
+        s.end_transform = Some(transform::translate(4.0, 0.0, 0.0));
+        assert_eq!(Mat4x4::identity(), s.transform_at(0.));
+        assert_eq!(transform::translate(1.0, 0.0, 0.0), s.transform_at(0.25));
+        assert_eq!(transform::translate(4.0, 0.0, 0.0), s.transform_at(1.));
+        assert_eq!(transform::translate(4.0, 0.0, 0.0), s.transform_at(2.));
+    }
+
+    #[test]
+    fn rotating_shape_keeps_its_size_mid_motion() {
+        let mut s = Shape::new(ShapeType::Sphere);
+        s.end_transform = Some(transform::rotate_y(std::f64::consts::PI));
+        let half = s.transform_at(0.5);
+        assert_eq!(transform::rotate_y(std::f64::consts::FRAC_PI_2), half);
+        assert_tuple_eq(point(0., 0., -1.), half * point(1., 0., 0.));
+    }
+
+    #[test]
+    fn shape_frozen_at_a_time_is_static() {
+        let mut s = Shape::new(ShapeType::Sphere);
+        s.end_transform = Some(transform::translate(4.0, 0.0, 0.0));
+        let frozen = s.at_time(0.5);
+        assert_eq!(transform::translate(2.0, 0.0, 0.0), frozen.transform);
+        assert_eq!(None, frozen.end_transform);
+    }
+
     #[test]
     fn sphere_has_default_material() {
         let s = Shape::new(ShapeType::Sphere);
@@ -165,8 +284,8 @@ mod tests {
         let mut s = Shape::new(ShapeType::Sphere);
         s.transform = transform::translate(0., 1., 0.);
         assert_tuple_eq(
-            vector(0., 0.70711, -0.70711),
-            s.normal(point(0., 1.70711, -0.70711)),
+            vector(0., FRAC_1_SQRT_2, -FRAC_1_SQRT_2),
+            s.normal(point(0., 1. + FRAC_1_SQRT_2, -FRAC_1_SQRT_2)),
         );
     }
 
@@ -264,6 +383,16 @@ mod tests {
         assert_eq!(0, xs.len());
     }
 
+    #[test]
+    fn intersect_moving_sphere_with_rays_at_different_times() {
+        let mut s = Shape::new(ShapeType::Sphere);
+        s.end_transform = Some(transform::translate(5.0, 0.0, 0.0));
+        let r0 = Ray::with_time(point(5.0, 0.0, -5.0), vector(0.0, 0.0, 1.0), 0.);
+        let r1 = Ray::with_time(point(5.0, 0.0, -5.0), vector(0.0, 0.0, 1.0), 1.);
+        assert_eq!(0, s.intersect(r0).len());
+        assert_eq!(2, s.intersect(r1).len());
+    }
+
     #[test]
     fn intersect_with_ray_parallel_to_the_plane() {
         let p = Shape::new(ShapeType::Plane);
@@ -299,4 +428,41 @@ mod tests {
         assert_eq!(1., xs[0].t);
         assert_eq!(&p, xs[0].shape);
     }
+
+    #[test]
+    fn flattened_shapes_fail_validation() {
+        let mut s = Shape::new(ShapeType::Sphere);
+        assert!(s.validate().is_ok());
+        s.transform = transform::scale(1., 0., 1.);
+        assert!(matches!(s.validate(), Err(Error::NonInvertibleMatrix)));
+
+        let mut s = Shape::new(ShapeType::Sphere);
+        let mut pattern = StripedPattern::new(Color::white(), Color::black());
+        pattern.transform = Mat4x4::zero();
+        s.material.pattern = Some(Pattern::Stripe(pattern));
+        assert!(s.validate().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Shape transform is not invertible")]
+    fn intersecting_a_flattened_shape_panics() {
+        let mut s = Shape::new(ShapeType::Sphere);
+        s.transform = transform::scale(1., 0., 1.);
+        s.intersect(Ray::new(point(0., 0., -5.), vector(0., 0., 1.)));
+    }
+
+    #[test]
+    fn moving_shapes_that_flatten_part_way_are_missed_then() {
+        let mut s = Shape::new(ShapeType::Sphere);
+        s.transform = transform::scale(1., 1., 1.);
+        s.end_transform = Some(transform::scale(1., 1., -1.));
+        assert!(s.validate().is_ok());
+        let r = Ray::with_time(point(0., 0., -5.), vector(0., 0., 1.), 0.5);
+        assert!(s.intersect(r).is_empty());
+        assert_eq!(
+            2,
+            s.intersect(Ray::new(point(0., 0., -5.), vector(0., 0., 1.)))
+                .len()
+        );
+    }
 }
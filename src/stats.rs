@@ -0,0 +1,294 @@
+// Counters for finding out why a scene renders slowly. Nothing is counted
+// unless the render asks for statistics, see `Camera::render_instrumented`:
+// each render thread then counts into its own thread-local counters, which
+// are merged when the thread finishes.
+use crate::camera::Region;
+use crate::shape::ShapeType;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RayKind {
+    Primary,
+    Shadow,
+    Reflection,
+    Refraction,
+    // Path tracer bounces off diffuse surfaces
+    Diffuse,
+    Occlusion,
+}
+
+// Time spent on one tile. The renderer hands out whole rows, so every tile
+// is one row of the rendered region.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileStats {
+    pub region: Region,
+    pub time: Duration,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    pub primary_rays: u64,
+    pub shadow_rays: u64,
+    pub reflection_rays: u64,
+    pub refraction_rays: u64,
+    pub diffuse_rays: u64,
+    pub occlusion_rays: u64,
+    pub sphere_tests: u64,
+    pub plane_tests: u64,
+    // Sum over primary rays of the deepest reflection, refraction or bounce
+    // level reached from them
+    pub total_depth: u64,
+    pub max_depth: u32,
+    // Sorted from top to bottom
+    pub tiles: Vec<TileStats>,
+}
+
+impl RenderStats {
+    pub fn rays(&self) -> u64 {
+        self.primary_rays
+            + self.shadow_rays
+            + self.reflection_rays
+            + self.refraction_rays
+            + self.diffuse_rays
+            + self.occlusion_rays
+    }
+
+    // Ray-shape intersection tests against shapes of this type
+    pub fn intersection_tests(&self, shape: ShapeType) -> u64 {
+        match shape {
+            ShapeType::Sphere => self.sphere_tests,
+            ShapeType::Plane => self.plane_tests,
+        }
+    }
+
+    // Mean recursion depth per primary ray, 0 for a camera ray that only
+    // hit a matte surface or nothing
+    pub fn average_depth(&self) -> f64 {
+        if self.primary_rays == 0 {
+            0.
+        } else {
+            self.total_depth as f64 / self.primary_rays as f64
+        }
+    }
+
+    pub fn slowest_tile(&self) -> Option<&TileStats> {
+        self.tiles.iter().max_by_key(|t| t.time)
+    }
+
+    pub fn merge(&mut self, other: &RenderStats) {
+        self.primary_rays += other.primary_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.reflection_rays += other.reflection_rays;
+        self.refraction_rays += other.refraction_rays;
+        self.diffuse_rays += other.diffuse_rays;
+        self.occlusion_rays += other.occlusion_rays;
+        self.sphere_tests += other.sphere_tests;
+        self.plane_tests += other.plane_tests;
+        self.total_depth += other.total_depth;
+        self.max_depth = self.max_depth.max(other.max_depth);
+        self.tiles.extend_from_slice(&other.tiles);
+        self.tiles.sort_by_key(|t| (t.region.y, t.region.x));
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Rays: {}", self.rays())?;
+        writeln!(f, "  primary     {}", self.primary_rays)?;
+        writeln!(f, "  shadow      {}", self.shadow_rays)?;
+        writeln!(f, "  reflection  {}", self.reflection_rays)?;
+        writeln!(f, "  refraction  {}", self.refraction_rays)?;
+        writeln!(f, "  diffuse     {}", self.diffuse_rays)?;
+        writeln!(f, "  occlusion   {}", self.occlusion_rays)?;
+        writeln!(
+            f,
+            "Intersection tests: {}",
+            self.sphere_tests + self.plane_tests
+        )?;
+        writeln!(f, "  sphere      {}", self.sphere_tests)?;
+        writeln!(f, "  plane       {}", self.plane_tests)?;
+        write!(
+            f,
+            "Depth: {:.2} average, {} deepest",
+            self.average_depth(),
+            self.max_depth
+        )?;
+        if let Some(tile) = self.slowest_tile() {
+            let total: Duration = self.tiles.iter().map(|t| t.time).sum();
+            write!(
+                f,
+                "\nTiles: {}, {:.3}s average, slowest row {} at {:.3}s",
+                self.tiles.len(),
+                total.as_secs_f64() / self.tiles.len() as f64,
+                tile.region.y,
+                tile.time.as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    stats: RenderStats,
+    // Recursion level of the ray being traced, and the deepest level reached
+    // from the current primary ray, added to the total at the next one
+    level: u32,
+    deepest: u32,
+}
+
+impl Counters {
+    fn count(&mut self, kind: RayKind, level: u32) {
+        let s = &mut self.stats;
+        match kind {
+            RayKind::Primary => {
+                s.total_depth += u64::from(self.deepest);
+                s.primary_rays += 1;
+                self.deepest = 0;
+            }
+            RayKind::Shadow => s.shadow_rays += 1,
+            RayKind::Reflection => s.reflection_rays += 1,
+            RayKind::Refraction => s.refraction_rays += 1,
+            RayKind::Diffuse => s.diffuse_rays += 1,
+            RayKind::Occlusion => s.occlusion_rays += 1,
+        }
+        self.deepest = self.deepest.max(level);
+        s.max_depth = s.max_depth.max(level);
+    }
+}
+
+thread_local! {
+    // Checked before touching the counters, so that renders without
+    // statistics only pay for reading a flag on every ray and test
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    static COUNTERS: RefCell<Counters> = RefCell::new(Counters::default());
+}
+
+fn with_counters(f: impl FnOnce(&mut Counters)) {
+    if ENABLED.with(Cell::get) {
+        COUNTERS.with(|c| f(&mut c.borrow_mut()))
+    }
+}
+
+// Starts counting on this thread from zero
+pub(crate) fn start() {
+    COUNTERS.with(|c| *c.borrow_mut() = Counters::default());
+    ENABLED.with(|e| e.set(true));
+}
+
+// Stops counting on this thread and returns what was counted since `start`
+pub(crate) fn finish() -> RenderStats {
+    ENABLED.with(|e| e.set(false));
+    COUNTERS.with(|c| {
+        let mut c = std::mem::take(&mut *c.borrow_mut());
+        c.stats.total_depth += u64::from(c.deepest);
+        c.stats
+    })
+}
+
+pub(crate) fn add_tile(tile: TileStats) {
+    with_counters(|c| c.stats.tiles.push(tile))
+}
+
+pub(crate) fn count_intersection_test(shape: ShapeType) {
+    with_counters(|c| match shape {
+        ShapeType::Sphere => c.stats.sphere_tests += 1,
+        ShapeType::Plane => c.stats.plane_tests += 1,
+    })
+}
+
+// Counts a ray that is traced without recursing: camera and shadow rays at
+// level 0, path tracer bounces at the level of the bounce
+pub(crate) fn count_ray(kind: RayKind, level: u32) {
+    with_counters(|c| c.count(kind, level))
+}
+
+// Counts the ray of a recursive trace one level below the current one. The
+// level goes back up when the returned guard is dropped.
+pub(crate) fn descend(kind: RayKind) -> Level {
+    with_counters(|c| {
+        c.level += 1;
+        c.count(kind, c.level)
+    });
+    Level
+}
+
+pub(crate) struct Level;
+
+impl Drop for Level {
+    fn drop(&mut self) {
+        with_counters(|c| c.level = c.level.saturating_sub(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::Region;
+    use crate::shape::ShapeType;
+    use crate::stats::{RenderStats, TileStats};
+    use std::time::Duration;
+
+    fn tile(y: u32, millis: u64) -> TileStats {
+        TileStats {
+            region: Region {
+                x: 0,
+                y,
+                width: 10,
+                height: 1,
+            },
+            time: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn merging_adds_counts_and_keeps_tiles_in_order() {
+        let mut a = RenderStats {
+            primary_rays: 4,
+            shadow_rays: 3,
+            sphere_tests: 10,
+            total_depth: 2,
+            max_depth: 1,
+            tiles: vec![tile(1, 20)],
+            ..RenderStats::default()
+        };
+        let b = RenderStats {
+            primary_rays: 4,
+            reflection_rays: 6,
+            plane_tests: 5,
+            total_depth: 4,
+            max_depth: 3,
+            tiles: vec![tile(0, 10), tile(2, 30)],
+            ..RenderStats::default()
+        };
+        a.merge(&b);
+        assert_eq!(17, a.rays());
+        assert_eq!(10, a.intersection_tests(ShapeType::Sphere));
+        assert_eq!(5, a.intersection_tests(ShapeType::Plane));
+        assert_eq!(0.75, a.average_depth());
+        assert_eq!(3, a.max_depth);
+        let rows: Vec<u32> = a.tiles.iter().map(|t| t.region.y).collect();
+        assert_eq!(vec![0, 1, 2], rows);
+        assert_eq!(Some(&tile(2, 30)), a.slowest_tile());
+        assert_eq!(0., RenderStats::default().average_depth());
+    }
+
+    #[test]
+    fn report_lists_the_counters() {
+        let stats = RenderStats {
+            primary_rays: 4,
+            shadow_rays: 2,
+            sphere_tests: 12,
+            total_depth: 2,
+            max_depth: 1,
+            tiles: vec![tile(0, 10), tile(1, 30)],
+            ..RenderStats::default()
+        };
+        let report = stats.to_string();
+        assert!(report.starts_with("Rays: 6\n  primary     4\n  shadow      2\n"));
+        assert!(report.contains("Intersection tests: 12\n  sphere      12\n"));
+        assert!(report.contains("Depth: 0.50 average, 1 deepest"));
+        assert!(report.ends_with("Tiles: 2, 0.020s average, slowest row 1 at 0.030s"));
+    }
+}
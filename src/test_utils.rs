@@ -7,7 +7,7 @@ use crate::tuple::Tuple;
 
 #[cfg(test)]
 pub fn assert_near(v1: f64, v2: f64) {
-    assert!((v1 - v2).abs() < std::f64::EPSILON);
+    assert!((v1 - v2).abs() < f64::EPSILON);
 }
 
 #[cfg(test)]
@@ -16,7 +16,8 @@ pub fn assert_f64_near(v1: f64, v2: f64, tol: f64) {
 }
 
 #[cfg(test)]
-pub fn assert_tuple_near(t1: Tuple, t2: Tuple, tol: f64) {
+pub fn assert_tuple_near<A: Into<Tuple>, B: Into<Tuple>>(t1: A, t2: B, tol: f64) {
+    let (t1, t2) = (t1.into(), t2.into());
     assert!((t1.x - t2.x).abs() < tol);
     assert!((t1.y - t2.y).abs() < tol);
     assert!((t1.z - t2.z).abs() < tol);
@@ -34,7 +35,7 @@ pub fn assert_mat4x4_near(a: Mat4x4, b: Mat4x4) {
 
 #[cfg(test)]
 pub fn assert_color_eq(c1: Color, c2: Color) {
-    assert_color_near(c1, c2, std::f64::EPSILON);
+    assert_color_near(c1, c2, f64::EPSILON);
 }
 
 #[cfg(test)]
@@ -0,0 +1,191 @@
+use crate::color::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    // Values above 1 are clipped by the output quantization
+    Clamp,
+    // x / (1 + x), never reaches white
+    Reinhard,
+    // Reinhard scaled so that `white` maps to 1. White points below
+    // `MIN_WHITE`, including 0 and NaN, are raised to it.
+    ExtendedReinhard { white: f64 },
+    // Narkowicz's fit of the ACES filmic curve
+    AcesFilmic,
+}
+
+impl ToneMap {
+    pub const MIN_WHITE: f64 = 1e-6;
+
+    pub fn map(&self, v: f64) -> f64 {
+        let v = v.max(0.);
+        match *self {
+            ToneMap::Clamp => v,
+            ToneMap::Reinhard => v / (1. + v),
+            ToneMap::ExtendedReinhard { white } => {
+                let white = white.max(Self::MIN_WHITE);
+                v * (1. + v / (white * white)) / (1. + v)
+            }
+            ToneMap::AcesFilmic => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                ((v * (a * v + b)) / (v * (c * v + d) + e)).clamp(0., 1.)
+            }
+        }
+    }
+}
+
+// Converts linear scene colors to display values when a canvas is written to
+// a low dynamic range format: exposure, then tone mapping, then encoding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputTransform {
+    // In stops, each one doubles the brightness
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+    // Applies the sRGB transfer function, otherwise values are written linear
+    pub srgb: bool,
+}
+
+impl OutputTransform {
+    // sRGB encoding with ACES filmic tone mapping
+    pub fn filmic() -> Self {
+        OutputTransform {
+            exposure: 0.,
+            tone_map: ToneMap::AcesFilmic,
+            srgb: true,
+        }
+    }
+
+    // Clamped linear values, as written before output transforms existed
+    pub fn linear() -> Self {
+        OutputTransform {
+            srgb: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn apply(&self, c: Color) -> Color {
+        let scale = 2f64.powf(self.exposure);
+        let transform = |v: f64| {
+            let mapped = self.tone_map.map(v * scale);
+            if self.srgb {
+                srgb_encode(mapped)
+            } else {
+                mapped
+            }
+        };
+        Color::new(transform(c.r), transform(c.g), transform(c.b))
+    }
+}
+
+// Clamped values with sRGB encoding, so that written images look as bright
+// on a display as the scene is
+impl Default for OutputTransform {
+    fn default() -> Self {
+        OutputTransform {
+            exposure: 0.,
+            tone_map: ToneMap::Clamp,
+            srgb: true,
+        }
+    }
+}
+
+// sRGB opto-electronic transfer function, for values in [0, 1]
+pub fn srgb_encode(v: f64) -> f64 {
+    let v = v.clamp(0., 1.);
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_decode(v: f64) -> f64 {
+    let v = v.clamp(0., 1.);
+    if v <= 0.040_45 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::Color;
+    use crate::test_utils::{assert_color_near, assert_f64_near};
+    use crate::tonemap::{srgb_decode, srgb_encode, OutputTransform, ToneMap};
+
+    #[test]
+    fn linear_transform_is_identity() {
+        let c = Color::new(0.25, 1.5, -0.5);
+        assert_eq!(
+            Color::new(0.25, 1.5, 0.),
+            OutputTransform::linear().apply(c)
+        );
+    }
+
+    #[test]
+    fn default_transform_encodes_srgb() {
+        let c = Color::new(0.25, 1.5, -0.5);
+        assert_color_near(
+            Color::new(srgb_encode(0.25), 1., 0.),
+            OutputTransform::default().apply(c),
+            1e-12,
+        );
+    }
+
+    #[test]
+    fn srgb_transfer_function() {
+        assert_eq!(0., srgb_encode(0.));
+        assert_f64_near(1., srgb_encode(1.), 1e-12);
+        assert_f64_near(0.001 * 12.92, srgb_encode(0.001), 1e-12);
+        assert_f64_near(0.7354, srgb_encode(0.5), 1e-4);
+        assert_f64_near(0.214, srgb_decode(0.5), 1e-3);
+        for i in 0..=10 {
+            let v = i as f64 / 10.;
+            assert_f64_near(v, srgb_decode(srgb_encode(v)), 1e-9);
+        }
+    }
+
+    #[test]
+    fn tone_mapping_operators() {
+        assert_eq!(3., ToneMap::Clamp.map(3.));
+        assert_eq!(0.5, ToneMap::Reinhard.map(1.));
+        assert_eq!(0.75, ToneMap::Reinhard.map(3.));
+        assert_f64_near(1., ToneMap::ExtendedReinhard { white: 4. }.map(4.), 1e-12);
+        assert!(ToneMap::ExtendedReinhard { white: 4. }.map(1.) > ToneMap::Reinhard.map(1.));
+        assert_eq!(0., ToneMap::AcesFilmic.map(0.));
+        assert_f64_near(0.8, ToneMap::AcesFilmic.map(1.), 0.01);
+        assert_eq!(1., ToneMap::AcesFilmic.map(100.));
+        assert_eq!(0., ToneMap::Reinhard.map(-1.));
+    }
+
+    #[test]
+    fn extended_reinhard_without_a_positive_white_point_stays_finite() {
+        for white in &[0., -2., f64::NAN] {
+            let tone_map = ToneMap::ExtendedReinhard { white: *white };
+            assert_eq!(0., tone_map.map(0.));
+            assert!(tone_map.map(0.5).is_finite());
+            assert!(tone_map.map(0.5) > 1.);
+        }
+    }
+
+    #[test]
+    fn exposure_is_applied_before_tone_mapping() {
+        let t = OutputTransform {
+            exposure: 1.,
+            tone_map: ToneMap::Reinhard,
+            srgb: false,
+        };
+        assert_color_near(
+            Color::new(0.5, 0.75, 0.),
+            t.apply(Color::new(0.5, 1.5, 0.)),
+            1e-12,
+        );
+    }
+
+    #[test]
+    fn filmic_output_is_brighter_than_linear_in_the_midtones() {
+        let c = Color::new(0.18, 0.18, 0.18);
+        let out = OutputTransform::filmic().apply(c);
+        assert!(out.r > 0.18 && out.r < 1.);
+    }
+}
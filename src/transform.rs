@@ -1,4 +1,5 @@
 use crate::matrix::Mat4x4;
+use crate::tuple::{Point3, Vector3};
 
 pub fn translate(x: f64, y: f64, z: f64) -> Mat4x4 {
     let mut m = Mat4x4::identity();
@@ -52,9 +53,107 @@ pub fn skew(xy: f64, xz: f64, yx: f64, yz: f64, zx: f64, zy: f64) -> Mat4x4 {
     ])
 }
 
+// Rotation by `r` radians about `axis`, counterclockwise when looking
+// against the axis like `rotate_x`, `rotate_y` and `rotate_z`
+pub fn rotate(axis: Vector3, r: f64) -> Mat4x4 {
+    let a = axis.normalize();
+    let (sin, cos) = r.sin_cos();
+    let t = 1. - cos;
+    Mat4x4::new([
+        [
+            t * a.x * a.x + cos,
+            t * a.x * a.y - sin * a.z,
+            t * a.x * a.z + sin * a.y,
+            0.,
+        ],
+        [
+            t * a.x * a.y + sin * a.z,
+            t * a.y * a.y + cos,
+            t * a.y * a.z - sin * a.x,
+            0.,
+        ],
+        [
+            t * a.x * a.z - sin * a.y,
+            t * a.y * a.z + sin * a.x,
+            t * a.z * a.z + cos,
+            0.,
+        ],
+        [0., 0., 0., 1.],
+    ])
+}
+
+// Places an object at `from` with its +z axis pointing at `to` and its +y
+// axis as close to `up` as possible. Unlike `camera::view_transform`, which
+// moves the world in front of a camera, this moves the object itself. When
+// `up` is parallel to the viewing direction, or `from` is `to`, there is no
+// such axis and the matrix is made of NaN, which `Mat4x4::inverse` and so
+// `Shape::validate` reject.
+pub fn look_at(from: Point3, to: Point3, up: Vector3) -> Mat4x4 {
+    let forward = (to - from).normalize();
+    let right = up.cross(forward).normalize();
+    let true_up = forward.cross(right);
+    Mat4x4::new([
+        [right.x, true_up.x, forward.x, from.x],
+        [right.y, true_up.y, forward.y, from.y],
+        [right.z, true_up.z, forward.z, from.z],
+        [0., 0., 0., 1.],
+    ])
+}
+
+pub fn identity() -> Mat4x4 {
+    Mat4x4::identity()
+}
+
+// Chained transforms, applied in reading order:
+//
+//   identity().rotate_x(PI / 2.).scale(5., 5., 5.).translate(10., 5., 7.)
+//
+// equals `translate(..) * scale(..) * rotate_x(..)`.
+impl Mat4x4 {
+    // Applies `m` after this transform
+    pub fn then(self, m: Mat4x4) -> Mat4x4 {
+        m * self
+    }
+
+    pub fn translate(self, x: f64, y: f64, z: f64) -> Mat4x4 {
+        self.then(translate(x, y, z))
+    }
+
+    pub fn scale(self, x: f64, y: f64, z: f64) -> Mat4x4 {
+        self.then(scale(x, y, z))
+    }
+
+    pub fn rotate_x(self, r: f64) -> Mat4x4 {
+        self.then(rotate_x(r))
+    }
+
+    pub fn rotate_y(self, r: f64) -> Mat4x4 {
+        self.then(rotate_y(r))
+    }
+
+    pub fn rotate_z(self, r: f64) -> Mat4x4 {
+        self.then(rotate_z(r))
+    }
+
+    pub fn rotate(self, axis: Vector3, r: f64) -> Mat4x4 {
+        self.then(rotate(axis, r))
+    }
+
+    pub fn skew(self, xy: f64, xz: f64, yx: f64, yz: f64, zx: f64, zy: f64) -> Mat4x4 {
+        self.then(skew(xy, xz, yx, yz, zx, zy))
+    }
+
+    pub fn look_at(self, from: Point3, to: Point3, up: Vector3) -> Mat4x4 {
+        self.then(look_at(from, to, up))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::transform::{rotate_x, rotate_y, rotate_z, scale, skew, translate};
+    use crate::test_utils::assert_mat4x4_near;
+    use crate::transform::{
+        identity, look_at, rotate, rotate_x, rotate_y, rotate_z, scale, skew, translate,
+    };
     use crate::tuple::test_utils::assert_tuple_eq;
     use crate::tuple::{point, vector};
 
@@ -201,4 +300,54 @@ mod tests {
 
         assert_tuple_eq(point(15., 0., 7.), t * p);
     }
+
+    #[test]
+    fn fluent_transformations_apply_in_reading_order() {
+        let t = identity()
+            .rotate_x(std::f64::consts::PI / 2.)
+            .scale(5., 5., 5.)
+            .translate(10., 5., 7.);
+        assert_tuple_eq(point(15., 0., 7.), t * point(1., 0., 1.));
+        assert_mat4x4_near(
+            translate(10., 5., 7.) * scale(5., 5., 5.) * rotate_x(std::f64::consts::PI / 2.),
+            t,
+        );
+    }
+
+    #[test]
+    fn rotation_about_the_principal_axes() {
+        let r = 0.7;
+        assert_mat4x4_near(rotate_x(r), rotate(vector(1., 0., 0.), r));
+        assert_mat4x4_near(rotate_y(r), rotate(vector(0., 2., 0.), r));
+        assert_mat4x4_near(rotate_z(r), rotate(vector(0., 0., 1.), r));
+    }
+
+    #[test]
+    fn rotation_about_an_arbitrary_axis() {
+        // A third of a turn about the diagonal cycles the axes
+        let r = rotate(vector(1., 1., 1.), 2. * std::f64::consts::PI / 3.);
+        assert_tuple_eq(vector(0., 1., 0.), r * vector(1., 0., 0.));
+        assert_tuple_eq(vector(0., 0., 1.), r * vector(0., 1., 0.));
+        assert_tuple_eq(point(2., 2., 2.), r * point(2., 2., 2.));
+    }
+
+    #[test]
+    fn look_at_points_the_z_axis_at_the_target() {
+        let m = look_at(point(1., 2., 3.), point(1., 2., 10.), vector(0., 1., 0.));
+        assert_mat4x4_near(translate(1., 2., 3.), m);
+
+        let m = look_at(point(0., 0., 0.), point(5., 0., 0.), vector(0., 1., 0.));
+        assert_tuple_eq(vector(1., 0., 0.), m * vector(0., 0., 1.));
+        assert_tuple_eq(vector(0., 1., 0.), m * vector(0., 1., 0.));
+        assert_tuple_eq(vector(0., 0., -1.), m * vector(1., 0., 0.));
+    }
+
+    #[test]
+    fn look_at_along_up_cannot_be_inverted() {
+        let m = look_at(point(0., 0., 0.), point(0., 5., 0.), vector(0., 1., 0.));
+        assert!(m[0][0].is_nan());
+        assert!(m.inverse().is_err());
+        let m = look_at(point(1., 2., 3.), point(1., 2., 3.), vector(0., 1., 0.));
+        assert!(m.inverse().is_err());
+    }
 }
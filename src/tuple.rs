@@ -24,11 +24,11 @@ impl Tuple {
     }
 
     pub fn is_point(&self) -> bool {
-        (self.w - 1.0).abs() < std::f64::EPSILON
+        (self.w - 1.0).abs() < f64::EPSILON
     }
 
     pub fn is_vector(&self) -> bool {
-        self.w.abs() < std::f64::EPSILON
+        self.w.abs() < f64::EPSILON
     }
 
     pub fn magnitude(&self) -> f64 {
@@ -50,10 +50,11 @@ impl Tuple {
     }
 
     pub fn cross(&self, rhs: Tuple) -> Tuple {
-        vector(
+        Tuple::new(
             self.y * rhs.z - self.z * rhs.y,
             self.z * rhs.x - self.x * rhs.z,
             self.x * rhs.y - self.y * rhs.x,
+            0.,
         )
     }
 
@@ -139,19 +140,271 @@ impl Mul<Tuple> for Mat4x4 {
     }
 }
 
-pub fn point(x: f64, y: f64, z: f64) -> Tuple {
-    Tuple { x, y, z, w: 1.0 }
+// Points, vectors and normals are separate types so that only meaningful
+// operations compile: points can be moved by vectors and subtracted from each
+// other, but not added or normalized. Multiplying by a matrix treats points
+// as w = 1 and vectors as w = 0, so vectors ignore translation.
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Point3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Vector3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+// Unit length surface normal. Transform it with the inverse transpose of the
+// shape's transform.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Normal3 {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+pub fn point(x: f64, y: f64, z: f64) -> Point3 {
+    Point3 { x, y, z }
 }
 
-pub fn vector(x: f64, y: f64, z: f64) -> Tuple {
-    Tuple { x, y, z, w: 0.0 }
+pub fn vector(x: f64, y: f64, z: f64) -> Vector3 {
+    Vector3 { x, y, z }
+}
+
+impl Point3 {
+    pub fn new(x: f64, y: f64, z: f64) -> Self {
+        Point3 { x, y, z }
+    }
+
+    pub fn origin() -> Self {
+        Point3::new(0., 0., 0.)
+    }
+}
+
+impl Vector3 {
+    pub fn new(x: f64, y: f64, z: f64) -> Self {
+        Vector3 { x, y, z }
+    }
+
+    pub fn zero() -> Self {
+        Vector3::new(0., 0., 0.)
+    }
+
+    pub fn magnitude(&self) -> f64 {
+        self.dot(*self).sqrt()
+    }
+
+    pub fn normalize(&self) -> Vector3 {
+        *self / self.magnitude()
+    }
+
+    // Accepts normals too
+    pub fn dot<V: Into<Vector3>>(&self, rhs: V) -> f64 {
+        let rhs = rhs.into();
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
+    }
+
+    pub fn cross<V: Into<Vector3>>(&self, rhs: V) -> Vector3 {
+        let rhs = rhs.into();
+        vector(
+            self.y * rhs.z - self.z * rhs.y,
+            self.z * rhs.x - self.x * rhs.z,
+            self.x * rhs.y - self.y * rhs.x,
+        )
+    }
+
+    pub fn reflect<V: Into<Vector3>>(&self, normal: V) -> Vector3 {
+        let normal = normal.into();
+        *self - normal * 2. * self.dot(normal)
+    }
+}
+
+impl Normal3 {
+    // Normalizes the given direction
+    pub fn new(x: f64, y: f64, z: f64) -> Self {
+        Normal3::from_vector(vector(x, y, z))
+    }
+
+    pub fn from_vector(v: Vector3) -> Self {
+        let v = v.normalize();
+        Normal3 {
+            x: v.x,
+            y: v.y,
+            z: v.z,
+        }
+    }
+
+    pub fn x(&self) -> f64 {
+        self.x
+    }
+
+    pub fn y(&self) -> f64 {
+        self.y
+    }
+
+    pub fn z(&self) -> f64 {
+        self.z
+    }
+
+    pub fn to_vector(self) -> Vector3 {
+        vector(self.x, self.y, self.z)
+    }
+
+    pub fn dot<V: Into<Vector3>>(&self, rhs: V) -> f64 {
+        self.to_vector().dot(rhs)
+    }
+}
+
+impl From<Normal3> for Vector3 {
+    fn from(n: Normal3) -> Self {
+        n.to_vector()
+    }
+}
+
+impl From<Point3> for Tuple {
+    fn from(p: Point3) -> Self {
+        Tuple::new(p.x, p.y, p.z, 1.)
+    }
+}
+
+impl From<Vector3> for Tuple {
+    fn from(v: Vector3) -> Self {
+        Tuple::new(v.x, v.y, v.z, 0.)
+    }
+}
+
+impl From<Normal3> for Tuple {
+    fn from(n: Normal3) -> Self {
+        Tuple::new(n.x, n.y, n.z, 0.)
+    }
+}
+
+impl Add<Vector3> for Point3 {
+    type Output = Point3;
+
+    fn add(self, rhs: Vector3) -> Point3 {
+        point(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl Sub<Vector3> for Point3 {
+    type Output = Point3;
+
+    fn sub(self, rhs: Vector3) -> Point3 {
+        point(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Sub for Point3 {
+    type Output = Vector3;
+
+    fn sub(self, rhs: Point3) -> Vector3 {
+        vector(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Add for Vector3 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        vector(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl Sub for Vector3 {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        vector(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl Neg for Vector3 {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        vector(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Mul<f64> for Vector3 {
+    type Output = Self;
+
+    fn mul(self, rhs: f64) -> Self {
+        vector(self.x * rhs, self.y * rhs, self.z * rhs)
+    }
+}
+
+impl Div<f64> for Vector3 {
+    type Output = Self;
+
+    fn div(self, rhs: f64) -> Self {
+        vector(self.x / rhs, self.y / rhs, self.z / rhs)
+    }
+}
+
+impl Neg for Normal3 {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Normal3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+// A scaled normal is an offset, no longer a normal
+impl Mul<f64> for Normal3 {
+    type Output = Vector3;
+
+    fn mul(self, rhs: f64) -> Vector3 {
+        self.to_vector() * rhs
+    }
+}
+
+// Assumes an affine transform, the bottom row is not used
+impl Mul<Point3> for Mat4x4 {
+    type Output = Point3;
+
+    fn mul(self, p: Point3) -> Point3 {
+        let row = |r: usize| self[r][0] * p.x + self[r][1] * p.y + self[r][2] * p.z + self[r][3];
+        point(row(0), row(1), row(2))
+    }
+}
+
+impl Mul<Vector3> for Mat4x4 {
+    type Output = Vector3;
+
+    fn mul(self, v: Vector3) -> Vector3 {
+        let row = |r: usize| self[r][0] * v.x + self[r][1] * v.y + self[r][2] * v.z;
+        vector(row(0), row(1), row(2))
+    }
+}
+
+// Renormalizes the result
+impl Mul<Normal3> for Mat4x4 {
+    type Output = Normal3;
+
+    fn mul(self, n: Normal3) -> Normal3 {
+        Normal3::from_vector(self * n.to_vector())
+    }
 }
 
 #[cfg(test)]
 pub mod test_utils {
     use crate::tuple::Tuple;
 
-    pub fn assert_tuple_eq(t1: Tuple, t2: Tuple) {
+    // Points, vectors and normals compare as tuples, so a point never
+    // equals a vector
+    pub fn assert_tuple_eq<A: Into<Tuple>, B: Into<Tuple>>(t1: A, t2: B) {
+        let (t1, t2) = (t1.into(), t2.into());
         let eps = 0.00001;
         assert!((t1.x - t2.x).abs() < eps);
         assert!((t1.y - t2.y).abs() < eps);
@@ -165,7 +418,7 @@ mod tests {
     use crate::matrix::Mat4x4;
     use crate::test_utils::assert_near;
     use crate::tuple::test_utils::assert_tuple_eq;
-    use crate::tuple::{point, vector, Tuple};
+    use crate::tuple::{point, vector, Normal3, Tuple, Vector3};
 
     #[test]
     fn tuple_with_w1_is_a_point() {
@@ -206,7 +459,7 @@ mod tests {
             z: 3.0,
             w: 1.0,
         };
-        assert_eq!(t, p);
+        assert_eq!(t, Tuple::from(p));
     }
 
     #[test]
@@ -218,7 +471,7 @@ mod tests {
             z: 3.0,
             w: 0.0,
         };
-        assert_eq!(t, p);
+        assert_eq!(t, Tuple::from(p));
     }
 
     #[test]
@@ -378,15 +631,20 @@ mod tests {
     fn dot_product_of_two_vectors() {
         let a = vector(1.0, 2.0, 3.0);
         let b = vector(2.0, 3.0, 4.0);
-        assert_near(20.0, Tuple::dot(&a, b));
+        assert_near(20.0, a.dot(b));
+        assert_near(20.0, Tuple::from(a).dot(Tuple::from(b)));
     }
 
     #[test]
     fn cross_product_of_two_vectors() {
         let a = vector(1.0, 2.0, 3.0);
         let b = vector(2.0, 3.0, 4.0);
-        assert_tuple_eq(vector(-1.0, 2.0, -1.0), Tuple::cross(&a, b));
-        assert_tuple_eq(vector(1.0, -2.0, 1.0), Tuple::cross(&b, a));
+        assert_tuple_eq(vector(-1.0, 2.0, -1.0), a.cross(b));
+        assert_tuple_eq(vector(1.0, -2.0, 1.0), b.cross(a));
+        assert_tuple_eq(
+            vector(-1.0, 2.0, -1.0),
+            Tuple::from(a).cross(Tuple::from(b)),
+        );
     }
 
     #[test]
@@ -399,12 +657,47 @@ mod tests {
         ]);
         let b = point(1.0, 2.0, 3.0);
         assert_eq!(point(18.0, 24.0, 33.0), a * b);
+        assert_eq!(Tuple::new(18.0, 24.0, 33.0, 1.0), a * Tuple::from(b));
+    }
+
+    #[test]
+    fn vectors_are_not_translated() {
+        let m = Mat4x4::new([
+            [2.0, 0.0, 0.0, 5.0],
+            [0.0, 3.0, 0.0, 6.0],
+            [0.0, 0.0, 4.0, 7.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert_eq!(vector(2.0, 3.0, 4.0), m * vector(1.0, 1.0, 1.0));
+        assert_eq!(point(7.0, 9.0, 11.0), m * point(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn normals_stay_unit_length() {
+        let n = Normal3::new(3.0, 0.0, 4.0);
+        assert_tuple_eq(vector(0.6, 0.0, 0.8), n);
+        let m = Mat4x4::new([
+            [2.0, 0.0, 0.0, 5.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert_near(1.0, Vector3::from(m * n).magnitude());
+        assert_tuple_eq(vector(-1.2, 0.0, -1.6), -n * 2.0);
+    }
+
+    #[test]
+    fn points_move_by_vectors() {
+        let p = point(1.0, 2.0, 3.0);
+        let v = vector(1.0, 1.0, 1.0);
+        assert_eq!(point(2.0, 3.0, 4.0), p + v);
+        assert_eq!(v, (p + v) - p);
     }
 
     #[test]
     fn reflecting_a_vector_approaching_at_45_deg() {
         let v = vector(1., -1., 0.);
-        let n = vector(0., 1., 0.);
+        let n = Normal3::new(0., 1., 0.);
         assert_tuple_eq(vector(1., 1., 0.), v.reflect(n));
     }
 
@@ -0,0 +1,343 @@
+// Checks for scene settings that panic, fail or produce garbage images.
+// Errors make the image wrong; warnings are legal but usually a mistake.
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::lights::PointLight;
+use crate::matrix::Mat4x4;
+use crate::shape::{Shape, ShapeType};
+use crate::tuple::Point3;
+use std::f64::consts::PI;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// What a diagnostic is about. Shapes are named by their index in
+// `World::shapes` and their type.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Subject {
+    Shape(usize, ShapeType),
+    Light,
+    Camera,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub subject: Subject,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Subject::Shape(index, shape) => {
+                let name = match shape {
+                    ShapeType::Sphere => "sphere",
+                    ShapeType::Plane => "plane",
+                };
+                write!(f, "shape {} ({})", index, name)
+            }
+            Subject::Light => write!(f, "light"),
+            Subject::Camera => write!(f, "camera"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.subject, self.message)
+    }
+}
+
+struct Checker {
+    subject: Subject,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn new(subject: Subject) -> Self {
+        Checker {
+            subject,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn report(&mut self, severity: Severity, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            subject: self.subject,
+            message,
+        })
+    }
+
+    fn error(&mut self, message: String) {
+        self.report(Severity::Error, message)
+    }
+
+    fn warning(&mut self, message: String) {
+        self.report(Severity::Warning, message)
+    }
+
+    fn transform(&mut self, name: &str, m: &Mat4x4) {
+        if m.data.iter().flatten().any(|v| !v.is_finite()) {
+            self.error(format!("{} has entries that are not finite", name));
+        } else if m.inverse().is_err() {
+            self.error(format!("{} is not invertible", name));
+        }
+    }
+
+    // Inclusive range check for material coefficients
+    fn range(&mut self, name: &str, value: f64, min: f64, max: f64) {
+        if !(min..=max).contains(&value) {
+            self.error(format!("{} {} is outside [{}, {}]", name, value, min, max));
+        }
+    }
+
+    fn color(&mut self, name: &str, c: Color) {
+        if [c.r, c.g, c.b].iter().any(|v| !v.is_finite() || *v < 0.) {
+            self.error(format!(
+                "{} ({}, {}, {}) has negative or non-finite channels",
+                name, c.r, c.g, c.b
+            ));
+        }
+    }
+}
+
+pub(crate) fn check_shape(index: usize, shape: &Shape) -> Vec<Diagnostic> {
+    let mut check = Checker::new(Subject::Shape(index, shape.shape));
+    check.transform("transform", &shape.transform);
+    if let Some(end) = &shape.end_transform {
+        check.transform("end transform", end);
+    }
+
+    let m = &shape.material;
+    check.color("color", m.color);
+    check.range("ambient", m.ambient, 0., 1.);
+    check.range("diffuse", m.diffuse, 0., 1.);
+    check.range("specular", m.specular, 0., 1.);
+    check.range("reflective", m.reflective, 0., 1.);
+    check.range("transparency", m.transparency, 0., 1.);
+    if !(m.shininess > 0. && m.shininess.is_finite()) {
+        check.error(format!("shininess {} must be positive", m.shininess));
+    }
+    if !(m.refractive_index > 0. && m.refractive_index.is_finite()) {
+        check.error(format!(
+            "refractive index {} must be positive",
+            m.refractive_index
+        ));
+    } else if m.refractive_index < 1. && m.transparency > 0. {
+        check.warning(format!(
+            "refractive index {} is below that of vacuum",
+            m.refractive_index
+        ));
+    }
+    if m.reflective + m.transparency > 1. {
+        check.warning(format!(
+            "reflective {} and transparency {} add up to more than 1, the surface adds light",
+            m.reflective, m.transparency
+        ));
+    }
+    if let Some(pattern) = &m.pattern {
+        check.transform("pattern transform", &pattern.transform());
+    }
+    check.diagnostics
+}
+
+// Copies of a shape pushed onto `World::shapes` share its id, so lookups by
+// id, such as `World::shape` and shape animations, only find the first
+pub(crate) fn check_shape_ids(shapes: &[Shape]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (i, shape) in shapes.iter().enumerate() {
+        if let Some(first) = shapes[..i].iter().position(|s| s.id() == shape.id()) {
+            let mut check = Checker::new(Subject::Shape(i, shape.shape));
+            check.warning(format!(
+                "has the same id as shape {}, add copies with World::add",
+                first
+            ));
+            diagnostics.extend(check.diagnostics);
+        }
+    }
+    diagnostics
+}
+
+pub(crate) fn check_light(light: &PointLight, shapes: &[Shape]) -> Vec<Diagnostic> {
+    let mut check = Checker::new(Subject::Light);
+    check.color("intensity", light.intensity);
+    let p = light.position;
+    if [p.x, p.y, p.z].iter().any(|v| !v.is_finite()) {
+        check.error("position is not finite".to_string());
+        return check.diagnostics;
+    }
+    if light.intensity == Color::black() {
+        check.warning("intensity is black, only ambient light remains".to_string());
+    }
+    for (i, shape) in shapes.iter().enumerate() {
+        if let Some(inside) = light_inside(shape, p) {
+            if shape.material.transparency == 0. {
+                check.warning(format!(
+                    "position is {} shape {}, which shadows the whole scene",
+                    inside, i
+                ));
+            }
+        }
+    }
+    check.diagnostics
+}
+
+// Whether `p` is inside a sphere or on a plane, as a phrase for the message
+fn light_inside(shape: &Shape, p: Point3) -> Option<&'static str> {
+    let local = shape.transform.inverse().ok()? * p;
+    match shape.shape {
+        ShapeType::Sphere if local.x * local.x + local.y * local.y + local.z * local.z < 1. => {
+            Some("inside")
+        }
+        ShapeType::Plane if local.y.abs() < 1e-9 => Some("on"),
+        _ => None,
+    }
+}
+
+pub(crate) fn check_camera(camera: &Camera) -> Vec<Diagnostic> {
+    let mut check = Checker::new(Subject::Camera);
+    if camera.hsize == 0 || camera.vsize == 0 {
+        check.error(format!(
+            "image size {}x{} is empty",
+            camera.hsize, camera.vsize
+        ));
+    }
+    if !(camera.field_of_view > 0. && camera.field_of_view < PI) {
+        check.error(format!(
+            "field of view {} must be between 0 and pi radians",
+            camera.field_of_view
+        ));
+    }
+    check.transform("transform", &camera.transform);
+    if camera.shutter_close < camera.shutter_open {
+        check.error(format!(
+            "shutter closes at {} before it opens at {}",
+            camera.shutter_close, camera.shutter_open
+        ));
+    }
+    check.diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::Camera;
+    use crate::color::Color;
+    use crate::matrix::Mat4x4;
+    use crate::patterns::{Pattern, StripedPattern};
+    use crate::shape::{Shape, ShapeType};
+    use crate::transform::scale;
+    use crate::tuple::point;
+    use crate::validation::{Severity, Subject};
+    use crate::world::World;
+
+    #[test]
+    fn default_world_and_camera_are_valid() {
+        assert!(World::default().diagnostics().is_empty());
+        assert!(Camera::new(100, 50, 1.).diagnostics().is_empty());
+    }
+
+    #[test]
+    fn diagnostics_name_the_offending_shape() {
+        let mut w = World::default();
+        let mut plane = Shape::new(ShapeType::Plane);
+        plane.transform = scale(10., 0., 10.);
+        plane.material.refractive_index = 0.;
+        plane.material.transparency = 1.5;
+        w.shapes.push(plane);
+
+        let diagnostics = w.diagnostics();
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            vec![
+                "error: shape 2 (plane): transform is not invertible",
+                "error: shape 2 (plane): transparency 1.5 is outside [0, 1]",
+                "error: shape 2 (plane): refractive index 0 must be positive",
+                "warning: shape 2 (plane): reflective 0 and transparency 1.5 add up to more than 1, the surface adds light",
+            ],
+            messages
+        );
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.subject == Subject::Shape(2, ShapeType::Plane)));
+        assert!(diagnostics[0].is_error());
+    }
+
+    #[test]
+    fn copies_sharing_an_id_are_a_warning() {
+        let mut w = World::default();
+        w.shapes.push(w.shapes[1]);
+        let diagnostics = w.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(
+            "warning: shape 2 (sphere): has the same id as shape 1, add copies with World::add",
+            diagnostics[0].to_string()
+        );
+        w.shapes.pop();
+        w.add(w.shapes[1]);
+        assert!(w.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn pattern_transforms_are_checked() {
+        let mut w = World::new();
+        let mut s = Shape::new(ShapeType::Sphere);
+        let mut pattern = StripedPattern::new(Color::white(), Color::black());
+        pattern.transform = Mat4x4::zero();
+        s.material.pattern = Some(Pattern::Stripe(pattern));
+        w.shapes.push(s);
+        let diagnostics = w.diagnostics();
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(
+            "pattern transform is not invertible",
+            diagnostics[0].message
+        );
+    }
+
+    #[test]
+    fn light_inside_an_opaque_shape_is_a_warning() {
+        let mut w = World::default();
+        w.light.position = point(0., 0., 0.);
+        let diagnostics = w.diagnostics();
+        assert_eq!(2, diagnostics.len());
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.subject == Subject::Light && d.severity == Severity::Warning));
+        assert!(diagnostics[0].message.contains("inside shape 0"));
+
+        w.light.intensity = Color::new(-1., 1., 1.);
+        assert!(w.diagnostics()[0].is_error());
+    }
+
+    #[test]
+    fn camera_settings_are_checked() {
+        let mut c = Camera::new(0, 50, 4.);
+        c.transform = scale(0., 1., 1.);
+        c.shutter_open = 1.;
+        let messages: Vec<String> = c.diagnostics().iter().map(|d| d.message.clone()).collect();
+        assert_eq!(
+            vec![
+                "image size 0x50 is empty",
+                "field of view 4 must be between 0 and pi radians",
+                "transform is not invertible",
+                "shutter closes at 0 before it opens at 1",
+            ],
+            messages
+        );
+    }
+}
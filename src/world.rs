@@ -1,27 +1,39 @@
 use crate::color::Color;
+use crate::error::Error;
 use crate::intersections::{hit, Intersection};
 use crate::lights::PointLight;
 use crate::materials::Material;
+use crate::occlusion::AmbientOcclusion;
 use crate::ray::Ray;
-use crate::shape::{Shape, ShapeType};
+use crate::shape::{Shape, ShapeId, ShapeType};
+use crate::stats::{self, RayKind};
 use crate::transform::scale;
-use crate::tuple::{point, Tuple};
+use crate::tuple::{point, Normal3, Point3, Vector3};
+use crate::validation::{self, Diagnostic};
+use std::collections::HashMap;
 
+#[derive(Clone)]
 pub struct World {
     pub light: PointLight,
+    // Shapes pushed here directly keep their id, so copies of one shape
+    // share it; `add` gives every shape its own
     pub shapes: Vec<Shape>,
+    // When set, the ambient term is scaled by the unoccluded fraction at each hit
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    names: HashMap<String, ShapeId>,
 }
 
 pub struct Comps<'a> {
-    t: f64,
-    shape: &'a Shape,
-    point: Tuple,
-    over_point: Tuple,
-    under_point: Tuple,
-    eyev: Tuple,
-    normalv: Tuple,
-    reflectv: Tuple,
-    inside: bool,
+    pub t: f64,
+    pub time: f64,
+    pub shape: &'a Shape,
+    pub point: Point3,
+    pub over_point: Point3,
+    pub under_point: Point3,
+    pub eyev: Vector3,
+    pub normalv: Normal3,
+    pub reflectv: Vector3,
+    pub inside: bool,
     pub n1: f64,
     pub n2: f64,
 }
@@ -30,31 +42,150 @@ impl<'a> Comps<'a> {
     const OVER_POINT_EPSILON: f64 = 0.000_000_1;
 }
 
+// The first surface along a ray, see `World::pick`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pick {
+    // Index into `World::shapes`
+    pub shape_index: usize,
+    // Distance from the ray origin in world units
+    pub distance: f64,
+    pub point: Point3,
+    // Surface normal, flipped to face the ray origin when it is inside the shape
+    pub normal: Normal3,
+    pub inside: bool,
+    pub material: Material,
+}
+
 impl World {
     pub fn new() -> Self {
         World {
             light: PointLight::new(Color::new(1., 1., 1.), point(-10., 10., -10.)),
             shapes: Vec::new(),
+            ambient_occlusion: None,
+            names: HashMap::new(),
         }
     }
-    pub fn color_at(&self, ray: Ray, remaining: i8) -> Color {
-        let intersections = self.intersect(ray);
 
-        match hit(self.intersect(ray)) {
-            Some(i) => self.shade_hit(
-                Self::prepare_computations_with_intersections(i, ray, intersections),
-                remaining,
-            ),
+    // Adds the shape, with a new id if a shape in the world already has its
+    // id, and returns the id
+    pub fn add(&mut self, mut shape: Shape) -> ShapeId {
+        if self.index_of(shape.id).is_some() {
+            shape.id = ShapeId::next();
+        }
+        self.shapes.push(shape);
+        shape.id
+    }
+
+    pub fn add_named(&mut self, name: &str, shape: Shape) -> ShapeId {
+        let id = self.add(shape);
+        self.set_name(id, name);
+        id
+    }
+
+    // Names are unique: naming a shape takes the name from any shape that
+    // had it, and replaces the shape's previous name
+    pub fn set_name(&mut self, id: ShapeId, name: &str) {
+        self.names.retain(|_, named| *named != id);
+        self.names.insert(name.to_string(), id);
+    }
+
+    pub fn name(&self, id: ShapeId) -> Option<&str> {
+        self.names
+            .iter()
+            .find(|(_, named)| **named == id)
+            .map(|(name, _)| name.as_str())
+    }
+
+    // Position in `shapes` of the shape with this id
+    pub fn index_of(&self, id: ShapeId) -> Option<usize> {
+        self.shapes.iter().position(|s| s.id == id)
+    }
+
+    pub fn shape(&self, id: ShapeId) -> Option<&Shape> {
+        self.shapes.iter().find(|s| s.id == id)
+    }
+
+    pub fn shape_mut(&mut self, id: ShapeId) -> Option<&mut Shape> {
+        self.shapes.iter_mut().find(|s| s.id == id)
+    }
+
+    pub fn shape_named(&self, name: &str) -> Option<&Shape> {
+        self.shape(*self.names.get(name)?)
+    }
+
+    pub fn shape_named_mut(&mut self, name: &str) -> Option<&mut Shape> {
+        let id = *self.names.get(name)?;
+        self.shape_mut(id)
+    }
+
+    // Removes the shape and its name. Later shapes move down one index.
+    pub fn remove(&mut self, id: ShapeId) -> Option<Shape> {
+        let index = self.index_of(id)?;
+        self.names.retain(|_, named| *named != id);
+        Some(self.shapes.remove(index))
+    }
+
+    pub fn remove_named(&mut self, name: &str) -> Option<Shape> {
+        let id = *self.names.get(name)?;
+        self.remove(id)
+    }
+    // Fails on the first shape that `Shape::validate` rejects
+    pub fn validate(&self) -> Result<(), Error> {
+        self.shapes.iter().try_for_each(|s| s.validate())
+    }
+
+    // Every problem found in the shapes and the light, see `validation`
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = self
+            .shapes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, s)| validation::check_shape(i, s))
+            .collect();
+        diagnostics.extend(validation::check_shape_ids(&self.shapes));
+        diagnostics.extend(validation::check_light(&self.light, &self.shapes));
+        diagnostics
+    }
+
+    pub fn color_at(&self, ray: Ray, remaining: i8) -> Color {
+        match self.first_hit(ray) {
+            Some(comps) => self.shade_hit(comps, remaining),
             None => Color::new(0., 0., 0.),
         }
     }
 
-    fn intersect(&self, ray: Ray) -> Vec<Intersection> {
+    pub(crate) fn first_hit(&self, ray: Ray) -> Option<Comps<'_>> {
+        let intersections = self.intersect(ray);
+        hit(intersections.clone())
+            .map(|i| Self::prepare_computations_with_intersections(i, ray, intersections))
+    }
+
+    // The shape the ray hits first, for selecting shapes in an editor
+    pub fn pick(&self, ray: Ray) -> Option<Pick> {
+        let comps = self.first_hit(ray)?;
+        Some(Pick {
+            shape_index: self.shape_index(comps.shape)?,
+            distance: comps.t * ray.direction.magnitude(),
+            point: comps.point,
+            normal: comps.normalv,
+            inside: comps.inside,
+            material: comps.shape.material,
+        })
+    }
+
+    // Index into `shapes` of a shape reference obtained from this world
+    pub fn shape_index(&self, shape: &Shape) -> Option<usize> {
+        self.shapes.iter().position(|s| std::ptr::eq(s, shape))
+    }
+
+    pub(crate) fn intersect(&self, ray: Ray) -> Vec<Intersection<'_>> {
         let mut xs = Vec::new();
         for shape in self.shapes.iter() {
             xs.append(&mut shape.intersect(ray));
         }
-        xs.sort_by(|x, y| x.t.partial_cmp(&y.t).unwrap());
+        // Rays with NaN components, e.g. from a degenerate view, hit nothing
+        xs.retain(|x| !x.t.is_nan());
+        xs.sort_by(|x, y| x.t.total_cmp(&y.t));
         xs
     }
 
@@ -63,7 +194,7 @@ impl World {
         let shape = intersection.shape;
         let point = ray.position(intersection.t);
         let eyev = -ray.direction;
-        let mut normalv = shape.normal(point);
+        let mut normalv = shape.at_time(ray.time).normal(point);
         let over_point = point + normalv * Comps::OVER_POINT_EPSILON;
         let under_point = point - normalv * Comps::OVER_POINT_EPSILON;
         let inside = if normalv.dot(eyev) < 0. {
@@ -75,6 +206,7 @@ impl World {
         let reflectv = ray.direction.reflect(normalv);
         Comps {
             t,
+            time: ray.time,
             shape,
             point,
             over_point,
@@ -93,7 +225,7 @@ impl World {
         ray: Ray,
         intersections: Vec<Intersection>,
     ) -> Comps<'a> {
-        let mut containers: Vec<Shape> = vec![];
+        let mut containers: Vec<&Shape> = vec![];
         let mut n1: f64 = 1.0;
         let mut n2: f64 = 1.0;
 
@@ -106,11 +238,11 @@ impl World {
                 }
             }
 
-            let shape_index = containers.iter().position(|&s| s == *i.shape);
+            let shape_index = containers.iter().position(|s| std::ptr::eq(*s, i.shape));
             if let Some(found_index) = shape_index {
                 containers.remove(found_index);
             } else {
-                containers.push(*i.shape);
+                containers.push(i.shape);
             }
 
             if i == intersection {
@@ -129,11 +261,16 @@ impl World {
         comps
     }
 
-    fn shade_hit(&self, comps: Comps, remaining: i8) -> Color {
-        let shadowed = self.is_shadowed(comps.over_point);
+    pub(crate) fn shade_hit(&self, comps: Comps, remaining: i8) -> Color {
+        let shadowed = self.is_shadowed(comps.over_point, comps.time);
+        let mut material = comps.shape.material;
+        if let Some(ao) = self.ambient_occlusion {
+            material.ambient *=
+                ao.unoccluded_fraction(self, comps.over_point, comps.normalv, comps.time);
+        }
         let surface = Material::lighting(
-            comps.shape.material,
-            comps.shape,
+            material,
+            &comps.shape.at_time(comps.time),
             self.light,
             comps.over_point,
             comps.eyev,
@@ -152,10 +289,11 @@ impl World {
         }
     }
 
-    fn is_shadowed(&self, p: Tuple) -> bool {
+    pub(crate) fn is_shadowed(&self, p: Point3, time: f64) -> bool {
         let direction = self.light.position - p;
         let distance = direction.magnitude();
-        let ray = Ray::new(p, direction.normalize());
+        let ray = Ray::with_time(p, direction.normalize(), time);
+        stats::count_ray(RayKind::Shadow, 0);
         match hit(self.intersect(ray)) {
             Some(i) => i.t < distance,
             None => false,
@@ -164,7 +302,8 @@ impl World {
 
     fn reflected_color(&self, comps: &Comps, remaining: i8) -> Color {
         if remaining > 0 && comps.shape.material.reflective > 0. {
-            let r = Ray::new(comps.over_point, comps.reflectv);
+            let r = Ray::with_time(comps.over_point, comps.reflectv, comps.time);
+            let _level = stats::descend(RayKind::Reflection);
             self.color_at(r, remaining - 1) * comps.shape.material.reflective
         } else {
             Color::black()
@@ -184,7 +323,8 @@ impl World {
 
         let cos_t = (1. - sin2_t).sqrt();
         let direction = comps.normalv * (n_ratio * cos_i - cos_t) - comps.eyev * n_ratio;
-        let refract_ray = Ray::new(comps.under_point, direction);
+        let refract_ray = Ray::with_time(comps.under_point, direction, comps.time);
+        let _level = stats::descend(RayKind::Refraction);
         self.color_at(refract_ray, remaining - 1) * comps.shape.material.transparency
     }
 
@@ -207,10 +347,7 @@ impl World {
 
 impl Default for World {
     fn default() -> Self {
-        let mut w = World {
-            light: PointLight::new(Color::new(1., 1., 1.), point(-10., 10., -10.)),
-            shapes: Vec::new(),
-        };
+        let mut w = World::new();
 
         let mut s1 = Shape::new(ShapeType::Sphere);
         s1.material.color = Color::new(0.8, 1.0, 0.6);
@@ -234,13 +371,14 @@ mod tests {
     use crate::lights::PointLight;
     use crate::materials::Material;
     use crate::matrix::Mat4x4;
+    use crate::occlusion::AmbientOcclusion;
     use crate::patterns::{Pattern, StripedPattern};
     use crate::ray::Ray;
     use crate::shape::glass_sphere;
     use crate::shape::{Shape, ShapeType};
     use crate::test_utils::assert_color_near;
-    use crate::transform::{scale, translate};
-    use crate::tuple::{point, vector};
+    use crate::transform::{rotate_x, scale, translate};
+    use crate::tuple::{point, vector, Normal3};
     use crate::world::{Comps, World};
 
     #[test]
@@ -275,6 +413,94 @@ mod tests {
         assert_eq!(6., xs[3].t);
     }
 
+    #[test]
+    fn rays_with_nan_components_hit_nothing() {
+        let w = World::default();
+        let r = Ray::new(point(0., 0., -5.), vector(f64::NAN, 0., 1.));
+        assert!(w.intersect(r).is_empty());
+        assert_eq!(Color::black(), w.color_at(r, 5));
+    }
+
+    #[test]
+    fn shape_index_finds_shapes_by_reference() {
+        let w = World::default();
+        assert_eq!(Some(1), w.shape_index(&w.shapes[1]));
+        let copy = w.shapes[1];
+        assert_eq!(None, w.shape_index(&copy));
+    }
+
+    #[test]
+    fn picking_the_first_shape_along_a_ray() {
+        let w = World::default();
+        let pick = w
+            .pick(Ray::new(point(0., 0., -5.), vector(0., 0., 2.)))
+            .unwrap();
+        assert_eq!(0, pick.shape_index);
+        assert_eq!(4., pick.distance);
+        assert_eq!(point(0., 0., -1.), pick.point);
+        assert_eq!(Normal3::new(0., 0., -1.), pick.normal);
+        assert!(!pick.inside);
+        assert_eq!(w.shapes[0].material, pick.material);
+
+        let inside = w.pick(Ray::new(point(0., 0., 0.25), vector(0., 0., -1.)));
+        assert_eq!(Some(1), inside.map(|p| p.shape_index));
+        assert!(inside.unwrap().inside);
+        assert_eq!(
+            None,
+            w.pick(Ray::new(point(0., 2., -5.), vector(0., 0., 1.)))
+        );
+    }
+
+    #[test]
+    fn shapes_are_found_and_removed_by_name() {
+        let mut w = World::default();
+        let floor = w.add_named("floor", Shape::new(ShapeType::Plane));
+        let ball = w.add_named("ball", w.shapes[0]);
+        assert_ne!(w.shapes[0].id(), ball);
+        assert_eq!(Some(3), w.index_of(ball));
+        assert_eq!(Some("floor"), w.name(floor));
+
+        w.shape_named_mut("ball").unwrap().material.reflective = 0.5;
+        assert_eq!(0.5, w.shape(ball).unwrap().material.reflective);
+        assert_eq!(0., w.shapes[0].material.reflective);
+
+        w.set_name(ball, "floor");
+        assert_eq!(None, w.name(floor));
+        assert_eq!(None, w.shape_named("ball"));
+        assert_eq!(Some(ball), w.shape_named("floor").map(|s| s.id()));
+
+        assert_eq!(
+            Some(ShapeType::Sphere),
+            w.remove_named("floor").map(|s| s.shape)
+        );
+        assert_eq!(None, w.shape(ball));
+        assert_eq!(Some(2), w.index_of(floor));
+        assert!(w.remove(ball).is_none());
+    }
+
+    #[test]
+    fn identical_overlapping_glass_spheres_are_told_apart() {
+        let glass = glass_sphere();
+        let mut added = World::new();
+        added.add(glass);
+        added.add(glass);
+        // Copies pushed directly share their id
+        let mut pushed = World::new();
+        pushed.shapes.push(glass);
+        pushed.shapes.push(glass);
+        for w in &[added, pushed] {
+            let r = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
+            let xs = w.intersect(r);
+            assert_eq!(4, xs.len());
+            let comps = World::prepare_computations_with_intersections(xs[1], r, xs.clone());
+            assert_eq!((1.5, 1.5), (comps.n1, comps.n2));
+            let comps = World::prepare_computations_with_intersections(xs[2], r, xs.clone());
+            assert_eq!((1.5, 1.5), (comps.n1, comps.n2));
+            let comps = World::prepare_computations_with_intersections(xs[3], r, xs);
+            assert_eq!((1.5, 1.), (comps.n1, comps.n2));
+        }
+    }
+
     #[test]
     fn precomputing_the_state_of_an_intersection() {
         let mut w = World::new();
@@ -287,7 +513,7 @@ mod tests {
         assert_eq!(c.shape, &s);
         assert_eq!(c.point, point(0., 0., -1.));
         assert_eq!(c.eyev, vector(0., 0., -1.));
-        assert_eq!(c.normalv, vector(0., 0., -1.));
+        assert_eq!(c.normalv, Normal3::new(0., 0., -1.));
     }
 
     #[test]
@@ -298,7 +524,7 @@ mod tests {
         let r = Ray::new(point(0., 0., -5.), vector(0., 0., 1.));
         let i = Intersection::new(4., &s);
         let c = World::prepare_computations(i, r);
-        assert_eq!(c.inside, false);
+        assert!(!c.inside);
     }
 
     #[test]
@@ -311,8 +537,8 @@ mod tests {
         let c = World::prepare_computations(i, r);
         assert_eq!(c.point, point(0., 0., 1.));
         assert_eq!(c.eyev, vector(0., 0., -1.));
-        assert_eq!(c.inside, true);
-        assert_eq!(c.normalv, vector(0., 0., -1.));
+        assert!(c.inside);
+        assert_eq!(c.normalv, Normal3::new(0., 0., -1.));
     }
 
     #[test]
@@ -349,8 +575,10 @@ mod tests {
 
     #[test]
     fn shading_an_intersection_from_the_inside() {
-        let mut w = World::default();
-        w.light = PointLight::new(Color::new(1., 1., 1.), point(0., 0.25, 0.));
+        let w = World {
+            light: PointLight::new(Color::new(1., 1., 1.), point(0., 0.25, 0.)),
+            ..World::default()
+        };
         let r = Ray::new(point(0., 0., 0.), vector(0., 0., 1.));
         let i = Intersection::new(0.5, &w.shapes[1]);
         let com = World::prepare_computations(i, r);
@@ -360,8 +588,10 @@ mod tests {
 
     #[test]
     fn interection_in_shadow() {
-        let mut w = World::default();
-        w.light = PointLight::new(Color::white(), point(0., 0., -10.));
+        let mut w = World {
+            light: PointLight::new(Color::white(), point(0., 0., -10.)),
+            ..World::default()
+        };
         w.shapes[1].transform = w.shapes[1].transform * translate(0., 0., 10.);
         let r = Ray::new(point(0., 0., 5.), vector(0., 0., 1.));
         let i = Intersection::new(4., &w.shapes[1]);
@@ -369,6 +599,24 @@ mod tests {
         assert_eq!(Color::new(0.1, 0.1, 0.1), w.shade_hit(comps, 5));
     }
 
+    #[test]
+    fn ambient_occlusion_darkens_the_ambient_term() {
+        let mut w = World::default();
+        w.shapes[0].material.ambient = 1.;
+        w.shapes[0].material.diffuse = 0.;
+        w.shapes[0].material.specular = 0.;
+        let mut wall = Shape::new(ShapeType::Plane);
+        wall.transform = translate(0., 0., -1.5) * rotate_x(std::f64::consts::PI / 2.);
+        w.shapes.push(wall);
+        let r = Ray::new(point(0., 0., -1.2), vector(0., 0., 1.));
+
+        let open = w.color_at(r, 5);
+        w.ambient_occlusion = Some(AmbientOcclusion::new(64, 1.));
+        let occluded = w.color_at(r, 5);
+        assert_eq!(Color::new(0.8, 1.0, 0.6), open);
+        assert!(occluded.g > 0. && occluded.g < open.g);
+    }
+
     #[test]
     fn the_color_when_a_ray_misses() {
         let w = World::default();
@@ -398,25 +646,49 @@ mod tests {
     #[test]
     fn no_shadow_when_nothing_is_collinear_with_point_and_light() {
         let w = World::default();
-        assert_eq!(false, w.is_shadowed(point(0., 10., 0.)));
+        assert!(!w.is_shadowed(point(0., 10., 0.), 0.));
     }
 
     #[test]
     fn shadow_when_an_object_is_between_the_point_and_the_light() {
         let w = World::default();
-        assert_eq!(true, w.is_shadowed(point(10., -10., 10.)));
+        assert!(w.is_shadowed(point(10., -10., 10.), 0.));
     }
 
     #[test]
     fn no_shadow_when_an_object_is_behind_the_light() {
         let w = World::default();
-        assert_eq!(false, w.is_shadowed(point(-20., 20., -20.)));
+        assert!(!w.is_shadowed(point(-20., 20., -20.), 0.));
     }
 
     #[test]
     fn no_shadow_when_an_object_is_behind_the_point() {
         let w = World::default();
-        assert_eq!(false, w.is_shadowed(point(-2., 2., -2.)));
+        assert!(!w.is_shadowed(point(-2., 2., -2.), 0.));
+    }
+
+    #[test]
+    fn shadow_follows_a_moving_object() {
+        let mut w = World::default();
+        w.shapes[0].end_transform = Some(translate(0., 20., 0.));
+        w.shapes[1].end_transform = Some(translate(0., 20., 0.));
+        assert!(w.is_shadowed(point(10., -10., 10.), 0.));
+        assert!(!w.is_shadowed(point(10., -10., 10.), 1.));
+    }
+
+    #[test]
+    fn color_at_samples_moving_shape_at_ray_time() {
+        let mut w = World::default();
+        w.shapes[0].end_transform = Some(translate(0., 20., 0.));
+        w.shapes[1].end_transform = Some(translate(0., 20., 0.));
+        let r0 = Ray::with_time(point(0., 0., -5.), vector(0., 0., 1.), 0.);
+        let r1 = Ray::with_time(point(0., 0., -5.), vector(0., 0., 1.), 1.);
+        assert_color_near(
+            w.color_at(r0, 5),
+            Color::new(0.38066, 0.47583, 0.2855),
+            0.0001,
+        );
+        assert_eq!(Color::black(), w.color_at(r1, 5));
     }
 
     #[test]
@@ -499,8 +771,10 @@ mod tests {
 
     #[test]
     fn color_at_with_mutually_reflective_surfaces() {
-        let mut w = World::default();
-        w.light = PointLight::new(Color::white(), point(0., 0., 0.));
+        let mut w = World {
+            light: PointLight::new(Color::white(), point(0., 0., 0.)),
+            ..World::default()
+        };
 
         let mut lower_plane = Shape::new(ShapeType::Plane);
         lower_plane.material.reflective = 1.;
@@ -516,7 +790,6 @@ mod tests {
 
         // Test that call to color_at() does not end up in an infinite recursion.
         w.color_at(r, 5);
-        assert!(true);
     }
 
     #[test]
@@ -0,0 +1,416 @@
+// Parser for the subset of YAML used by scene files: block mappings and
+// sequences, flow sequences and mappings, plain and quoted scalars, and
+// comments. Anchors, tags and multi-line scalars are not supported.
+use crate::scene::{SceneError, MAX_NESTING};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Scalar(String),
+    Sequence(Vec<Node>),
+    Mapping(Vec<(String, Node)>),
+}
+
+// A value and the line (1-based) it starts on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub value: Value,
+    pub line: usize,
+}
+
+impl Node {
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        match &self.value {
+            Value::Mapping(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+struct Line {
+    number: usize,
+    indent: usize,
+    content: String,
+}
+
+pub fn parse(source: &str) -> Result<Node, SceneError> {
+    let mut lines = Vec::new();
+    for (i, raw) in source.lines().enumerate() {
+        let content = strip_comment(raw).trim_end();
+        let trimmed = content.trim_start();
+        if trimmed.is_empty() || trimmed == "---" {
+            continue;
+        }
+        if content.starts_with('\t') {
+            return Err(SceneError::new(
+                i + 1,
+                "Tabs are not allowed for indentation",
+            ));
+        }
+        lines.push(Line {
+            number: i + 1,
+            indent: content.len() - trimmed.len(),
+            content: trimmed.to_string(),
+        });
+    }
+    if lines.is_empty() {
+        return Ok(Node {
+            value: Value::Sequence(Vec::new()),
+            line: 1,
+        });
+    }
+
+    let mut pos = 0;
+    let indent = lines[0].indent;
+    let node = parse_block(&mut lines, &mut pos, indent)?;
+    if pos < lines.len() {
+        return Err(SceneError::new(lines[pos].number, "Unexpected indentation"));
+    }
+    Ok(node)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &line[..i],
+            None => {}
+        }
+        previous = c;
+    }
+    line
+}
+
+fn is_sequence_item(content: &str) -> bool {
+    content == "-" || content.starts_with("- ")
+}
+
+// Splits `key: value` at the first colon followed by a space or the end of
+// the line, outside of quotes and flow collections.
+fn split_key(content: &str) -> Option<(String, &str)> {
+    if content.starts_with('[') || content.starts_with('{') {
+        return None;
+    }
+    let mut quote = None;
+    for (i, c) in content.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ':' => {
+                let rest = &content[i + 1..];
+                if rest.is_empty() || rest.starts_with(' ') {
+                    return Some((unquote(content[..i].trim()), rest.trim()));
+                }
+            }
+            None => {}
+        }
+    }
+    None
+}
+
+fn unquote(s: &str) -> String {
+    let quoted = s.len() >= 2
+        && ((s.starts_with('"') && s.ends_with('"')) || (s.starts_with('\'') && s.ends_with('\'')));
+    if quoted {
+        s[1..s.len() - 1].to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+fn parse_block(lines: &mut [Line], pos: &mut usize, indent: usize) -> Result<Node, SceneError> {
+    let line = lines[*pos].number;
+    if is_sequence_item(&lines[*pos].content) {
+        let mut items = Vec::new();
+        while *pos < lines.len()
+            && lines[*pos].indent == indent
+            && is_sequence_item(&lines[*pos].content)
+        {
+            let rest = lines[*pos].content[1..].trim_start().to_string();
+            if rest.is_empty() {
+                *pos += 1;
+                items.push(parse_nested(lines, pos, indent, lines[*pos - 1].number)?);
+            } else if split_key(&rest).is_some() {
+                // A mapping starting on the same line as the dash continues at
+                // the column of its first key
+                let offset = lines[*pos].content.len() - rest.len();
+                lines[*pos].indent += offset;
+                lines[*pos].content = rest;
+                let child_indent = lines[*pos].indent;
+                items.push(parse_block(lines, pos, child_indent)?);
+            } else {
+                let number = lines[*pos].number;
+                items.push(parse_flow(&rest, number)?);
+                *pos += 1;
+            }
+        }
+        return Ok(Node {
+            value: Value::Sequence(items),
+            line,
+        });
+    }
+
+    if split_key(&lines[*pos].content).is_none() {
+        // A lone scalar or flow collection
+        let node = parse_flow(&lines[*pos].content, line)?;
+        *pos += 1;
+        return Ok(node);
+    }
+
+    let mut entries: Vec<(String, Node)> = Vec::new();
+    while *pos < lines.len() && lines[*pos].indent == indent {
+        let number = lines[*pos].number;
+        if is_sequence_item(&lines[*pos].content) {
+            return Err(SceneError::new(
+                number,
+                "Expected a key, found a sequence item",
+            ));
+        }
+        let (key, rest) = split_key(&lines[*pos].content)
+            .ok_or_else(|| SceneError::new(number, "Expected 'key: value'"))?;
+        let rest = rest.to_string();
+        if entries.iter().any(|(k, _)| *k == key) {
+            return Err(SceneError::with_key(number, &key, "Duplicate key"));
+        }
+        *pos += 1;
+        let value = if rest.is_empty() {
+            // Sequences may be indented at the same level as their key
+            if *pos < lines.len()
+                && lines[*pos].indent == indent
+                && is_sequence_item(&lines[*pos].content)
+            {
+                parse_block(lines, pos, indent)?
+            } else {
+                parse_nested(lines, pos, indent, number).map_err(|e| e.or_key(&key))?
+            }
+        } else {
+            parse_flow(&rest, number).map_err(|e| e.or_key(&key))?
+        };
+        entries.push((key, value));
+    }
+    Ok(Node {
+        value: Value::Mapping(entries),
+        line,
+    })
+}
+
+// The block indented below a line ending in `:` or `-`
+fn parse_nested(
+    lines: &mut [Line],
+    pos: &mut usize,
+    indent: usize,
+    line: usize,
+) -> Result<Node, SceneError> {
+    if *pos >= lines.len() || lines[*pos].indent <= indent {
+        return Err(SceneError::new(line, "Missing value"));
+    }
+    let child_indent = lines[*pos].indent;
+    parse_block(lines, pos, child_indent)
+}
+
+pub fn parse_flow(text: &str, line: usize) -> Result<Node, SceneError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut pos = 0;
+    let node = flow_value(&chars, &mut pos, line, 0)?;
+    skip_spaces(&chars, &mut pos);
+    if pos < chars.len() {
+        return Err(SceneError::new(
+            line,
+            &format!("Unexpected '{}'", chars[pos..].iter().collect::<String>()),
+        ));
+    }
+    Ok(node)
+}
+
+fn skip_spaces(chars: &[char], pos: &mut usize) {
+    while *pos < chars.len() && chars[*pos].is_whitespace() {
+        *pos += 1;
+    }
+}
+
+// depth: flow collections around the value
+fn flow_value(
+    chars: &[char],
+    pos: &mut usize,
+    line: usize,
+    depth: usize,
+) -> Result<Node, SceneError> {
+    skip_spaces(chars, pos);
+    if depth >= MAX_NESTING && matches!(chars.get(*pos), Some('[') | Some('{')) {
+        return Err(SceneError::new(
+            line,
+            "Flow collections are nested too deeply",
+        ));
+    }
+    let value = match chars.get(*pos) {
+        Some('[') => {
+            *pos += 1;
+            let mut items = Vec::new();
+            flow_items(chars, pos, line, ']', |chars, pos| {
+                items.push(flow_value(chars, pos, line, depth + 1)?);
+                Ok(())
+            })?;
+            Value::Sequence(items)
+        }
+        Some('{') => {
+            *pos += 1;
+            let mut entries = Vec::new();
+            flow_items(chars, pos, line, '}', |chars, pos| {
+                let key = flow_scalar(chars, pos, true);
+                skip_spaces(chars, pos);
+                if chars.get(*pos) != Some(&':') {
+                    return Err(SceneError::with_key(line, &key, "Expected ':'"));
+                }
+                *pos += 1;
+                entries.push((key, flow_value(chars, pos, line, depth + 1)?));
+                Ok(())
+            })?;
+            Value::Mapping(entries)
+        }
+        Some(_) => Value::Scalar(flow_scalar(chars, pos, false)),
+        None => return Err(SceneError::new(line, "Missing value")),
+    };
+    Ok(Node { value, line })
+}
+
+fn flow_items<F>(
+    chars: &[char],
+    pos: &mut usize,
+    line: usize,
+    close: char,
+    mut item: F,
+) -> Result<(), SceneError>
+where
+    F: FnMut(&[char], &mut usize) -> Result<(), SceneError>,
+{
+    skip_spaces(chars, pos);
+    if chars.get(*pos) == Some(&close) {
+        *pos += 1;
+        return Ok(());
+    }
+    loop {
+        item(chars, pos)?;
+        skip_spaces(chars, pos);
+        match chars.get(*pos) {
+            Some(',') => *pos += 1,
+            Some(c) if *c == close => {
+                *pos += 1;
+                return Ok(());
+            }
+            _ => {
+                return Err(SceneError::new(
+                    line,
+                    &format!("Expected ',' or '{}'", close),
+                ))
+            }
+        }
+    }
+}
+
+fn flow_scalar(chars: &[char], pos: &mut usize, is_key: bool) -> String {
+    skip_spaces(chars, pos);
+    if let Some(&q) = chars.get(*pos).filter(|c| **c == '"' || **c == '\'') {
+        let start = *pos + 1;
+        let mut end = start;
+        while end < chars.len() && chars[end] != q {
+            end += 1;
+        }
+        *pos = (end + 1).min(chars.len());
+        return chars[start..end].iter().collect();
+    }
+    let start = *pos;
+    while *pos < chars.len() {
+        let c = chars[*pos];
+        if c == ',' || c == ']' || c == '}' || (is_key && c == ':') {
+            break;
+        }
+        *pos += 1;
+    }
+    chars[start..*pos]
+        .iter()
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::yaml::{parse, Node, Value};
+
+    fn scalar(node: &Node) -> &str {
+        match &node.value {
+            Value::Scalar(s) => s,
+            _ => panic!("Expected a scalar, found {:?}", node),
+        }
+    }
+
+    fn items(node: &Node) -> &Vec<Node> {
+        match &node.value {
+            Value::Sequence(items) => items,
+            _ => panic!("Expected a sequence, found {:?}", node),
+        }
+    }
+
+    #[test]
+    fn parsing_block_mappings_and_sequences() {
+        let doc = parse(
+            "# scene\n- add: camera\n  width: 100 # pixels\n  up: [0, 1, 0]\n\n- define: m\n  value:\n    color: \"a # b\"\n    list:\n    - x\n    -\n      y: 2\n",
+        )
+        .unwrap();
+        let items = items(&doc);
+        assert_eq!(2, items.len());
+        assert_eq!(2, items[0].line);
+        assert_eq!("camera", scalar(items[0].get("add").unwrap()));
+        assert_eq!("100", scalar(items[0].get("width").unwrap()));
+        assert_eq!(3, items[0].get("width").unwrap().line);
+        let up = items[0].get("up").unwrap();
+        assert_eq!(
+            vec!["0", "1", "0"],
+            super::tests::items(up)
+                .iter()
+                .map(scalar)
+                .collect::<Vec<_>>()
+        );
+
+        let value = items[1].get("value").unwrap();
+        assert_eq!("a # b", scalar(value.get("color").unwrap()));
+        let list = super::tests::items(value.get("list").unwrap());
+        assert_eq!("x", scalar(&list[0]));
+        assert_eq!("2", scalar(list[1].get("y").unwrap()));
+    }
+
+    #[test]
+    fn parsing_flow_collections() {
+        let doc = parse("a: [ [scale, 1, 2], { b: 'c, d', e: [] } ]\n").unwrap();
+        let a = items(doc.get("a").unwrap());
+        assert_eq!("scale", scalar(&items(&a[0])[0]));
+        assert_eq!("c, d", scalar(a[1].get("b").unwrap()));
+        assert!(items(a[1].get("e").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn errors_report_line_and_key() {
+        let e = parse("a: 1\nb: [1, 2\n").unwrap_err();
+        assert_eq!(2, e.line);
+        assert_eq!(Some("b".to_string()), e.key);
+
+        let e = parse("a:\nb: 1\n").unwrap_err();
+        assert_eq!(1, e.line);
+        assert_eq!("line 1, key 'a': Missing value", e.to_string());
+
+        assert_eq!(3, parse("a: 1\nb: 2\n    c: 3\n").unwrap_err().line);
+        assert_eq!(2, parse("a: 1\na: 2\n").unwrap_err().line);
+        assert_eq!(2, parse("a: 1\n- b\n").unwrap_err().line);
+    }
+
+    #[test]
+    fn deep_flow_nesting_is_an_error() {
+        assert!(parse(&format!("a: {}", "[".repeat(100_000))).is_err());
+        let nested = format!("a: {}{}", "[".repeat(100), "]".repeat(100));
+        assert!(parse(&nested).is_ok());
+    }
+}